@@ -86,13 +86,16 @@ if ! dfx canister id zerolock_backend > /dev/null 2>&1; then
     dfx canister create --all
 fi
 
+# The deploying identity becomes the backend's first SuperAdmin
+BACKEND_INIT_ARGS="(record { super_admin = principal \"$(dfx identity get-principal)\" })"
+
 # Deploy backend
 if [[ "$FRONTEND_ONLY" != "true" && "$IDENTITY_ONLY" != "true" ]]; then
     echo -e "${GREEN}Deploying backend Canister...${NC}"
     if [[ "$FORCE_DEPLOY" == "true" ]]; then
-        dfx deploy zerolock_backend --mode reinstall
+        dfx deploy zerolock_backend --mode reinstall --argument "$BACKEND_INIT_ARGS"
     else
-        dfx deploy zerolock_backend
+        dfx deploy zerolock_backend --argument "$BACKEND_INIT_ARGS"
     fi
 fi
 
@@ -2,14 +2,23 @@
 //! Manages the complete lifecycle of security challenges on ICP blockchain
 
 use crate::types::*;
+use crate::memory::{get_memory, Memory, MemoryRegion};
+use crate::wasm_store;
+use crate::judge;
+use crate::events;
+use crate::invariants;
+use crate::replay;
+use crate::leaderboard;
+use crate::rbac::{self, Role};
+use crate::vault::{self, LockRequest, UnlockReason, UnlockRequest};
+use crate::migrations::{encode_record, decode_stored_record};
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk::api::management_canister::main::{
-    create_canister, install_code, CanisterSettings, CreateCanisterArgument,
-    InstallCodeArgument, CanisterInstallMode,
+    create_canister, delete_canister, install_code, stop_canister, CanisterIdRecord,
+    CanisterSettings, CreateCanisterArgument, InstallCodeArgument, CanisterInstallMode,
 };
 use ic_cdk_macros::*;
-use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, Storable};
+use ic_stable_structures::{StableBTreeMap, StableCell, Storable};
 use serde::Serialize;
 use std::cell::RefCell;
 
@@ -49,28 +58,43 @@ impl Storable for StorablePrincipal {
 
 
 // Memory management
-type Memory = VirtualMemory<DefaultMemoryImpl>;
 type ChallengeStorage = StableBTreeMap<u64, Challenge, Memory>;
 type AdminStorage = StableBTreeMap<u64, StorablePrincipal, Memory>;
+type FactoryStateCell = StableCell<FactoryState, Memory>;
+type AttackStorage = StableBTreeMap<u64, AttackAttempt, Memory>;
+type ChallengeAttackIndex = StableBTreeMap<(u64, u64), (), Memory>;
+type HackerAttackIndex = StableBTreeMap<(StorablePrincipal, u64), (), Memory>;
+type SandboxStorage = StableBTreeMap<(u64, StorablePrincipal), Sandbox, Memory>;
+type TargetTeardownQueue = StableBTreeMap<u64, StorablePrincipal, Memory>;
+type TierPayoutStorage = StableBTreeMap<(u64, u64), TierPayout, Memory>;
 
 // Configuration constants
 const MAX_CHALLENGES_PER_USER: u64 = 10;
-const MIN_BOUNTY_AMOUNT: u64 = 1_000_000; // 0.01 ICP in e8s
 const MAX_DURATION_HOURS: u64 = 720; // 30 days
 const MIN_DURATION_HOURS: u64 = 24; // 1 day
 const MAX_WASM_SIZE: usize = 2_000_000; // 2MB
 const MAX_DESCRIPTION_LENGTH: usize = 1000;
+const MAX_PROOF_SIZE: usize = 4096; // Fits the AttackAttempt storage bound
+const TARGET_CANISTER_CYCLES: u128 = 1_000_000_000_000; // 1T cycles
+const SANDBOX_CANISTER_CYCLES: u128 = 500_000_000_000; // 0.5T cycles per hacker instance
+const MAX_SANDBOXES_PER_CHALLENGE: usize = 50;
+const MAX_ATTACKS_PER_HACKER: usize = 10; // Per challenge; each replay deploys a funded instance
+const ATTACK_COOLDOWN_SECONDS: i64 = 600; // Between one hacker's attempts on a challenge
 
 // Request types
 #[derive(CandidType, Deserialize, Clone, Debug)]
 pub struct CreateChallengeRequest {
-    pub wasm_code: Vec<u8>,
+    pub wasm_hash: Vec<u8>, // Hash returned by commit_wasm
     pub candid_interface: String,
     pub bounty_amount: u64,
     pub duration_hours: u64,
     pub token_type: TokenType,
     pub description: String,
     pub difficulty_level: u8,
+    pub balance_source: BalanceSource, // Where the judge reads the target's balance
+    pub invariants: Option<Vec<Invariant>>,
+    pub sandboxed: Option<bool>, // Give each hacker their own target instance
+    pub severity_tiers: Option<Vec<SeverityTier>>, // Pay findings by severity instead of one winner
 }
 
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
@@ -82,25 +106,163 @@ pub struct ChallengeStats {
     pub cancelled: u64,
 }
 
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum SandboxStatus {
+    Deploying,
+    Active,
+    TornDown,
+}
+
+// A hacker's own instance of a sandboxed challenge's target
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct Sandbox {
+    pub challenge_id: u64,
+    pub hacker: Principal,
+    pub canister_id: Option<Principal>, // Set once deployed
+    pub cycles: u64,
+    pub status: SandboxStatus,
+    pub created_at: i64,
+    pub torn_down_at: Option<i64>,
+}
+
+// Payout of one finding from a challenge's severity tiers
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct TierPayout {
+    pub challenge_id: u64,
+    pub attack_attempt_id: u64,
+    pub hacker: Principal,
+    pub severity: Severity,
+    pub amount: u64,          // Tier amount, or less if the lock held less
+    pub transaction_id: u64,  // The vault's Payout transaction
+    pub paid_by: Principal,
+    pub paid_at: i64,
+}
+
+// ID counters kept in stable memory so they survive upgrades
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct FactoryState {
+    pub next_challenge_id: u64,
+    pub next_admin_id: u64,
+    pub next_attack_id: u64,
+}
+
+impl Default for FactoryState {
+    fn default() -> Self {
+        Self {
+            next_challenge_id: 1,
+            next_admin_id: 1,
+            next_attack_id: 1,
+        }
+    }
+}
+
+impl Storable for FactoryState {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
+
+impl Storable for TierPayout {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
+
+impl Storable for Sandbox {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
+
 // Global state - BountyFactory uses MemoryId 0-9
 thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
-        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
-    
     static CHALLENGES: RefCell<ChallengeStorage> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(0)))
+            get_memory(MemoryRegion::BountyFactory, 0)
         )
     );
     
+    // Admins from before RBAC, moved into role grants on upgrade
     static ADMINS: RefCell<AdminStorage> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(1)))
+            get_memory(MemoryRegion::BountyFactory, 1)
         )
     );
     
-    static NEXT_CHALLENGE_ID: RefCell<u64> = RefCell::new(1);
-    static NEXT_ADMIN_ID: RefCell<u64> = RefCell::new(1);
+    static FACTORY_STATE: RefCell<FactoryStateCell> = RefCell::new(
+        StableCell::init(
+            get_memory(MemoryRegion::BountyFactory, 2),
+            FactoryState::default()
+        ).expect("Failed to initialize factory state")
+    );
+
+    static ATTACK_ATTEMPTS: RefCell<AttackStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::BountyFactory, 3)
+        )
+    );
+
+    // Attempt IDs keyed by (challenge_id, attempt_id)
+    static CHALLENGE_ATTACKS: RefCell<ChallengeAttackIndex> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::BountyFactory, 4)
+        )
+    );
+
+    // Attempt IDs keyed by (hacker, attempt_id)
+    static HACKER_ATTACKS: RefCell<HackerAttackIndex> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::BountyFactory, 5)
+        )
+    );
+
+    // Per-hacker target instances keyed by (challenge_id, hacker)
+    static SANDBOXES: RefCell<SandboxStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::BountyFactory, 6)
+        )
+    );
+
+    // Targets of ended challenges waiting to be stopped and deleted
+    static TARGET_TEARDOWNS: RefCell<TargetTeardownQueue> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::BountyFactory, 7)
+        )
+    );
+
+    // Severity tier payouts keyed by (challenge_id, attack_attempt_id)
+    static TIER_PAYOUTS: RefCell<TierPayoutStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::BountyFactory, 8)
+        )
+    );
 }
 
 /// Creates a new security challenge
@@ -131,18 +293,13 @@ pub async fn create_challenge(request: CreateChallengeRequest) -> ApiResponse<u6
     }
     
     let current_time = current_time();
-    let challenge_id = NEXT_CHALLENGE_ID.with(|id| {
-        let mut id = id.borrow_mut();
-        let current = *id;
-        *id += 1;
-        current
-    });
+    let challenge_id = factory_state().next_challenge_id;
     
     let challenge = Challenge {
         id: challenge_id,
         company: caller,
         target_canister: None,
-        wasm_code: request.wasm_code,
+        wasm_hash: request.wasm_hash,
         candid_interface: request.candid_interface,
         bounty_amount: request.bounty_amount,
         token_type: request.token_type,
@@ -151,14 +308,28 @@ pub async fn create_challenge(request: CreateChallengeRequest) -> ApiResponse<u6
         status: ChallengeStatus::Created,
         description: request.description,
         difficulty_level: request.difficulty_level,
+        balance_source: request.balance_source,
+        invariants: request.invariants.unwrap_or_default(),
+        sandboxed: request.sandboxed.unwrap_or(false),
+        severity_tiers: request.severity_tiers.unwrap_or_default(),
         created_at: current_time,
         updated_at: current_time,
     };
     
+    // Escrow bounty and fee first; nothing is stored if the company cannot cover them
+    if let Err(error) = ensure_escrow(&challenge) {
+        return ApiResponse::Err(error);
+    }
+    
+    update_factory_state(|state| state.next_challenge_id += 1);
+    let bounty_amount = challenge.bounty_amount;
     CHALLENGES.with(|challenges| {
         challenges.borrow_mut().insert(challenge_id, challenge)
     });
     
+    events::record_event(Event::ChallengeCreated { challenge_id, company: caller });
+    events::record_event(Event::FundsLocked { challenge_id, amount: bounty_amount });
+    
     ic_cdk::println!("Challenge created: ID={}, Company={}", challenge_id, caller.to_text());
     
     ApiResponse::Ok(challenge_id)
@@ -250,17 +421,64 @@ pub fn update_challenge_status(id: u64, new_status: ChallengeStatus) -> ApiRespo
         match challenges.get(&id) {
             Some(challenge) => {
                 // Check authorization
-                if caller != challenge.company && !is_admin(&caller) {
+                if caller != challenge.company && !rbac::is_admin(&caller) {
                     return ApiResponse::Err(ZeroLockError::Unauthorized(
                         "Not authorized to update this challenge".to_string()
                     ));
                 }
                 
+                // Completion pays the bounty, so only the judge's settlement may do it
+                if new_status == ChallengeStatus::Completed {
+                    return ApiResponse::Err(ZeroLockError::InvalidState(
+                        "Challenges are completed by the judge's settlement".to_string()
+                    ));
+                }
+                
                 // Validate status transition
                 if let Err(error) = validate_status_transition(&challenge.status, &new_status) {
                     return ApiResponse::Err(error);
                 }
                 
+                // Ending the challenge refunds the escrow, so it must not cut claims short
+                if challenge.status != new_status {
+                    let ending = match new_status {
+                        ChallengeStatus::Expired => check_can_expire(&challenge),
+                        ChallengeStatus::Cancelled => check_no_open_claims(id),
+                        _ => Ok(()),
+                    };
+                    if let Err(error) = ending {
+                        return ApiResponse::Err(error);
+                    }
+                }
+                
+                // Keep the escrow in step with the new status
+                if challenge.status != new_status {
+                    let escrow_result = match new_status {
+                        ChallengeStatus::Active => ensure_escrow(&challenge),
+                        ChallengeStatus::Cancelled => release_escrow(&challenge, UnlockReason::ChallengeCancelled),
+                        ChallengeStatus::Expired => release_escrow(&challenge, UnlockReason::ChallengeExpired),
+                        _ => Ok(()),
+                    };
+                    if let Err(error) = escrow_result {
+                        return ApiResponse::Err(error);
+                    }
+                    
+                    match new_status {
+                        ChallengeStatus::Active => {
+                            events::record_event(Event::ChallengeActivated { challenge_id: id });
+                        }
+                        ChallengeStatus::Expired => {
+                            events::record_event(Event::ChallengeExpired { challenge_id: id });
+                            wind_down_challenge(&challenge);
+                        }
+                        ChallengeStatus::Cancelled => {
+                            events::record_event(Event::ChallengeCancelled { challenge_id: id });
+                            wind_down_challenge(&challenge);
+                        }
+                        _ => {}
+                    }
+                }
+                
                 let mut updated_challenge = challenge;
                 updated_challenge.status = new_status.clone();
                 updated_challenge.updated_at = current_time();
@@ -292,7 +510,7 @@ pub async fn deploy_target_canister(challenge_id: u64) -> ApiResponse<Principal>
     };
     
     // Check authorization
-    if caller != challenge.company && !is_admin(&caller) {
+    if caller != challenge.company && !rbac::is_admin(&caller) {
         return ApiResponse::Err(ZeroLockError::Unauthorized(
             "Not authorized to deploy canister for this challenge".to_string()
         ));
@@ -305,60 +523,54 @@ pub async fn deploy_target_canister(challenge_id: u64) -> ApiResponse<Principal>
         ));
     }
     
-    // Deploy canister using IC management canister
-    match create_canister(
-        CreateCanisterArgument {
-            settings: Some(CanisterSettings {
-                controllers: Some(vec![ic_cdk::id()]),
-                compute_allocation: None,
-                memory_allocation: None,
-                freezing_threshold: None,
-                reserved_cycles_limit: None,
-            }),
-        },
-        1_000_000_000_000u128, // 1T cycles
-    ).await {
-        Ok((canister_result,)) => {
-            let canister_id = canister_result.canister_id;
-            
-            // Install the WASM code
-            match install_code(InstallCodeArgument {
-                mode: CanisterInstallMode::Install,
-                canister_id,
-                wasm_module: challenge.wasm_code.clone(),
-                arg: vec![], // Empty argument
-            }).await {
-                Ok(_) => {
-                    // Update challenge with deployed canister
-                    CHALLENGES.with(|challenges| {
-                        let mut challenges = challenges.borrow_mut();
-                        if let Some(mut updated_challenge) = challenges.get(&challenge_id) {
-                            updated_challenge.target_canister = Some(canister_id);
-                            updated_challenge.status = ChallengeStatus::Active;
-                            updated_challenge.updated_at = current_time();
-                            challenges.insert(challenge_id, updated_challenge);
-                        }
-                    });
-                    
-                    ic_cdk::println!(
-                        "Target canister deployed: Challenge={}, Canister={}",
-                        challenge_id,
-                        canister_id.to_text()
-                    );
-                    ApiResponse::Ok(canister_id)
-                }
-                Err((code, msg)) => ApiResponse::Err(ZeroLockError::InternalError(
-                    format!("Failed to install code: {:?} - {}", code, msg)
-                )),
+    // The escrow may have been refunded since creation; nothing goes live unfunded
+    if let Err(e) = ensure_escrow(&challenge) {
+        return ApiResponse::Err(e);
+    }
+    
+    let canister_id = match deploy_wasm_instance(&challenge.wasm_hash, TARGET_CANISTER_CYCLES).await {
+        Ok(canister_id) => canister_id,
+        Err(e) => return ApiResponse::Err(e),
+    };
+    
+    // The challenge may have been cancelled or expired while the target was installed
+    let activated = CHALLENGES.with(|challenges| {
+        let mut challenges = challenges.borrow_mut();
+        match challenges.get(&challenge_id) {
+            Some(mut updated_challenge) if updated_challenge.status == ChallengeStatus::Created => {
+                updated_challenge.target_canister = Some(canister_id);
+                updated_challenge.status = ChallengeStatus::Active;
+                updated_challenge.updated_at = current_time();
+                challenges.insert(challenge_id, updated_challenge);
+                true
             }
+            _ => false,
         }
-        Err((code, msg)) => ApiResponse::Err(ZeroLockError::InternalError(
-            format!("Failed to create canister: {:?} - {}", code, msg)
-        )),
+    });
+    if !activated {
+        TARGET_TEARDOWNS.with(|queue| {
+            queue.borrow_mut().insert(challenge_id, StorablePrincipal(canister_id))
+        });
+        return ApiResponse::Err(ZeroLockError::InvalidState(
+            "Challenge left Created status while its target was deployed".to_string()
+        ));
+    }
+    events::record_event(Event::ChallengeActivated { challenge_id });
+    
+    // Attacks are judged against the balance measured from here on
+    if let Err(e) = judge::start_monitoring_internal(challenge_id, canister_id).await {
+        ic_cdk::println!("Monitoring not started for challenge {}: {:?}", challenge_id, e);
     }
+    
+    ic_cdk::println!(
+        "Target canister deployed: Challenge={}, Canister={}",
+        challenge_id,
+        canister_id.to_text()
+    );
+    ApiResponse::Ok(canister_id)
 }
 
-/// Manually expires a challenge
+/// Manually expires a challenge that has passed its end time
 /// @param id Challenge identifier
 /// @returns Success or error
 #[update]
@@ -370,7 +582,7 @@ pub fn expire_challenge(id: u64) -> ApiResponse<()> {
         match challenges.get(&id) {
             Some(challenge) => {
                 // Check authorization
-                if caller != challenge.company && !is_admin(&caller) {
+                if caller != challenge.company && !rbac::is_admin(&caller) {
                     return ApiResponse::Err(ZeroLockError::Unauthorized(
                         "Not authorized to expire this challenge".to_string()
                     ));
@@ -383,11 +595,21 @@ pub fn expire_challenge(id: u64) -> ApiResponse<()> {
                     ));
                 }
                 
+                if let Err(error) = check_can_expire(&challenge) {
+                    return ApiResponse::Err(error);
+                }
+                
+                if let Err(error) = release_escrow(&challenge, UnlockReason::ChallengeExpired) {
+                    return ApiResponse::Err(error);
+                }
+                wind_down_challenge(&challenge);
+                
                 let mut updated_challenge = challenge;
                 updated_challenge.status = ChallengeStatus::Expired;
                 updated_challenge.updated_at = current_time();
                 
                 challenges.insert(id, updated_challenge);
+                events::record_event(Event::ChallengeExpired { challenge_id: id });
                 
                 ic_cdk::println!("Challenge expired: ID={}", id);
                 ApiResponse::Ok(())
@@ -476,126 +698,656 @@ pub fn get_company_challenges(company: Principal, offset: u64, limit: u64) -> Ap
     })
 }
 
-/// Adds an admin (only callable by existing admins or during initialization)
+/// Submits an attack attempt against an active challenge
+/// The attempt is recorded for the caller and handed to the judge for evaluation
+/// Only registered hackers can submit, a limited number of times per challenge and not
+/// more often than the cooldown allows, since replays are paid for in this canister's cycles
+/// @param challenge_id Challenge identifier
+/// @param proof Candid-encoded ReplayProof; required when the challenge declares invariants
+/// @returns Recorded attack attempt, with success set by the judge's decision
 #[update]
-pub fn add_admin(new_admin: Principal) -> ApiResponse<()> {
-    let caller = ic_cdk::caller();
-    
-    let admin_count = ADMINS.with(|admins| admins.borrow().len());
-    
-    if admin_count > 0 && !is_admin(&caller) {
+pub async fn submit_attack(challenge_id: u64, proof: Option<Vec<u8>>) -> ApiResponse<AttackAttempt> {
+    let hacker = match check_caller_not_anonymous() {
+        Ok(c) => c,
+        Err(e) => return ApiResponse::Err(e),
+    };
+
+    if !leaderboard::is_registered_hacker(hacker) {
         return ApiResponse::Err(ZeroLockError::Unauthorized(
-            "Only admins can add new admins".to_string()
+            "Register as a hacker before submitting attacks".to_string()
         ));
     }
-    
-    if is_admin(&new_admin) {
-        return ApiResponse::Err(ZeroLockError::InvalidInput(
-            "Principal is already an admin".to_string()
+
+    if let Some(proof) = &proof {
+        if proof.len() > MAX_PROOF_SIZE {
+            return ApiResponse::Err(ZeroLockError::InvalidInput(format!(
+                "Proof size exceeds maximum allowed: {} bytes",
+                MAX_PROOF_SIZE
+            )));
+        }
+
+        if let Err(e) = replay::decode_proof(proof) {
+            return ApiResponse::Err(e);
+        }
+    }
+
+    let challenge = match CHALLENGES.with(|challenges| challenges.borrow().get(&challenge_id)) {
+        Some(challenge) => challenge,
+        None => return ApiResponse::Err(ZeroLockError::NotFound("Challenge not found".to_string())),
+    };
+
+    if challenge.status != ChallengeStatus::Active {
+        return ApiResponse::Err(ZeroLockError::InvalidState(
+            "Challenge is not active".to_string()
         ));
     }
-    
-    let admin_id = NEXT_ADMIN_ID.with(|id| {
-        let mut id = id.borrow_mut();
-        let current = *id;
-        *id += 1;
+
+    let now = current_time();
+    if now < challenge.start_time || now > challenge.end_time {
+        return ApiResponse::Err(ZeroLockError::InvalidState(
+            "Challenge is outside its attack window".to_string()
+        ));
+    }
+
+    if challenge.company == hacker {
+        return ApiResponse::Err(ZeroLockError::Unauthorized(
+            "Companies cannot attack their own challenges".to_string()
+        ));
+    }
+
+    // On sandboxed challenges each hacker is judged on their own instance
+    if challenge.sandboxed && get_active_sandbox(challenge_id, hacker).is_none() {
+        return ApiResponse::Err(ZeroLockError::InvalidState(
+            "Request a sandbox before submitting attacks on this challenge".to_string()
+        ));
+    }
+
+    // Earlier attempts are stored before the judge awaits, so concurrent calls see each other
+    let previous = hacker_challenge_attempts(hacker, challenge_id);
+    if previous.len() >= MAX_ATTACKS_PER_HACKER {
+        return ApiResponse::Err(ZeroLockError::ResourceLimit(format!(
+            "At most {} attack attempts per challenge",
+            MAX_ATTACKS_PER_HACKER
+        )));
+    }
+    if let Some(last) = previous.iter().map(|attempt| attempt.timestamp).max() {
+        let ready_at = last.saturating_add(ATTACK_COOLDOWN_SECONDS * 1_000_000_000);
+        if now < ready_at {
+            return ApiResponse::Err(ZeroLockError::ResourceLimit(format!(
+                "Wait {} more seconds before attacking this challenge again",
+                (ready_at - now + 999_999_999) / 1_000_000_000
+            )));
+        }
+    }
+
+    let attack_id = update_factory_state(|state| {
+        let current = state.next_attack_id;
+        state.next_attack_id += 1;
         current
     });
-    
-    ADMINS.with(|admins| {
-        admins.borrow_mut().insert(admin_id, StorablePrincipal::from(new_admin))
+
+    let mut attempt = AttackAttempt {
+        id: attack_id,
+        challenge_id,
+        hacker,
+        timestamp: now,
+        success: false,
+        proof,
+        gas_used: 0,
+    };
+
+    store_attack_attempt(&attempt);
+    events::record_event(Event::AttackAttempted { challenge_id, hacker });
+    ic_cdk::println!("Attack submitted: ID={}, Challenge={}, Hacker={}", attack_id, challenge_id, hacker.to_text());
+
+    // The attempt stays recorded even if the judge cannot evaluate it yet
+    match judge::evaluate_attack(attempt.clone()).await {
+        Ok(evaluation) => {
+            attempt.success = evaluation.decision == JudgeDecision::Valid;
+            store_attack_attempt(&attempt);
+        }
+        Err(e) => {
+            ic_cdk::println!("Attack {} not evaluated: {:?}", attack_id, e);
+        }
+    }
+
+    ApiResponse::Ok(attempt)
+}
+
+/// Retrieves an attack attempt by ID
+/// @param id Attack attempt identifier
+/// @returns Attack attempt if found
+#[query]
+pub fn get_attack_attempt(id: u64) -> ApiResponse<AttackAttempt> {
+    match get_attack_attempt_internal(id) {
+        Some(attempt) => ApiResponse::Ok(attempt),
+        None => ApiResponse::Err(ZeroLockError::NotFound("Attack attempt not found".to_string())),
+    }
+}
+
+/// Lists attack attempts made against a challenge (newest first)
+/// @param challenge_id Challenge identifier
+/// @param offset Pagination offset
+/// @param limit Maximum number of results
+/// @returns Paginated result of attack attempts
+#[query]
+pub fn get_challenge_attacks(challenge_id: u64, offset: u64, limit: u64) -> ApiResponse<PaginatedResult<AttackAttempt>> {
+    let attempt_ids: Vec<u64> = CHALLENGE_ATTACKS.with(|index| {
+        index
+            .borrow()
+            .range((challenge_id, 0)..=(challenge_id, u64::MAX))
+            .map(|((_, attempt_id), _)| attempt_id)
+            .collect()
     });
-    
-    ic_cdk::println!("Admin added: {}", new_admin.to_text());
-    ApiResponse::Ok(())
+
+    paginate_attack_attempts(attempt_ids, offset, limit)
 }
 
-/// Gets the list of admin principals
+/// Lists attack attempts made by a hacker (newest first)
+/// @param hacker Principal of the hacker
+/// @param offset Pagination offset
+/// @param limit Maximum number of results
+/// @returns Paginated result of attack attempts
 #[query]
-pub fn get_admins() -> ApiResponse<Vec<Principal>> {
-    let caller = match check_caller_not_anonymous() {
+pub fn get_hacker_attacks(hacker: Principal, offset: u64, limit: u64) -> ApiResponse<PaginatedResult<AttackAttempt>> {
+    let attempt_ids: Vec<u64> = HACKER_ATTACKS.with(|index| {
+        index
+            .borrow()
+            .range((StorablePrincipal(hacker), 0)..=(StorablePrincipal(hacker), u64::MAX))
+            .map(|((_, attempt_id), _)| attempt_id)
+            .collect()
+    });
+
+    paginate_attack_attempts(attempt_ids, offset, limit)
+}
+
+/// Deploys the caller's own instance of a sandboxed challenge's target
+/// @param challenge_id Challenge identifier
+/// @returns The deployed sandbox
+#[update]
+pub async fn request_sandbox(challenge_id: u64) -> ApiResponse<Sandbox> {
+    let hacker = match check_caller_not_anonymous() {
         Ok(c) => c,
         Err(e) => return ApiResponse::Err(e),
     };
-    
-    if !is_admin(&caller) {
+
+    if !leaderboard::is_registered_hacker(hacker) {
+        return ApiResponse::Err(ZeroLockError::Unauthorized(
+            "Register as a hacker before requesting a sandbox".to_string()
+        ));
+    }
+
+    let challenge = match CHALLENGES.with(|challenges| challenges.borrow().get(&challenge_id)) {
+        Some(challenge) => challenge,
+        None => return ApiResponse::Err(ZeroLockError::NotFound("Challenge not found".to_string())),
+    };
+
+    if !challenge.sandboxed {
+        return ApiResponse::Err(ZeroLockError::InvalidState(
+            "Challenge does not offer per-hacker sandboxes".to_string()
+        ));
+    }
+
+    if challenge.status != ChallengeStatus::Active || current_time() > challenge.end_time {
+        return ApiResponse::Err(ZeroLockError::InvalidState(
+            "Challenge is not active".to_string()
+        ));
+    }
+
+    if challenge.company == hacker {
+        return ApiResponse::Err(ZeroLockError::Unauthorized(
+            "Companies cannot attack their own challenges".to_string()
+        ));
+    }
+
+    let key = (challenge_id, StorablePrincipal(hacker));
+    if let Some(existing) = SANDBOXES.with(|sandboxes| sandboxes.borrow().get(&key)) {
+        if existing.status != SandboxStatus::TornDown {
+            return ApiResponse::Err(ZeroLockError::AlreadyExists(
+                "A sandbox already exists for this challenge".to_string()
+            ));
+        }
+    }
+
+    let live_sandboxes = challenge_sandboxes(challenge_id)
+        .iter()
+        .filter(|sandbox| sandbox.status != SandboxStatus::TornDown)
+        .count();
+    if live_sandboxes >= MAX_SANDBOXES_PER_CHALLENGE {
+        return ApiResponse::Err(ZeroLockError::ResourceLimit(
+            "Maximum sandboxes for this challenge reached".to_string()
+        ));
+    }
+
+    // Reserve the slot before awaiting so concurrent requests cannot deploy twice
+    let mut sandbox = Sandbox {
+        challenge_id,
+        hacker,
+        canister_id: None,
+        cycles: SANDBOX_CANISTER_CYCLES as u64,
+        status: SandboxStatus::Deploying,
+        created_at: current_time(),
+        torn_down_at: None,
+    };
+    SANDBOXES.with(|sandboxes| sandboxes.borrow_mut().insert(key.clone(), sandbox.clone()));
+
+    let canister_id = match deploy_wasm_instance(&challenge.wasm_hash, SANDBOX_CANISTER_CYCLES).await {
+        Ok(canister_id) => canister_id,
+        Err(e) => {
+            SANDBOXES.with(|sandboxes| sandboxes.borrow_mut().remove(&key));
+            return ApiResponse::Err(e);
+        }
+    };
+
+    sandbox.canister_id = Some(canister_id);
+    sandbox.status = SandboxStatus::Active;
+    SANDBOXES.with(|sandboxes| sandboxes.borrow_mut().insert(key, sandbox.clone()));
+
+    if let Err(e) = judge::start_sandbox_monitoring(challenge_id, canister_id).await {
+        ic_cdk::println!("Monitoring not started for sandbox {}: {:?}", canister_id.to_text(), e);
+    }
+
+    ic_cdk::println!(
+        "Sandbox deployed: Challenge={}, Hacker={}, Canister={}",
+        challenge_id,
+        hacker.to_text(),
+        canister_id.to_text()
+    );
+    ApiResponse::Ok(sandbox)
+}
+
+/// Gets a hacker's sandbox for a challenge
+/// @param challenge_id Challenge identifier
+/// @param hacker Principal of the hacker
+/// @returns Sandbox if one was requested
+#[query]
+pub fn get_sandbox(challenge_id: u64, hacker: Principal) -> ApiResponse<Sandbox> {
+    match SANDBOXES.with(|sandboxes| sandboxes.borrow().get(&(challenge_id, StorablePrincipal(hacker)))) {
+        Some(sandbox) => ApiResponse::Ok(sandbox),
+        None => ApiResponse::Err(ZeroLockError::NotFound("Sandbox not found".to_string())),
+    }
+}
+
+/// Lists the sandboxes deployed for a challenge
+/// @param challenge_id Challenge identifier
+/// @returns Sandboxes of every hacker, including torn down ones
+#[query]
+pub fn get_challenge_sandboxes(challenge_id: u64) -> ApiResponse<Vec<Sandbox>> {
+    ApiResponse::Ok(challenge_sandboxes(challenge_id))
+}
+
+/// Pays a finding from the challenge's severity tiers (challenge company or admin)
+/// The attempt must have been judged valid: by the judge, by the panel, or by a dispute
+/// the hacker won. Each attack attempt is paid once, as its own Payout transaction. The rest of the
+/// bounty stays locked for further findings, and the challenge completes once the
+/// lock is exhausted; whatever is left at expiry goes back to the company
+/// @param attack_attempt_id Attack attempt that found the issue
+/// @param severity Severity the finding was triaged as
+/// @returns The payout record
+#[update]
+pub fn pay_severity_tier(attack_attempt_id: u64, severity: Severity) -> ApiResponse<TierPayout> {
+    pay_severity_tier_internal(ic_cdk::caller(), attack_attempt_id, severity).into()
+}
+
+/// Lists the severity tier payouts of a challenge
+/// @param challenge_id Challenge identifier
+/// @returns Payouts in attack attempt order
+#[query]
+pub fn get_tier_payouts(challenge_id: u64) -> ApiResponse<Vec<TierPayout>> {
+    ApiResponse::Ok(TIER_PAYOUTS.with(|payouts| {
+        payouts
+            .borrow()
+            .range((challenge_id, 0)..=(challenge_id, u64::MAX))
+            .map(|(_, payout)| payout)
+            .collect()
+    }))
+}
+
+/// Adds a platform admin (SuperAdmin only)
+/// Kept for existing clients; grants the Admin role
+#[update]
+pub fn add_admin(new_admin: Principal) -> ApiResponse<()> {
+    rbac::grant_role_internal(ic_cdk::caller(), new_admin, Role::Admin).into()
+}
+
+/// Gets the list of admin principals
+#[query]
+pub fn get_admins() -> ApiResponse<Vec<Principal>> {
+    if !rbac::is_admin(&ic_cdk::caller()) {
         return ApiResponse::Err(ZeroLockError::PermissionDenied("Only admins can view admin list".to_string()));
     }
     
-    ADMINS.with(|admins| {
-        let admin_list: Vec<Principal> = admins.borrow().iter().map(|(_, storable_principal)| storable_principal.0).collect();
-        ApiResponse::Ok(admin_list)
-    })
+    ApiResponse::Ok(rbac::role_members(Role::Admin))
 }
 
-/// Removes an admin principal
+/// Removes a platform admin (SuperAdmin only)
+/// Kept for existing clients; revokes the Admin role
 #[update]
 pub fn remove_admin(admin_to_remove: Principal) -> ApiResponse<()> {
-    let caller = ic_cdk::caller();
+    rbac::revoke_role_internal(ic_cdk::caller(), admin_to_remove, Role::Admin).into()
+}
+
+// Public functions for other modules
+
+/// Empties the pre-RBAC admin list, oldest admin first
+pub fn take_legacy_admins() -> Vec<Principal> {
+    ADMINS.with(|admins| {
+        let mut admins = admins.borrow_mut();
+        let legacy: Vec<(u64, StorablePrincipal)> = admins.iter().collect();
+        for (id, _) in &legacy {
+            admins.remove(id);
+        }
+        legacy.into_iter().map(|(_, admin)| admin.0).collect()
+    })
+}
+
+/// Loads a challenge by ID
+pub fn get_challenge_internal(id: u64) -> Option<Challenge> {
+    CHALLENGES.with(|challenges| challenges.borrow().get(&id))
+}
+
+/// Returns the canister of a hacker's deployed sandbox, if any
+pub fn get_active_sandbox(challenge_id: u64, hacker: Principal) -> Option<Principal> {
+    SANDBOXES.with(|sandboxes| sandboxes.borrow().get(&(challenge_id, StorablePrincipal(hacker))))
+        .filter(|sandbox| sandbox.status == SandboxStatus::Active)
+        .and_then(|sandbox| sandbox.canister_id)
+}
+
+/// Loads an attack attempt by ID
+pub fn get_attack_attempt_internal(id: u64) -> Option<AttackAttempt> {
+    ATTACK_ATTEMPTS.with(|attempts| attempts.borrow().get(&id))
+}
+
+/// Marks a challenge completed once its bounty has been paid out
+/// Monitoring stops and the target is queued for teardown, as for any other ending
+pub fn complete_challenge(id: u64) -> Result<(), ZeroLockError> {
+    CHALLENGES.with(|challenges| {
+        let mut challenges = challenges.borrow_mut();
+        let mut challenge = challenges.get(&id)
+            .ok_or_else(|| ZeroLockError::NotFound("Challenge not found".to_string()))?;
+        
+        validate_status_transition(&challenge.status, &ChallengeStatus::Completed)?;
+        wind_down_challenge(&challenge);
+        
+        challenge.status = ChallengeStatus::Completed;
+        challenge.updated_at = current_time();
+        challenges.insert(id, challenge);
+        
+        ic_cdk::println!("Challenge completed: ID={}", id);
+        Ok(())
+    })
+}
+
+/// Pays an attack attempt from one of the challenge's severity tiers
+/// Callers check who may pay and that the attempt was judged valid. The payout is
+/// also recorded as the attempt's settlement, so a dispute can reverse it
+/// @returns The payout record
+pub fn pay_tier_internal(
+    challenge: &Challenge,
+    attempt: &AttackAttempt,
+    severity: Severity,
+    paid_by: Principal,
+) -> Result<TierPayout, ZeroLockError> {
+    if challenge.status != ChallengeStatus::Active {
+        return Err(ZeroLockError::InvalidState(
+            "Only active challenges can pay findings".to_string()
+        ));
+    }
     
-    // Only existing admins can remove other admins
-    if !is_admin(&caller) {
-        return ApiResponse::Err(ZeroLockError::Unauthorized(
-            "Only admins can remove other admins".to_string()
+    let tier = challenge.severity_tiers
+        .iter()
+        .find(|tier| tier.severity == severity)
+        .ok_or_else(|| ZeroLockError::InvalidInput(format!(
+            "Challenge has no {:?} tier",
+            severity
+        )))?;
+    
+    let key = (challenge.id, attempt.id);
+    if TIER_PAYOUTS.with(|payouts| payouts.borrow().contains_key(&key)) {
+        return Err(ZeroLockError::AlreadyExists(
+            "This attack attempt has already been paid".to_string()
         ));
     }
     
-    if caller == admin_to_remove {
-        return ApiResponse::Err(ZeroLockError::InvalidInput(
-            "Cannot remove yourself as admin".to_string()
+    let evaluation_id = judge::confirming_evaluation(attempt.id).ok_or_else(|| {
+        ZeroLockError::InvalidState("Only attacks judged valid can be paid".to_string())
+    })?;
+    
+    let lock_info = vault::get_active_lock(challenge.id).ok_or_else(|| {
+        ZeroLockError::InvalidState("No escrow is left for this challenge".to_string())
+    })?;
+    
+    // The last finding gets whatever the lock still holds and completes the challenge;
+    // decided before any funds move so nothing after the payout can fail it
+    let amount = tier.amount.min(lock_info.remaining_bounty());
+    let completes = amount == lock_info.remaining_bounty();
+    if completes {
+        validate_status_transition(&challenge.status, &ChallengeStatus::Completed)?;
+    }
+    
+    let transaction_id = vault::unlock_funds_internal(UnlockRequest {
+        challenge_id: challenge.id,
+        recipient: attempt.hacker,
+        amount,
+        reason: UnlockReason::BountyPayout(attempt.hacker),
+    })?;
+    
+    let payout = TierPayout {
+        challenge_id: challenge.id,
+        attack_attempt_id: attempt.id,
+        hacker: attempt.hacker,
+        severity,
+        amount,
+        transaction_id,
+        paid_by,
+        paid_at: current_time(),
+    };
+    TIER_PAYOUTS.with(|payouts| payouts.borrow_mut().insert(key, payout.clone()));
+    judge::record_settlement(challenge.id, attempt.hacker, attempt.id, evaluation_id, amount);
+    
+    // The payout has already moved, so a leaderboard overflow must not fail it
+    if let Err(e) = leaderboard::record_successful_attack_internal(attempt.hacker, challenge.id, amount) {
+        ic_cdk::println!("Leaderboard not updated for challenge {}: {:?}", challenge.id, e);
+    }
+    events::record_event(Event::BountyPaid { challenge_id: challenge.id, recipient: attempt.hacker, amount });
+    
+    if completes {
+        match complete_challenge(challenge.id) {
+            Ok(()) => {
+                events::record_event(Event::ChallengeCompleted { challenge_id: challenge.id, winner: attempt.hacker });
+            }
+            Err(e) => ic_cdk::println!("Challenge {} not completed after its last tier payout: {:?}", challenge.id, e),
+        }
+    }
+    
+    ic_cdk::println!(
+        "Tier payout: Challenge={}, Attempt={}, Severity={:?}, Amount={}",
+        challenge.id,
+        attempt.id,
+        severity,
+        amount
+    );
+    Ok(payout)
+}
+
+/// Cancels a live challenge and returns its escrow to the company
+pub fn refund_challenge(id: u64) -> Result<(), ZeroLockError> {
+    CHALLENGES.with(|challenges| {
+        let mut challenges = challenges.borrow_mut();
+        let mut challenge = challenges.get(&id)
+            .ok_or_else(|| ZeroLockError::NotFound("Challenge not found".to_string()))?;
+        
+        validate_status_transition(&challenge.status, &ChallengeStatus::Cancelled)?;
+        check_no_open_claims(id)?;
+        release_escrow(&challenge, UnlockReason::ChallengeCancelled)?;
+        wind_down_challenge(&challenge);
+        
+        challenge.status = ChallengeStatus::Cancelled;
+        challenge.updated_at = current_time();
+        challenges.insert(id, challenge);
+        
+        events::record_event(Event::ChallengeCancelled { challenge_id: id });
+        ic_cdk::println!("Challenge refunded: ID={}", id);
+        Ok(())
+    })
+}
+
+/// Pushes back the deadline of an active challenge
+/// @param extension Nanoseconds to add to the end time
+/// @returns The new end time
+pub fn extend_challenge_deadline(id: u64, extension: i64) -> Result<i64, ZeroLockError> {
+    if extension <= 0 {
+        return Err(ZeroLockError::InvalidInput(
+            "Deadline extension must be positive".to_string()
         ));
     }
     
-    // Find and remove the admin
-    let removed = ADMINS.with(|admins| {
-        let mut admins = admins.borrow_mut();
-        let mut to_remove: Option<u64> = None;
+    CHALLENGES.with(|challenges| {
+        let mut challenges = challenges.borrow_mut();
+        let mut challenge = challenges.get(&id)
+            .ok_or_else(|| ZeroLockError::NotFound("Challenge not found".to_string()))?;
         
-        for (id, storable_principal) in admins.iter() {
-            if storable_principal.0 == admin_to_remove {
-                to_remove = Some(id);
-                break;
-            }
+        if challenge.status != ChallengeStatus::Active {
+            return Err(ZeroLockError::InvalidState(
+                "Only active challenges can be extended".to_string()
+            ));
         }
         
-        if let Some(id) = to_remove {
-            admins.remove(&id);
-            true
-        } else {
-            false
+        let end_time = challenge.end_time.saturating_add(extension);
+        if end_time - challenge.start_time > MAX_CHALLENGE_DURATION {
+            return Err(ZeroLockError::InvalidInput(
+                "Extension would exceed the maximum challenge duration".to_string()
+            ));
         }
+        
+        challenge.end_time = end_time;
+        challenge.updated_at = current_time();
+        challenges.insert(id, challenge);
+        
+        events::record_event(Event::DeadlineExtended { challenge_id: id, end_time });
+        ic_cdk::println!("Challenge deadline extended: ID={}, End={}", id, end_time);
+        Ok(end_time)
+    })
+}
+
+/// Loads the attack attempts made against a challenge, oldest first
+pub fn challenge_attack_attempts(challenge_id: u64) -> Vec<AttackAttempt> {
+    let attempt_ids: Vec<u64> = CHALLENGE_ATTACKS.with(|index| {
+        index
+            .borrow()
+            .range((challenge_id, 0)..=(challenge_id, u64::MAX))
+            .map(|((_, attempt_id), _)| attempt_id)
+            .collect()
     });
+
+    attempt_ids.into_iter().filter_map(get_attack_attempt_internal).collect()
+}
+
+/// Finds a hacker's most recent attack attempt on a challenge
+pub fn latest_attack_by(challenge_id: u64, hacker: Principal) -> Option<AttackAttempt> {
+    HACKER_ATTACKS.with(|index| {
+        index
+            .borrow()
+            .range((StorablePrincipal(hacker), 0)..=(StorablePrincipal(hacker), u64::MAX))
+            .rev()
+            .filter_map(|((_, attempt_id), _)| get_attack_attempt_internal(attempt_id))
+            .find(|attempt| attempt.challenge_id == challenge_id)
+    })
+}
+
+/// Creates a canister controlled by this one and installs a stored WASM module
+/// @returns Principal of the new canister
+pub async fn deploy_wasm_instance(wasm_hash: &[u8], cycles: u128) -> Result<Principal, ZeroLockError> {
+    // Load the target module from the WASM store
+    let wasm_module = wasm_store::load_wasm_module(wasm_hash)?;
     
-    if removed {
-        ic_cdk::println!("Admin removed: {}", admin_to_remove.to_text());
-        ApiResponse::Ok(())
-    } else {
-        ApiResponse::Err(ZeroLockError::NotFound(
-            "Admin not found".to_string()
-        ))
-    }
+    let (canister_result,) = create_canister(
+        CreateCanisterArgument {
+            settings: Some(CanisterSettings {
+                controllers: Some(vec![ic_cdk::id()]),
+                compute_allocation: None,
+                memory_allocation: None,
+                freezing_threshold: None,
+                reserved_cycles_limit: None,
+            }),
+        },
+        cycles,
+    ).await.map_err(|(code, msg)| {
+        ZeroLockError::InternalError(format!("Failed to create canister: {:?} - {}", code, msg))
+    })?;
+    let canister_id = canister_result.canister_id;
+    
+    install_code(InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id,
+        wasm_module,
+        arg: vec![], // Empty argument
+    }).await.map_err(|(code, msg)| {
+        ZeroLockError::InternalError(format!("Failed to install code: {:?} - {}", code, msg))
+    })?;
+    
+    Ok(canister_id)
+}
+
+/// Stops and deletes a canister this one deployed
+pub async fn delete_wasm_instance(canister_id: Principal) -> Result<(), ZeroLockError> {
+    stop_canister(CanisterIdRecord { canister_id }).await.map_err(|(code, msg)| {
+        ZeroLockError::InternalError(format!("Failed to stop canister: {:?} - {}", code, msg))
+    })?;
+    delete_canister(CanisterIdRecord { canister_id }).await.map_err(|(code, msg)| {
+        ZeroLockError::InternalError(format!("Failed to delete canister: {:?} - {}", code, msg))
+    })?;
+    
+    ic_cdk::println!("Deleted canister: {}", canister_id.to_text());
+    Ok(())
 }
 
 // Private helper functions
 
-/// Validates challenge creation request
-fn validate_challenge_request(request: &CreateChallengeRequest) -> Result<(), ZeroLockError> {
-    // Validate WASM size
-    validate_wasm_size(&request.wasm_code)?;
+/// Pays a triaged finding once the caller and the judge's decision allow it
+fn pay_severity_tier_internal(
+    caller: Principal,
+    attack_attempt_id: u64,
+    severity: Severity,
+) -> Result<TierPayout, ZeroLockError> {
+    let attempt = get_attack_attempt_internal(attack_attempt_id)
+        .ok_or_else(|| ZeroLockError::NotFound("Attack attempt not found".to_string()))?;
+    let challenge = get_challenge_internal(attempt.challenge_id)
+        .ok_or_else(|| ZeroLockError::NotFound("Challenge not found".to_string()))?;
     
-    if request.wasm_code.is_empty() {
-        return Err(ZeroLockError::InvalidInput(
-            "WASM code cannot be empty".to_string()
+    if caller != challenge.company && !rbac::is_admin(&caller) {
+        return Err(ZeroLockError::Unauthorized(
+            "Only the challenge company or an admin can pay findings".to_string()
         ));
     }
     
-    // Validate bounty amount
-    if request.bounty_amount < MIN_BOUNTY_AMOUNT {
-        return Err(ZeroLockError::InvalidInput(
-            "Bounty amount below minimum threshold".to_string()
+    if !judge::is_attack_confirmed(&attempt) {
+        return Err(ZeroLockError::InvalidState(
+            "Only attacks judged valid can be paid".to_string()
         ));
     }
     
+    pay_tier_internal(&challenge, &attempt, severity, caller)
+}
+
+/// Validates challenge creation request
+fn validate_challenge_request(request: &CreateChallengeRequest) -> Result<(), ZeroLockError> {
+    // Validate the target module has been uploaded and committed
+    if !wasm_store::wasm_module_exists(&request.wasm_hash) {
+        return Err(ZeroLockError::NotFound(
+            "WASM module not found, upload and commit it first".to_string()
+        ));
+    }
+    
+    // Validate the token is allow-listed and the bounty meets its minimum
+    let token = vault::enabled_token(&request.token_type)?;
+    if request.bounty_amount < token.min_bounty {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "Bounty amount below the {} minimum of {}",
+            token.symbol, token.min_bounty
+        )));
+    }
+    
     // Validate duration
     if request.duration_hours < MIN_DURATION_HOURS || request.duration_hours > MAX_DURATION_HOURS {
         return Err(ZeroLockError::InvalidInput(
@@ -612,6 +1364,39 @@ fn validate_challenge_request(request: &CreateChallengeRequest) -> Result<(), Ze
     // Validate candid interface
     validate_candid_interface(&request.candid_interface)?;
     
+    // Validate where the judge will read the target's balance
+    validate_balance_source(&request.balance_source)?;
+    
+    // A fixed ledger account cannot tell one hacker's instance from another's
+    if request.sandboxed == Some(true) && matches!(request.balance_source, BalanceSource::Icrc1Balance { .. }) {
+        return Err(ZeroLockError::InvalidInput(
+            "Sandboxed challenges must read balances from the instance itself".to_string()
+        ));
+    }
+    
+    // Validate the invariants the judge will check after each attack
+    if let Some(declared) = &request.invariants {
+        invariants::validate_invariants(declared)?;
+    }
+    
+    // Validate the severity tiers findings are paid from
+    if let Some(tiers) = &request.severity_tiers {
+        for (index, tier) in tiers.iter().enumerate() {
+            if tier.amount == 0 || tier.amount > request.bounty_amount {
+                return Err(ZeroLockError::InvalidInput(format!(
+                    "The {:?} tier must pay between 1 and the bounty amount",
+                    tier.severity
+                )));
+            }
+            if tiers[..index].iter().any(|earlier| earlier.severity == tier.severity) {
+                return Err(ZeroLockError::InvalidInput(format!(
+                    "The {:?} tier is declared more than once",
+                    tier.severity
+                )));
+            }
+        }
+    }
+    
     // Validate difficulty level
     if !is_valid_difficulty_level(request.difficulty_level) {
         return Err(ZeroLockError::InvalidInput(
@@ -654,24 +1439,261 @@ fn count_user_challenges(user: &Principal) -> u64 {
     })
 }
 
-/// Checks if a principal is an admin
-fn is_admin(principal: &Principal) -> bool {
-    ADMINS.with(|admins| {
-        admins
+/// Moves the challenge's bounty plus platform fee into a vault lock unless one is active
+/// Fails once the challenge has passed its end time, so nothing is activated after its deadline
+fn ensure_escrow(challenge: &Challenge) -> Result<(), ZeroLockError> {
+    if current_time() >= challenge.end_time {
+        return Err(ZeroLockError::InvalidState(
+            "Challenge has passed its end time".to_string()
+        ));
+    }
+    
+    if vault::get_active_lock(challenge.id).is_some() {
+        return Ok(());
+    }
+    
+    vault::lock_funds_internal(LockRequest {
+        challenge_id: challenge.id,
+        company: challenge.company,
+        amount: challenge.bounty_amount,
+        token_type: challenge.token_type.clone(),
+        duration: challenge.end_time - current_time(),
+    })?;
+    Ok(())
+}
+
+/// Fails until the challenge has passed its end time and its open claims are decided
+/// Hackers may attack until then, so the escrow stays locked for them
+fn check_can_expire(challenge: &Challenge) -> Result<(), ZeroLockError> {
+    if current_time() <= challenge.end_time {
+        return Err(ZeroLockError::InvalidState(
+            "Challenges expire at their end time; cancel it to end it earlier".to_string()
+        ));
+    }
+    check_no_open_claims(challenge.id)
+}
+
+/// Fails while a dispute or a panel review could still award the challenge's bounty
+fn check_no_open_claims(challenge_id: u64) -> Result<(), ZeroLockError> {
+    if judge::has_open_claims(challenge_id) {
+        return Err(ZeroLockError::InvalidState(
+            "Challenge has open disputes or attacks awaiting the panel".to_string()
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the challenge's escrow to the company, if any is still locked
+fn release_escrow(challenge: &Challenge, reason: UnlockReason) -> Result<(), ZeroLockError> {
+    match vault::get_active_lock(challenge.id) {
+        Some(lock_info) => {
+            vault::unlock_funds_internal(UnlockRequest {
+                challenge_id: challenge.id,
+                recipient: challenge.company,
+                amount: lock_info.amount,
+                reason,
+            })?;
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Lists all sandboxes of a challenge
+fn challenge_sandboxes(challenge_id: u64) -> Vec<Sandbox> {
+    let lowest = StorablePrincipal(Principal::from_slice(&[]));
+    SANDBOXES.with(|sandboxes| {
+        sandboxes
+            .borrow()
+            .range((challenge_id, lowest.clone())..(challenge_id + 1, lowest))
+            .map(|(_, sandbox)| sandbox)
+            .collect()
+    })
+}
+
+/// Returns escrow that outlived its challenge to the company
+/// Catches locks whose release failed when the challenge ended, e.g. while the vault was paused
+/// Run by the scheduler's LockExpiry job
+pub fn release_expired_locks() {
+    for lock_info in vault::expired_active_locks(current_time()) {
+        let challenge = get_challenge_internal(lock_info.challenge_id);
+        let reason = match challenge.as_ref().map(|challenge| &challenge.status) {
+            // Live challenges are released by the expiry job once they end
+            Some(ChallengeStatus::Active) => continue,
+            Some(ChallengeStatus::Cancelled) => UnlockReason::ChallengeCancelled,
+            _ => UnlockReason::ChallengeExpired,
+        };
+
+        let result = vault::unlock_funds_internal(UnlockRequest {
+            challenge_id: lock_info.challenge_id,
+            recipient: lock_info.company,
+            amount: lock_info.amount,
+            reason,
+        });
+        if let Err(error) = result {
+            ic_cdk::println!("Expired lock of challenge {} not released: {:?}", lock_info.challenge_id, error);
+            continue;
+        }
+
+        // A challenge that never went live cannot be activated without its escrow
+        if let Some(challenge) = challenge.filter(|challenge| challenge.status == ChallengeStatus::Created) {
+            expire_unfunded_challenge(challenge);
+        }
+    }
+}
+
+/// Marks a Created challenge whose escrow was refunded as Expired
+fn expire_unfunded_challenge(mut challenge: Challenge) {
+    wind_down_challenge(&challenge);
+    challenge.status = ChallengeStatus::Expired;
+    challenge.updated_at = current_time();
+    let challenge_id = challenge.id;
+    CHALLENGES.with(|challenges| challenges.borrow_mut().insert(challenge_id, challenge));
+    events::record_event(Event::ChallengeExpired { challenge_id });
+    ic_cdk::println!("Challenge {} expired before it was activated", challenge_id);
+}
+
+/// Stops judging an expired or cancelled challenge and queues its target for deletion
+/// The escrow has already been returned; the ChallengeExpiry job deletes the target
+fn wind_down_challenge(challenge: &Challenge) {
+    judge::stop_challenge_monitoring(challenge.id);
+
+    if let Some(target) = challenge.target_canister {
+        TARGET_TEARDOWNS.with(|queue| {
+            queue.borrow_mut().insert(challenge.id, StorablePrincipal(target))
+        });
+    }
+}
+
+/// Stops and deletes the targets queued by wind_down_challenge
+async fn teardown_finished_targets() {
+    let queued: Vec<(u64, StorablePrincipal)> = TARGET_TEARDOWNS.with(|queue| {
+        queue.borrow().iter().collect()
+    });
+
+    for (challenge_id, target) in queued {
+        // Keep the entry so the next run retries the deletion
+        if let Err(e) = delete_wasm_instance(target.0).await {
+            ic_cdk::println!("Target of challenge {} not torn down: {:?}", challenge_id, e);
+            continue;
+        }
+
+        TARGET_TEARDOWNS.with(|queue| queue.borrow_mut().remove(&challenge_id));
+    }
+}
+
+/// Deletes the sandboxes of challenges that have reached a final state
+async fn teardown_finished_sandboxes() {
+    let finished: Vec<Sandbox> = SANDBOXES.with(|sandboxes| {
+        sandboxes
             .borrow()
             .iter()
-            .any(|(_, admin)| admin.0 == *principal)
+            .map(|(_, sandbox)| sandbox)
+            .filter(|sandbox| sandbox.status == SandboxStatus::Active)
+            .filter(|sandbox| {
+                CHALLENGES.with(|challenges| challenges.borrow().get(&sandbox.challenge_id))
+                    .is_none_or(|challenge| is_final_status(&challenge.status))
+            })
+            .collect()
+    });
+
+    for mut sandbox in finished {
+        let Some(canister_id) = sandbox.canister_id else { continue };
+        judge::stop_sandbox_monitoring(canister_id);
+
+        // Leave the sandbox active so the next run retries the deletion
+        if let Err(e) = delete_wasm_instance(canister_id).await {
+            ic_cdk::println!("Sandbox {} not torn down: {:?}", canister_id.to_text(), e);
+            continue;
+        }
+
+        sandbox.status = SandboxStatus::TornDown;
+        sandbox.torn_down_at = Some(current_time());
+        let key = (sandbox.challenge_id, StorablePrincipal(sandbox.hacker));
+        SANDBOXES.with(|sandboxes| sandboxes.borrow_mut().insert(key, sandbox));
+    }
+}
+
+/// Reads the persisted factory state
+fn factory_state() -> FactoryState {
+    FACTORY_STATE.with(|cell| cell.borrow().get().clone())
+}
+
+/// Applies a change to the persisted factory state and writes it back
+fn update_factory_state<R>(f: impl FnOnce(&mut FactoryState) -> R) -> R {
+    FACTORY_STATE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut state = cell.get().clone();
+        let result = f(&mut state);
+        cell.set(state).expect("Failed to persist factory state");
+        result
+    })
+}
+
+/// Writes an attack attempt and its challenge and hacker index entries
+fn store_attack_attempt(attempt: &AttackAttempt) {
+    ATTACK_ATTEMPTS.with(|attempts| {
+        attempts.borrow_mut().insert(attempt.id, attempt.clone())
+    });
+    CHALLENGE_ATTACKS.with(|index| {
+        index.borrow_mut().insert((attempt.challenge_id, attempt.id), ())
+    });
+    HACKER_ATTACKS.with(|index| {
+        index.borrow_mut().insert((StorablePrincipal(attempt.hacker), attempt.id), ())
+    });
+}
+
+/// Loads a hacker's attack attempts on one challenge
+fn hacker_challenge_attempts(hacker: Principal, challenge_id: u64) -> Vec<AttackAttempt> {
+    let attempt_ids: Vec<u64> = HACKER_ATTACKS.with(|index| {
+        index
+            .borrow()
+            .range((StorablePrincipal(hacker), 0)..=(StorablePrincipal(hacker), u64::MAX))
+            .map(|((_, attempt_id), _)| attempt_id)
+            .collect()
+    });
+
+    attempt_ids
+        .into_iter()
+        .filter_map(get_attack_attempt_internal)
+        .filter(|attempt| attempt.challenge_id == challenge_id)
+        .collect()
+}
+
+/// Loads one page of attack attempts from an ascending list of IDs
+fn paginate_attack_attempts(attempt_ids: Vec<u64>, offset: u64, limit: u64) -> ApiResponse<PaginatedResult<AttackAttempt>> {
+    let validated_limit = match validate_pagination_params(offset, limit) {
+        Ok(l) => l,
+        Err(e) => return ApiResponse::Err(e),
+    };
+
+    let total = attempt_ids.len() as u64;
+    let data: Vec<AttackAttempt> = attempt_ids
+        .into_iter()
+        .rev()
+        .skip(offset as usize)
+        .take(validated_limit as usize)
+        .filter_map(get_attack_attempt_internal)
+        .collect();
+    let has_more = offset + (data.len() as u64) < total;
+
+    ApiResponse::Ok(PaginatedResult {
+        data,
+        total,
+        offset,
+        limit: validated_limit,
+        has_more,
     })
 }
 
-/// Periodic task to check and expire challenges (called by heartbeat)
-#[heartbeat]
+/// Expires challenges past their end time and tears down finished sandboxes and targets
+/// Run by the scheduler's ChallengeExpiry job
 pub async fn check_expired_challenges() {
     let current_time = current_time();
     
-    CHALLENGES.with(|challenges| {
-        let mut challenges = challenges.borrow_mut();
-        let expired_challenges: Vec<u64> = challenges
+    let expired_challenges: Vec<u64> = CHALLENGES.with(|challenges| {
+        challenges
+            .borrow()
             .iter()
             .filter_map(|(id, challenge)| {
                 if challenge.status == ChallengeStatus::Active && current_time > challenge.end_time {
@@ -680,15 +1702,39 @@ pub async fn check_expired_challenges() {
                     None
                 }
             })
-            .collect();
+            .collect()
+    });
+    
+    for id in expired_challenges {
+        // Expiry rules may extend the deadline, pay out or refund first
+        judge::apply_expiry_rules(id);
         
-        for id in expired_challenges {
+        CHALLENGES.with(|challenges| {
+            let mut challenges = challenges.borrow_mut();
             if let Some(mut challenge) = challenges.get(&id) {
+                if challenge.status != ChallengeStatus::Active || current_time <= challenge.end_time {
+                    return;
+                }
+                // A dispute or panel review may still award the bounty; the next run checks again
+                if judge::has_open_claims(id) {
+                    ic_cdk::println!("Expiry of challenge {} held for open claims", id);
+                    return;
+                }
+                // Leave the challenge active so the next run retries the refund
+                if let Err(error) = release_escrow(&challenge, UnlockReason::ChallengeExpired) {
+                    ic_cdk::println!("Escrow release failed for challenge {}: {:?}", id, error);
+                    return;
+                }
+                wind_down_challenge(&challenge);
                 challenge.status = ChallengeStatus::Expired;
                 challenge.updated_at = current_time;
                 challenges.insert(id, challenge);
+                events::record_event(Event::ChallengeExpired { challenge_id: id });
                 ic_cdk::println!("Auto-expired challenge: ID={}", id);
             }
-        }
-    });
-}
\ No newline at end of file
+        });
+    }
+
+    teardown_finished_sandboxes().await;
+    teardown_finished_targets().await;
+}
@@ -0,0 +1,153 @@
+//! Events Module - Append-only log of platform events
+//! Modules record lifecycle events here so clients can follow challenges and payouts
+
+use crate::types::*;
+use crate::memory::{get_memory, Memory, MemoryRegion};
+use crate::migrations::{encode_record, decode_stored_record};
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::*;
+use ic_stable_structures::{StableBTreeMap, StableCell, Storable};
+use serde::Serialize;
+use std::cell::RefCell;
+
+use std::borrow::Cow;
+
+// Memory management
+type EventStorage = StableBTreeMap<u64, EventRecord, Memory>;
+type EventCounterCell = StableCell<u64, Memory>;
+
+// Configuration constants
+const MAX_EVENTS_PAGE_SIZE: u64 = 100;
+
+// Logged event with its position in the log
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct EventRecord {
+    pub id: u64,
+    pub event: Event,
+    pub timestamp: i64,
+}
+
+// Global state - Events uses MemoryId 50-59
+thread_local! {
+    static EVENT_LOG: RefCell<EventStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Events, 50)
+        )
+    );
+
+    static NEXT_EVENT_ID: RefCell<EventCounterCell> = RefCell::new(
+        StableCell::init(
+            get_memory(MemoryRegion::Events, 51),
+            1
+        ).expect("Failed to initialize event counter")
+    );
+}
+
+/// Lists logged events, newest first
+/// @param offset Pagination offset
+/// @param limit Maximum number of results (at most 100)
+/// @returns Paginated list of events
+#[query]
+pub fn get_events(offset: u64, limit: u64) -> ApiResponse<PaginatedResult<EventRecord>> {
+    let limit = limit.min(MAX_EVENTS_PAGE_SIZE);
+
+    EVENT_LOG.with(|log| {
+        let log = log.borrow();
+        let total = log.len();
+
+        let data: Vec<EventRecord> = log
+            .iter()
+            .rev()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|(_, record)| record)
+            .collect();
+
+        ApiResponse::Ok(PaginatedResult {
+            data,
+            total,
+            offset,
+            limit,
+            has_more: offset + limit < total,
+        })
+    })
+}
+
+/// Lists the events concerning one challenge, oldest first
+/// @param challenge_id Challenge identifier
+/// @returns Events recorded for the challenge
+#[query]
+pub fn get_challenge_events(challenge_id: u64) -> ApiResponse<Vec<EventRecord>> {
+    EVENT_LOG.with(|log| {
+        let events = log
+            .borrow()
+            .iter()
+            .map(|(_, record)| record)
+            .filter(|record| event_challenge_id(&record.event) == challenge_id)
+            .collect();
+
+        ApiResponse::Ok(events)
+    })
+}
+
+// Public functions for other modules
+
+/// Appends an event to the log
+/// @returns ID of the logged event
+pub fn record_event(event: Event) -> u64 {
+    let event_id = NEXT_EVENT_ID.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let current = *cell.get();
+        cell.set(current + 1).expect("Failed to update event counter");
+        current
+    });
+
+    let record = EventRecord {
+        id: event_id,
+        event,
+        timestamp: current_time(),
+    };
+
+    EVENT_LOG.with(|log| {
+        log.borrow_mut().insert(event_id, record)
+    });
+
+    event_id
+}
+
+// Private helper functions
+
+/// Returns the challenge an event belongs to
+fn event_challenge_id(event: &Event) -> u64 {
+    match event {
+        Event::ChallengeCreated { challenge_id, .. }
+        | Event::ChallengeActivated { challenge_id }
+        | Event::AttackAttempted { challenge_id, .. }
+        | Event::AttackSuccessful { challenge_id, .. }
+        | Event::ChallengeCompleted { challenge_id, .. }
+        | Event::ChallengeExpired { challenge_id }
+        | Event::FundsLocked { challenge_id, .. }
+        | Event::BountyPaid { challenge_id, .. }
+        | Event::ChallengeCancelled { challenge_id }
+        | Event::DeadlineExtended { challenge_id, .. }
+        | Event::RuleTriggered { challenge_id, .. }
+        | Event::BountyReversed { challenge_id, .. } => *challenge_id,
+    }
+}
+
+// Storable implementations for stable storage
+
+impl Storable for EventRecord {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
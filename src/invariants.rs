@@ -0,0 +1,226 @@
+//! Invariants Module - Evaluates company-declared invariants against a target canister
+//! Each invariant queries a method on the target and checks a predicate on the decoded reply
+
+use crate::types::*;
+use candid::{CandidType, Deserialize, IDLArgs, IDLValue, Int, Principal};
+use ic_cdk::api::call::call_raw;
+use serde::Serialize;
+
+// Configuration constants
+const MAX_INVARIANTS: usize = 8;
+const MAX_INVARIANT_NAME_LENGTH: usize = 64;
+const MAX_INVARIANT_PATH_DEPTH: usize = 8;
+const MAX_OBSERVED_LENGTH: usize = 256;
+
+// Result of checking one invariant against the target
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct InvariantOutcome {
+    pub name: String,
+    pub holds: bool,
+    pub observed: String, // Decoded reply, or why it could not be checked
+}
+
+/// Checks a challenge's declared invariants before it is stored
+pub fn validate_invariants(invariants: &[Invariant]) -> Result<(), ZeroLockError> {
+    if invariants.len() > MAX_INVARIANTS {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "At most {} invariants can be declared",
+            MAX_INVARIANTS
+        )));
+    }
+
+    for (index, invariant) in invariants.iter().enumerate() {
+        if invariant.name.is_empty() || invariant.name.len() > MAX_INVARIANT_NAME_LENGTH {
+            return Err(ZeroLockError::InvalidInput(format!(
+                "Invariant name must be 1 to {} characters",
+                MAX_INVARIANT_NAME_LENGTH
+            )));
+        }
+
+        if invariants[..index].iter().any(|other| other.name == invariant.name) {
+            return Err(ZeroLockError::InvalidInput(format!(
+                "Invariant name '{}' is declared twice",
+                invariant.name
+            )));
+        }
+
+        if invariant.method.is_empty() || invariant.method.len() > MAX_BALANCE_METHOD_LENGTH {
+            return Err(ZeroLockError::InvalidInput(format!(
+                "Invariant method name must be 1 to {} characters",
+                MAX_BALANCE_METHOD_LENGTH
+            )));
+        }
+
+        let paths: Vec<&Vec<String>> = match &invariant.predicate {
+            InvariantPredicate::Equals { path, .. }
+            | InvariantPredicate::AtLeast { path, .. }
+            | InvariantPredicate::AtMost { path, .. } => vec![path],
+            InvariantPredicate::SumEquals { total, items, element } => vec![total, items, element],
+        };
+        if paths.iter().any(|path| path.len() > MAX_INVARIANT_PATH_DEPTH) {
+            return Err(ZeroLockError::InvalidInput(format!(
+                "Invariant paths can be at most {} segments deep",
+                MAX_INVARIANT_PATH_DEPTH
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Queries the target for each invariant and evaluates its predicate
+/// Invariants whose method fails or whose reply lacks the declared path count as broken,
+/// since a target that can no longer answer has lost the property it promised
+pub async fn check_invariants(target_canister: Principal, invariants: &[Invariant]) -> Vec<InvariantOutcome> {
+    let mut outcomes = Vec::new();
+
+    for invariant in invariants {
+        let outcome = match query_target(target_canister, &invariant.method).await {
+            Ok(reply) => match evaluate_predicate(&invariant.predicate, &reply) {
+                Ok(holds) => InvariantOutcome {
+                    name: invariant.name.clone(),
+                    holds,
+                    observed: truncate(reply.to_string()),
+                },
+                Err(e) => InvariantOutcome {
+                    name: invariant.name.clone(),
+                    holds: false,
+                    observed: truncate(format!("{:?}", e)),
+                },
+            },
+            Err(e) => InvariantOutcome {
+                name: invariant.name.clone(),
+                holds: false,
+                observed: truncate(format!("{:?}", e)),
+            },
+        };
+        outcomes.push(outcome);
+    }
+
+    outcomes
+}
+
+/// Evaluates a predicate on a decoded reply
+/// @returns Whether the invariant holds, or an error if the reply lacks a declared path
+pub fn evaluate_predicate(predicate: &InvariantPredicate, reply: &IDLValue) -> Result<bool, ZeroLockError> {
+    match predicate {
+        InvariantPredicate::Equals { path, expected } => {
+            let value = resolve_path(reply, path)?;
+            Ok(matches_expected(value, expected))
+        }
+        InvariantPredicate::AtLeast { path, minimum } => {
+            Ok(to_number(resolve_path(reply, path)?)? >= *minimum)
+        }
+        InvariantPredicate::AtMost { path, maximum } => {
+            Ok(to_number(resolve_path(reply, path)?)? <= *maximum)
+        }
+        InvariantPredicate::SumEquals { total, items, element } => {
+            let expected_total = to_number(resolve_path(reply, total)?)?;
+            let entries = match resolve_path(reply, items)? {
+                IDLValue::Vec(entries) => entries,
+                _ => return Err(ZeroLockError::InvalidInput("Summed path is not a vector".to_string())),
+            };
+
+            let mut sum = Int::from(0u64);
+            for entry in entries {
+                sum += to_number(resolve_path(entry, element)?)?;
+            }
+            Ok(sum == expected_total)
+        }
+    }
+}
+
+// Private helper functions
+
+/// Calls a no-argument method on the target and decodes its first return value
+async fn query_target(target_canister: Principal, method: &str) -> Result<IDLValue, ZeroLockError> {
+    let reply = call_raw(target_canister, method, candid::encode_args(()).unwrap(), 0)
+        .await
+        .map_err(|(code, msg)| ZeroLockError::NetworkError(format!("{} failed: {:?} - {}", method, code, msg)))?;
+
+    let args = IDLArgs::from_bytes(&reply)
+        .map_err(|e| ZeroLockError::InvalidInput(format!("{} returned invalid Candid: {}", method, e)))?;
+
+    args.args.into_iter().next().ok_or_else(|| {
+        ZeroLockError::InvalidInput(format!("{} returned no value", method))
+    })
+}
+
+/// Follows a path of record fields and vector indices, looking through optional values
+fn resolve_path<'a>(value: &'a IDLValue, path: &[String]) -> Result<&'a IDLValue, ZeroLockError> {
+    let missing = |segment: &str| ZeroLockError::InvalidInput(format!("Reply has no '{}'", segment));
+
+    let mut current = unwrap_opt(value);
+    for segment in path {
+        // Decoded replies carry field hashes, so names are compared by their Candid hash
+        let field_id = segment.parse::<u32>().unwrap_or_else(|_| candid::idl_hash(segment));
+
+        current = match current {
+            IDLValue::Record(fields) => fields
+                .iter()
+                .find(|field| field.id.get_id() == field_id)
+                .map(|field| &field.val)
+                .ok_or_else(|| missing(segment))?,
+            IDLValue::Variant(variant) if variant.0.id.get_id() == field_id => &variant.0.val,
+            IDLValue::Vec(entries) => segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| entries.get(index))
+                .ok_or_else(|| missing(segment))?,
+            _ => return Err(missing(segment)),
+        };
+        current = unwrap_opt(current);
+    }
+
+    Ok(current)
+}
+
+/// Looks through `opt` wrappers to the value inside
+fn unwrap_opt(mut value: &IDLValue) -> &IDLValue {
+    while let IDLValue::Opt(inner) = value {
+        value = inner;
+    }
+    value
+}
+
+/// Reads any Candid integer as an arbitrary-precision number
+fn to_number(value: &IDLValue) -> Result<Int, ZeroLockError> {
+    match value {
+        IDLValue::Nat(n) => Ok(Int::from(n.clone())),
+        IDLValue::Int(i) => Ok(i.clone()),
+        IDLValue::Nat8(n) => Ok(Int::from(*n)),
+        IDLValue::Nat16(n) => Ok(Int::from(*n)),
+        IDLValue::Nat32(n) => Ok(Int::from(*n)),
+        IDLValue::Nat64(n) => Ok(Int::from(*n)),
+        IDLValue::Int8(i) => Ok(Int::from(*i)),
+        IDLValue::Int16(i) => Ok(Int::from(*i)),
+        IDLValue::Int32(i) => Ok(Int::from(*i)),
+        IDLValue::Int64(i) => Ok(Int::from(*i)),
+        other => Err(ZeroLockError::InvalidInput(format!("{} is not a number", other))),
+    }
+}
+
+/// Compares a decoded value with an invariant's expected value
+fn matches_expected(value: &IDLValue, expected: &InvariantValue) -> bool {
+    match (value, expected) {
+        (IDLValue::Bool(actual), InvariantValue::Bool(expected)) => actual == expected,
+        (IDLValue::Text(actual), InvariantValue::Text(expected)) => actual == expected,
+        (IDLValue::Principal(actual), InvariantValue::Principal(expected))
+        | (IDLValue::Service(actual), InvariantValue::Principal(expected)) => actual == expected,
+        (_, InvariantValue::Nat(expected)) => to_number(value).is_ok_and(|n| n == Int::from(expected.clone())),
+        (_, InvariantValue::Int(expected)) => to_number(value).is_ok_and(|n| n == *expected),
+        _ => false,
+    }
+}
+
+/// Caps recorded replies so breach records stay small
+fn truncate(mut text: String) -> String {
+    if text.len() > MAX_OBSERVED_LENGTH {
+        let mut end = MAX_OBSERVED_LENGTH;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
@@ -2,28 +2,45 @@
 //! Monitors challenge states, validates attacks, and triggers automatic settlements
 
 use crate::types::*;
-use candid::{CandidType, Deserialize, Principal};
+use crate::memory::{get_memory, Memory, MemoryRegion};
+use crate::migrations::{encode_record, decode_stored_record};
+use crate::{bounty_factory, events, leaderboard, panel, rbac};
+use crate::invariants::{self, InvariantOutcome};
+use crate::replay;
+use crate::vault::{self, UnlockReason, UnlockRequest};
+use crate::ledger;
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::call::call_raw;
+use ic_cdk::api::management_canister::main::{canister_status, CanisterIdRecord};
 use ic_cdk_macros::*;
-use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, Storable};
+use ic_stable_structures::{StableBTreeMap, StableCell, Storable};
 use serde::Serialize;
 use std::cell::RefCell;
 
 use std::borrow::Cow;
 
 // Memory management
-type Memory = VirtualMemory<DefaultMemoryImpl>;
 type EvaluationStorage = StableBTreeMap<u64, Evaluation, Memory>;
 type MonitoringStorage = StableBTreeMap<u64, MonitoringState, Memory>;
 type DisputeStorage = StableBTreeMap<u64, DisputeCase, Memory>;
 type RuleStorage = StableBTreeMap<u64, AutomatedRule, Memory>;
 type BalanceHistoryStorage = StableBTreeMap<StorableString, StorableVecBalanceSnapshot, Memory>;
+type JudgeStateCell = StableCell<JudgeState, Memory>;
+type SettlementStorage = StableBTreeMap<(u64, u64), Settlement, Memory>;
+type InvariantBreachStorage = StableBTreeMap<u64, StorableVecInvariantBreach, Memory>;
+type SandboxMonitoringStorage = StableBTreeMap<StorableString, MonitoringState, Memory>;
+type RuleFiringStorage = StableBTreeMap<(u64, u64), u64, Memory>;
 
 // Configuration constants
 const BALANCE_CHECK_INTERVAL: i64 = 60 * 1_000_000_000; // 60 seconds in nanoseconds
 const MAX_BALANCE_HISTORY: usize = 1000;
+const MAX_BALANCE_HISTORY_BYTES: usize = 16384; // Storable bound of one canister's history
 const ATTACK_THRESHOLD_PERCENTAGE: u64 = 10; // 10% balance decrease
-const DISPUTE_REVIEW_PERIOD: i64 = 7 * 24 * 3600 * 1_000_000_000; // 7 days
+pub const DISPUTE_REVIEW_PERIOD: i64 = 7 * 24 * 3600 * 1_000_000_000; // 7 days per review stage
+const MAX_DISPUTE_EVIDENCE_BYTES: usize = 2048;
+const MAX_RULES: u64 = 100;
+const MAX_RULE_NAME_LENGTH: usize = 64;
+const MAX_DEADLINE_EXTENSION: i64 = 30 * 24 * 3600 * 1_000_000_000; // 30 days
 
 // Judge-specific types
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
@@ -41,6 +58,7 @@ pub struct MonitoringState {
 pub struct BalanceSnapshot {
     pub canister_id: Principal,
     pub balance: u64,
+    pub source: BalanceSource,
     pub timestamp: i64,
     pub block_height: Option<u64>,
 }
@@ -69,6 +87,7 @@ impl Ord for BalanceSnapshot {
 pub struct AutomatedRule {
     pub id: u64,
     pub name: String,
+    pub challenge_id: Option<u64>, // None applies the rule to every challenge
     pub condition: RuleCondition,
     pub action: RuleAction,
     pub enabled: bool,
@@ -91,6 +110,17 @@ pub enum RuleAction {
     ExtendDeadline(i64),
 }
 
+// Rule fields set by create_rule and update_rule
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct RuleRequest {
+    pub name: String,
+    pub challenge_id: Option<u64>,
+    pub condition: RuleCondition,
+    pub action: RuleAction,
+    pub enabled: bool,
+    pub priority: u64, // Higher priorities are evaluated first
+}
+
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct DisputeCase {
     pub id: u64,
@@ -100,6 +130,8 @@ pub struct DisputeCase {
     pub reason: String,
     pub evidence: Vec<Vec<u8>>,
     pub status: DisputeStatus,
+    pub reviewer: Option<Principal>, // Assigned when review starts
+    pub review_deadline: i64, // When the current stage times out
     pub created_at: i64,
     pub resolved_at: Option<i64>,
     pub resolution: Option<String>,
@@ -107,69 +139,122 @@ pub struct DisputeCase {
 
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub enum DisputeStatus {
-    Open,
-    UnderReview,
-    Resolved,
-    Rejected,
+    Open,        // Waiting for a reviewer
+    UnderReview, // Assigned to a reviewer
+    Escalated,   // Review period passed; only admins can decide
+    Resolved,    // Decided in the disputer's favor
+    Rejected,    // Decided against the disputer, or never decided
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
-pub struct UnlockRequest {
-    pub challenge_id: u64,
-    pub recipient: Principal,
-    pub amount: u64,
-    pub reason: UnlockReason,
+// Counters and canister references kept in stable memory so they survive upgrades
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct JudgeState {
+    pub next_evaluation_id: u64,
+    pub next_dispute_id: u64,
+    pub next_rule_id: u64,
+    pub bounty_factory_canister: Option<Principal>,
+    pub vault_canister: Option<Principal>,
+}
+
+impl Default for JudgeState {
+    fn default() -> Self {
+        Self {
+            next_evaluation_id: 1,
+            next_dispute_id: 1,
+            next_rule_id: 1,
+            bounty_factory_canister: None,
+            vault_canister: None,
+        }
+    }
+}
+
+// First observation of a broken invariant on a challenge's target
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct InvariantBreach {
+    pub invariant: String,
+    pub observed: String,
+    pub attack_attempt_id: Option<u64>, // None when found by a periodic check
+    pub detected_at: i64,
 }
 
+// Payout made for an attack attempt; at most one exists per attempt
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
-pub enum UnlockReason {
-    BountyPayout(Principal),
-    ChallengeExpired,
-    ChallengeCancelled,
-    AdminOverride(String),
+pub struct Settlement {
+    pub challenge_id: u64,
+    pub winner: Principal,
+    pub attack_attempt_id: u64,
+    pub evaluation_id: u64,
+    pub amount: u64,
+    pub settled_at: i64,
+    pub reversed_at: Option<i64>, // Set when a dispute returns the bounty to the company
 }
 
 // Global state - Judge uses MemoryId 20-29
 thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
-        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
-    
     static EVALUATIONS: RefCell<EvaluationStorage> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20)))
+            get_memory(MemoryRegion::Judge, 20)
         )
     );
     
     static MONITORING_STATES: RefCell<MonitoringStorage> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21)))
+            get_memory(MemoryRegion::Judge, 21)
         )
     );
     
     static DISPUTES: RefCell<DisputeStorage> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22)))
+            get_memory(MemoryRegion::Judge, 22)
         )
     );
     
     static AUTOMATED_RULES: RefCell<RuleStorage> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23)))
+            get_memory(MemoryRegion::Judge, 23)
         )
     );
     
     static BALANCE_HISTORY: RefCell<BalanceHistoryStorage> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24)))
+            get_memory(MemoryRegion::Judge, 24)
+        )
+    );
+    
+    static JUDGE_STATE: RefCell<JudgeStateCell> = RefCell::new(
+        StableCell::init(
+            get_memory(MemoryRegion::Judge, 25),
+            JudgeState::default()
+        ).expect("Failed to initialize judge state")
+    );
+    
+    // Payouts keyed by (challenge_id, attack_attempt_id)
+    static SETTLEMENTS: RefCell<SettlementStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Judge, 26)
+        )
+    );
+    
+    static INVARIANT_BREACHES: RefCell<InvariantBreachStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Judge, 27)
         )
     );
     
-    static NEXT_EVALUATION_ID: RefCell<u64> = RefCell::new(1);
-    static NEXT_DISPUTE_ID: RefCell<u64> = RefCell::new(1);
-    static NEXT_RULE_ID: RefCell<u64> = RefCell::new(1);
+    // Monitoring of per-hacker sandboxes, keyed by sandbox canister
+    static SANDBOX_MONITORING: RefCell<SandboxMonitoringStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Judge, 28)
+        )
+    );
     
-    static BOUNTY_FACTORY_CANISTER: RefCell<Option<Principal>> = RefCell::new(None);
-    static VAULT_CANISTER: RefCell<Option<Principal>> = RefCell::new(None);
+    // When each (rule_id, challenge_id) pair last fired a challenge-wide action
+    static RULE_FIRINGS: RefCell<RuleFiringStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Judge, 29)
+        )
+    );
+
 }
 
 /// Starts monitoring a challenge's target canister
@@ -180,13 +265,13 @@ thread_local! {
 pub async fn start_monitoring(challenge_id: u64, target_canister: Principal) -> ApiResponse<()> {
     let caller = ic_cdk::caller();
     
-    // Verify caller is authorized (BountyFactory)
-    let bounty_factory = BOUNTY_FACTORY_CANISTER.with(|bf| *bf.borrow());
+    // Verify caller is authorized (BountyFactory, or an admin retrying after a failed start)
+    let bounty_factory = judge_state().bounty_factory_canister;
     match bounty_factory {
         Some(factory) => {
-            if caller != factory {
+            if caller != factory && !rbac::is_admin(&caller) {
                 return ApiResponse::Err(ZeroLockError::Unauthorized(
-                    "Only BountyFactory can start monitoring".to_string()
+                    "Only BountyFactory or an admin can start monitoring".to_string()
                 ));
             }
         }
@@ -197,6 +282,12 @@ pub async fn start_monitoring(challenge_id: u64, target_canister: Principal) ->
         }
     }
     
+    start_monitoring_internal(challenge_id, target_canister).await.into()
+}
+
+/// Starts monitoring once the caller has been authorized
+/// Called by BountyFactory after it deploys a challenge's target canister
+pub async fn start_monitoring_internal(challenge_id: u64, target_canister: Principal) -> Result<(), ZeroLockError> {
     // Check if already monitoring
     let existing_state = MONITORING_STATES.with(|states| {
         states.borrow().get(&challenge_id)
@@ -204,38 +295,62 @@ pub async fn start_monitoring(challenge_id: u64, target_canister: Principal) ->
     
     if let Some(existing) = existing_state {
         if existing.monitoring_active {
-            return ApiResponse::Err(ZeroLockError::InvalidState(
+            return Err(ZeroLockError::InvalidState(
                 "Already monitoring this challenge".to_string()
             ));
         }
     }
     
-    // Get initial balance
-    let initial_balance = get_canister_balance(target_canister).await;
-    
-    let monitoring_state = MonitoringState {
-        challenge_id,
-        target_canister,
-        initial_balance,
-        current_balance: initial_balance,
-        last_check: current_time(),
-        monitoring_active: true,
-        attack_detected: false,
-    };
-    
+    let monitoring_state = open_monitoring_state(challenge_id, target_canister).await?;
     MONITORING_STATES.with(|states| {
         states.borrow_mut().insert(challenge_id, monitoring_state)
     });
     
-    // Record initial balance snapshot
-    record_balance_snapshot(target_canister, initial_balance).await;
-    
     ic_cdk::println!(
         "Started monitoring challenge: {}, Target: {}",
         challenge_id,
         target_canister.to_text()
     );
-    ApiResponse::Ok(())
+    Ok(())
+}
+
+/// Starts monitoring a hacker's sandbox instance
+/// Called by BountyFactory after it deploys the sandbox
+pub async fn start_sandbox_monitoring(challenge_id: u64, sandbox_canister: Principal) -> Result<(), ZeroLockError> {
+    let monitoring_state = open_monitoring_state(challenge_id, sandbox_canister).await?;
+    SANDBOX_MONITORING.with(|states| {
+        states.borrow_mut().insert(StorableString(sandbox_canister.to_text()), monitoring_state)
+    });
+    
+    ic_cdk::println!(
+        "Started monitoring sandbox: Challenge={}, Canister={}",
+        challenge_id,
+        sandbox_canister.to_text()
+    );
+    Ok(())
+}
+
+/// Stops monitoring a challenge's shared target once the challenge has ended
+pub fn stop_challenge_monitoring(challenge_id: u64) {
+    MONITORING_STATES.with(|states| {
+        let mut states = states.borrow_mut();
+        if let Some(mut state) = states.get(&challenge_id) {
+            state.monitoring_active = false;
+            states.insert(challenge_id, state);
+        }
+    });
+}
+
+/// Stops monitoring a sandbox before it is torn down
+pub fn stop_sandbox_monitoring(sandbox_canister: Principal) {
+    let key = StorableString(sandbox_canister.to_text());
+    SANDBOX_MONITORING.with(|states| {
+        let mut states = states.borrow_mut();
+        if let Some(mut state) = states.get(&key) {
+            state.monitoring_active = false;
+            states.insert(key, state);
+        }
+    });
 }
 
 /// Stops monitoring a challenge
@@ -246,7 +361,7 @@ pub async fn stop_monitoring(challenge_id: u64) -> ApiResponse<()> {
     let caller = ic_cdk::caller();
     
     // Verify caller is authorized
-    let bounty_factory = BOUNTY_FACTORY_CANISTER.with(|bf| *bf.borrow());
+    let bounty_factory = judge_state().bounty_factory_canister;
     match bounty_factory {
         Some(factory) => {
             if caller != factory {
@@ -282,34 +397,47 @@ pub async fn stop_monitoring(challenge_id: u64) -> ApiResponse<()> {
     }
 }
 
-/// Evaluates an attack attempt and makes a decision
-/// @param challenge_id Challenge identifier
-/// @param attack_attempt Attack attempt data
+/// Evaluates a submitted attack attempt and makes a decision
+/// Called by BountyFactory::submit_attack once the attempt is recorded
+/// @param attack_attempt Stored attack attempt
 /// @returns Evaluation result
-#[update]
-pub async fn evaluate_attack(challenge_id: u64, attack_attempt: AttackAttempt) -> ApiResponse<Evaluation> {
-    let state = MONITORING_STATES.with(|states| {
-        states.borrow().get(&challenge_id)
-    });
+pub async fn evaluate_attack(attack_attempt: AttackAttempt) -> Result<Evaluation, ZeroLockError> {
+    let challenge_id = attack_attempt.challenge_id;
+    let challenge = load_challenge(challenge_id)?;
+    
+    // Sandboxed challenges are judged on the hacker's own instance
+    let state = if challenge.sandboxed {
+        bounty_factory::get_active_sandbox(challenge_id, attack_attempt.hacker)
+            .and_then(|canister| SANDBOX_MONITORING.with(|states| {
+                states.borrow().get(&StorableString(canister.to_text()))
+            }))
+    } else {
+        MONITORING_STATES.with(|states| {
+            states.borrow().get(&challenge_id)
+        })
+    };
     
     let state = match state {
         Some(state) => state,
         None => {
-            return ApiResponse::Err(ZeroLockError::NotFound(
+            return Err(ZeroLockError::NotFound(
                 "No monitoring state found for this challenge".to_string()
             ));
         }
     };
     
     if !state.monitoring_active {
-        return ApiResponse::Err(ZeroLockError::InvalidState(
+        return Err(ZeroLockError::InvalidState(
             "Monitoring is not active for this challenge".to_string()
         ));
     }
     
     // Check current balance
-    let current_balance = get_canister_balance(state.target_canister).await;
-    record_balance_snapshot(state.target_canister, current_balance).await;
+    let current_balance = get_canister_balance(state.target_canister, &challenge.balance_source).await?;
+    record_balance_snapshot(state.target_canister, current_balance, challenge.balance_source.clone());
+    
+    // Check the company's invariants
+    let broken_invariants = check_challenge_invariants(&challenge, state.target_canister, Some(attack_attempt.id)).await;
     
     // Calculate balance change
     let balance_decrease = if state.initial_balance > current_balance {
@@ -324,39 +452,35 @@ pub async fn evaluate_attack(challenge_id: u64, attack_attempt: AttackAttempt) -
         0
     };
     
-    // Make decision based on balance change
-    let decision = if decrease_percentage >= ATTACK_THRESHOLD_PERCENTAGE {
-        JudgeDecision::Valid
-    } else {
-        JudgeDecision::Invalid
-    };
-    
-    let reasoning = format!(
+    let mut reasoning = format!(
         "Balance change: {}% ({} tokens). Threshold: {}%",
         decrease_percentage, balance_decrease, ATTACK_THRESHOLD_PERCENTAGE
     );
+    if !broken_invariants.is_empty() {
+        reasoning.push_str(&format!(". Broken invariants: {}", broken_invariants.join(", ")));
+    }
     
-    // Create evaluation record
-    let evaluation_id = NEXT_EVALUATION_ID.with(|id| {
-        let mut id = id.borrow_mut();
-        let current = *id;
-        *id += 1;
-        current
-    });
-    
-    let evaluation = Evaluation {
-        id: evaluation_id,
-        challenge_id,
-        attack_attempt_id: attack_attempt.id,
-        decision: decision.clone(),
-        reasoning,
-        timestamp: current_time(),
-        evaluator: ic_cdk::id(),
+    // Challenges with invariants are decided by replaying the proof on a fresh instance;
+    // without invariants the balance decides, but only a hacker's own sandbox ties a drop
+    // to their attack, so a drop on a shared target is held for the panel
+    let mut decision = if challenge.invariants.is_empty() {
+        if decrease_percentage < ATTACK_THRESHOLD_PERCENTAGE {
+            JudgeDecision::Invalid
+        } else if challenge.sandboxed {
+            JudgeDecision::Valid
+        } else {
+            reasoning.push_str(". Held for panel review: the shared target's balance does not show who drained it");
+            JudgeDecision::Disputed
+        }
+    } else {
+        let (decision, replay_reasoning) = verify_replay(&challenge, &attack_attempt).await?;
+        reasoning.push_str(&format!(". {}", replay_reasoning));
+        decision
     };
     
-    EVALUATIONS.with(|evaluations| {
-        evaluations.borrow_mut().insert(evaluation_id, evaluation.clone())
-    });
+    // Create evaluation record
+    let mut evaluation = record_evaluation(challenge_id, attack_attempt.id, decision.clone(), reasoning, ic_cdk::id());
+    let evaluation_id = evaluation.id;
     
     // Update monitoring state
     let updated_state = MonitoringState {
@@ -369,13 +493,37 @@ pub async fn evaluate_attack(challenge_id: u64, attack_attempt: AttackAttempt) -
         attack_detected: decision == JudgeDecision::Valid,
     };
     
-    MONITORING_STATES.with(|states| {
-        states.borrow_mut().insert(challenge_id, updated_state)
-    });
+    if challenge.sandboxed {
+        SANDBOX_MONITORING.with(|states| {
+            states.borrow_mut().insert(StorableString(state.target_canister.to_text()), updated_state)
+        });
+    } else {
+        MONITORING_STATES.with(|states| {
+            states.borrow_mut().insert(challenge_id, updated_state)
+        });
+    }
+    
+    // Rules run before payout, so one can still hold the attack for manual review
+    let context = RuleContext {
+        challenge: &challenge,
+        balances: Some((state.initial_balance, current_balance)),
+        attack_attempt: Some(&attack_attempt),
+        evaluation_id: Some(evaluation_id),
+    };
+    if apply_rules(&context) {
+        decision = JudgeDecision::Disputed;
+        evaluation.decision = decision.clone();
+        evaluation.reasoning.push_str(". Held for manual review by rule");
+        EVALUATIONS.with(|evaluations| {
+            evaluations.borrow_mut().insert(evaluation_id, evaluation.clone())
+        });
+    }
     
     // Trigger settlement if attack is valid
     if decision == JudgeDecision::Valid {
-        let _ = trigger_settlement(challenge_id, attack_attempt.hacker).await;
+        if let Err(e) = trigger_settlement(challenge_id, attack_attempt.hacker, attack_attempt.id, evaluation_id) {
+            ic_cdk::println!("Settlement failed for challenge {}: {:?}", challenge_id, e);
+        }
     }
     
     ic_cdk::println!(
@@ -383,10 +531,11 @@ pub async fn evaluate_attack(challenge_id: u64, attack_attempt: AttackAttempt) -
         challenge_id,
         decision
     );
-    ApiResponse::Ok(evaluation)
+    Ok(evaluation)
 }
 
-/// Creates a dispute for an evaluation
+/// Creates a dispute over the evaluation of an attack attempt
+/// The hacker can dispute an attempt that was not paid, the company one that was
 /// @param challenge_id Challenge identifier
 /// @param attack_attempt_id Attack attempt identifier
 /// @param reason Dispute reason
@@ -407,16 +556,70 @@ pub async fn create_dispute(
         ));
     }
     
-    if reason.is_empty() {
-        return ApiResponse::Err(ZeroLockError::InvalidInput(
-            "Dispute reason cannot be empty".to_string()
+    if reason.is_empty() || reason.len() > MAX_DESCRIPTION_LENGTH {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(format!(
+            "Dispute reason must be 1 to {} characters",
+            MAX_DESCRIPTION_LENGTH
+        )));
+    }
+    
+    if evidence.iter().map(|item| item.len()).sum::<usize>() > MAX_DISPUTE_EVIDENCE_BYTES {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(format!(
+            "Evidence cannot exceed {} bytes",
+            MAX_DISPUTE_EVIDENCE_BYTES
+        )));
+    }
+    
+    let attempt = match bounty_factory::get_attack_attempt_internal(attack_attempt_id) {
+        Some(attempt) if attempt.challenge_id == challenge_id => attempt,
+        _ => return ApiResponse::Err(ZeroLockError::NotFound(
+            "Attack attempt not found on this challenge".to_string()
+        )),
+    };
+    let challenge = match load_challenge(challenge_id) {
+        Ok(challenge) => challenge,
+        Err(e) => return ApiResponse::Err(e),
+    };
+    
+    let paid = unreversed_settlement(challenge_id, attack_attempt_id).is_some();
+    if caller == attempt.hacker {
+        if paid {
+            return ApiResponse::Err(ZeroLockError::InvalidState(
+                "This attack has already been paid".to_string()
+            ));
+        }
+        // Winning pays from the escrow, which is gone once the challenge has ended
+        if challenge.status != ChallengeStatus::Active {
+            return ApiResponse::Err(ZeroLockError::InvalidState(
+                "Unpaid attacks can only be disputed while the challenge is active".to_string()
+            ));
+        }
+    } else if caller == challenge.company {
+        if !paid {
+            return ApiResponse::Err(ZeroLockError::InvalidState(
+                "Companies can only dispute the attack that was paid".to_string()
+            ));
+        }
+    } else {
+        return ApiResponse::Err(ZeroLockError::Unauthorized(
+            "Only the hacker or the company can dispute an attack".to_string()
         ));
     }
     
-    let dispute_id = NEXT_DISPUTE_ID.with(|id| {
-        let mut id = id.borrow_mut();
-        let current = *id;
-        *id += 1;
+    let already_disputed = DISPUTES.with(|disputes| {
+        disputes.borrow().iter().any(|(_, dispute)| {
+            dispute.attack_attempt_id == attack_attempt_id && !is_dispute_closed(&dispute.status)
+        })
+    });
+    if already_disputed {
+        return ApiResponse::Err(ZeroLockError::AlreadyExists(
+            "This attack already has an open dispute".to_string()
+        ));
+    }
+    
+    let dispute_id = update_judge_state(|state| {
+        let current = state.next_dispute_id;
+        state.next_dispute_id += 1;
         current
     });
     
@@ -428,6 +631,8 @@ pub async fn create_dispute(
         reason,
         evidence,
         status: DisputeStatus::Open,
+        reviewer: None,
+        review_deadline: current_time() + DISPUTE_REVIEW_PERIOD,
         created_at: current_time(),
         resolved_at: None,
         resolution: None,
@@ -445,9 +650,58 @@ pub async fn create_dispute(
     ApiResponse::Ok(dispute_id)
 }
 
-/// Resolves a dispute (admin function)
+/// Assigns a reviewer to an open dispute (admin function)
+/// @param dispute_id Dispute identifier
+/// @param reviewer Admin or panel judge who will decide the dispute
+/// @returns Success or error
+#[update]
+pub fn assign_dispute_reviewer(dispute_id: u64, reviewer: Principal) -> ApiResponse<()> {
+    let caller = ic_cdk::caller();
+    if !rbac::is_admin(&caller) {
+        return ApiResponse::Err(ZeroLockError::Unauthorized(
+            "Only admins can assign dispute reviewers".to_string()
+        ));
+    }
+    
+    if !rbac::is_admin(&reviewer) && !panel::is_panel_judge(reviewer) {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(
+            "Reviewers must be admins or panel judges".to_string()
+        ));
+    }
+    
+    let mut dispute = match DISPUTES.with(|disputes| disputes.borrow().get(&dispute_id)) {
+        Some(dispute) => dispute,
+        None => return ApiResponse::Err(ZeroLockError::NotFound("Dispute not found".to_string())),
+    };
+    
+    if dispute.status != DisputeStatus::Open {
+        return ApiResponse::Err(ZeroLockError::InvalidState(
+            "Only open disputes can be assigned".to_string()
+        ));
+    }
+    
+    if reviewer == dispute.disputer {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(
+            "Disputers cannot review their own disputes".to_string()
+        ));
+    }
+    
+    dispute.status = DisputeStatus::UnderReview;
+    dispute.reviewer = Some(reviewer);
+    dispute.review_deadline = current_time() + DISPUTE_REVIEW_PERIOD;
+    DISPUTES.with(|disputes| {
+        disputes.borrow_mut().insert(dispute_id, dispute)
+    });
+    
+    ic_cdk::println!("Dispute {} assigned to {}", dispute_id, reviewer.to_text());
+    ApiResponse::Ok(())
+}
+
+/// Decides a dispute under review
+/// Callable by the assigned reviewer, or by an admin; escalated disputes are admin only.
+/// Resolving for a hacker pays the bounty, resolving for a company reverses the payout
 /// @param dispute_id Dispute identifier
-/// @param resolution Resolution decision
+/// @param resolution Resolved (for the disputer) or Rejected
 /// @param resolution_text Explanation of resolution
 /// @returns Success or error
 #[update]
@@ -456,7 +710,7 @@ pub async fn resolve_dispute(
     resolution: DisputeStatus,
     resolution_text: String,
 ) -> ApiResponse<()> {
-    // In production, this should be restricted to authorized judges/admins
+    let caller = ic_cdk::caller();
     
     let dispute = DISPUTES.with(|disputes| {
         disputes.borrow().get(&dispute_id)
@@ -471,23 +725,45 @@ pub async fn resolve_dispute(
         }
     };
     
-    if dispute.status != DisputeStatus::Open && dispute.status != DisputeStatus::UnderReview {
-        return ApiResponse::Err(ZeroLockError::InvalidState(
-            "Dispute is already resolved".to_string()
+    let authorized = match dispute.status {
+        DisputeStatus::UnderReview => dispute.reviewer == Some(caller) || rbac::is_admin(&caller),
+        DisputeStatus::Escalated => rbac::is_admin(&caller),
+        _ => {
+            return ApiResponse::Err(ZeroLockError::InvalidState(
+                "Only disputes under review or escalated can be decided".to_string()
+            ));
+        }
+    };
+    if !authorized {
+        return ApiResponse::Err(ZeroLockError::Unauthorized(
+            "Only the assigned reviewer or an admin can decide this dispute".to_string()
+        ));
+    }
+    
+    if resolution != DisputeStatus::Resolved && resolution != DisputeStatus::Rejected {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(
+            "Disputes are decided as Resolved or Rejected".to_string()
+        ));
+    }
+    
+    if resolution_text.is_empty() {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(
+            "Resolution text cannot be empty".to_string()
         ));
     }
     
+    // Money moves first, so a failed payout or reversal leaves the dispute undecided
+    if resolution == DisputeStatus::Resolved {
+        if let Err(e) = apply_dispute_outcome(&dispute, caller, &resolution_text) {
+            return ApiResponse::Err(e);
+        }
+    }
+    
     let updated_dispute = DisputeCase {
-        id: dispute.id,
-        challenge_id: dispute.challenge_id,
-        attack_attempt_id: dispute.attack_attempt_id,
-        disputer: dispute.disputer,
-        reason: dispute.reason,
-        evidence: dispute.evidence,
         status: resolution.clone(),
-        created_at: dispute.created_at,
         resolved_at: Some(current_time()),
         resolution: Some(resolution_text),
+        ..dispute
     };
     
     DISPUTES.with(|disputes| {
@@ -502,6 +778,17 @@ pub async fn resolve_dispute(
     ApiResponse::Ok(())
 }
 
+/// Gets a dispute
+/// @param dispute_id Dispute identifier
+/// @returns Dispute or error if not found
+#[query]
+pub fn get_dispute(dispute_id: u64) -> ApiResponse<DisputeCase> {
+    match DISPUTES.with(|disputes| disputes.borrow().get(&dispute_id)) {
+        Some(dispute) => ApiResponse::Ok(dispute),
+        None => ApiResponse::Err(ZeroLockError::NotFound("Dispute not found".to_string())),
+    }
+}
+
 /// Gets monitoring state for a challenge
 /// @param challenge_id Challenge identifier
 /// @returns Monitoring state
@@ -517,6 +804,21 @@ pub fn get_monitoring_state(challenge_id: u64) -> ApiResponse<MonitoringState> {
     })
 }
 
+/// Gets monitoring state for a hacker's sandbox
+/// @param sandbox_canister Principal of the sandbox canister
+/// @returns Monitoring state
+#[query]
+pub fn get_sandbox_monitoring_state(sandbox_canister: Principal) -> ApiResponse<MonitoringState> {
+    SANDBOX_MONITORING.with(|states| {
+        match states.borrow().get(&StorableString(sandbox_canister.to_text())) {
+            Some(state) => ApiResponse::Ok(state),
+            None => ApiResponse::Err(ZeroLockError::NotFound(
+                "No monitoring state found".to_string()
+            )),
+        }
+    })
+}
+
 /// Gets evaluation history for a challenge
 /// @param challenge_id Challenge identifier
 /// @returns Array of evaluations
@@ -541,6 +843,30 @@ pub fn get_evaluations(challenge_id: u64) -> ApiResponse<Vec<Evaluation>> {
     })
 }
 
+/// Gets the settlements of a challenge, one per paid attack attempt
+/// @param challenge_id Challenge identifier
+/// @returns Settlements in attack attempt order, including reversed ones
+#[query]
+pub fn get_settlements(challenge_id: u64) -> ApiResponse<Vec<Settlement>> {
+    ApiResponse::Ok(SETTLEMENTS.with(|settlements| {
+        settlements
+            .borrow()
+            .range((challenge_id, 0)..=(challenge_id, u64::MAX))
+            .map(|(_, settlement)| settlement)
+            .collect()
+    }))
+}
+
+/// Gets the invariants found broken on a challenge's target
+/// @param challenge_id Challenge identifier
+/// @returns First breach of each broken invariant, oldest first
+#[query]
+pub fn get_invariant_breaches(challenge_id: u64) -> ApiResponse<Vec<InvariantBreach>> {
+    INVARIANT_BREACHES.with(|breaches| {
+        ApiResponse::Ok(breaches.borrow().get(&challenge_id).unwrap_or_default().0)
+    })
+}
+
 /// Gets balance history for a canister
 /// @param canister_id Canister principal
 /// @param limit Maximum number of snapshots to return
@@ -578,7 +904,7 @@ pub fn get_open_disputes() -> ApiResponse<Vec<DisputeCase>> {
             .borrow()
             .iter()
             .filter_map(|(_, dispute)| {
-                if dispute.status == DisputeStatus::Open || dispute.status == DisputeStatus::UnderReview {
+                if !is_dispute_closed(&dispute.status) {
                     Some(dispute)
                 } else {
                     None
@@ -592,43 +918,223 @@ pub fn get_open_disputes() -> ApiResponse<Vec<DisputeCase>> {
     })
 }
 
-/// Sets the BountyFactory canister reference
+/// Creates an automated rule (admin function)
+/// @param request Rule definition
+/// @returns ID of the new rule
 #[update]
-pub fn set_bounty_factory(canister: Principal) -> ApiResponse<()> {
-    // In production, this should be restricted to governance
-    BOUNTY_FACTORY_CANISTER.with(|bf| *bf.borrow_mut() = Some(canister));
-    ic_cdk::println!("BountyFactory canister set: {}", canister.to_text());
-    ApiResponse::Ok(())
+pub fn create_rule(request: RuleRequest) -> ApiResponse<u64> {
+    if let Err(e) = check_caller_is_admin() {
+        return ApiResponse::Err(e);
+    }
+    
+    if let Err(e) = validate_rule_request(&request) {
+        return ApiResponse::Err(e);
+    }
+    
+    if AUTOMATED_RULES.with(|rules| rules.borrow().len()) >= MAX_RULES {
+        return ApiResponse::Err(ZeroLockError::ResourceLimit(
+            "Maximum number of rules reached".to_string()
+        ));
+    }
+    
+    let rule_id = update_judge_state(|state| {
+        let current = state.next_rule_id;
+        state.next_rule_id += 1;
+        current
+    });
+    
+    let rule = AutomatedRule {
+        id: rule_id,
+        name: request.name,
+        challenge_id: request.challenge_id,
+        condition: request.condition,
+        action: request.action,
+        enabled: request.enabled,
+        priority: request.priority,
+    };
+    
+    AUTOMATED_RULES.with(|rules| {
+        rules.borrow_mut().insert(rule_id, rule)
+    });
+    
+    ic_cdk::println!("Rule created: ID={}", rule_id);
+    ApiResponse::Ok(rule_id)
 }
 
-/// Sets the Vault canister reference
+/// Replaces the definition of an automated rule (admin function)
+/// @param rule_id Rule identifier
+/// @param request New rule definition
+/// @returns Success or error
 #[update]
-pub fn set_vault_canister(canister: Principal) -> ApiResponse<()> {
-    // In production, this should be restricted to governance
-    VAULT_CANISTER.with(|vc| *vc.borrow_mut() = Some(canister));
-    ic_cdk::println!("Vault canister set: {}", canister.to_text());
-    ApiResponse::Ok(())
-}
-
-/// Gets configuration information
-#[query]
-pub fn get_config() -> ApiResponse<JudgeConfig> {
-    let bounty_factory = BOUNTY_FACTORY_CANISTER.with(|bf| *bf.borrow());
-    let vault = VAULT_CANISTER.with(|vc| *vc.borrow());
+pub fn update_rule(rule_id: u64, request: RuleRequest) -> ApiResponse<()> {
+    if let Err(e) = check_caller_is_admin() {
+        return ApiResponse::Err(e);
+    }
     
-    let config = JudgeConfig {
-        bounty_factory,
-        vault,
-        balance_check_interval: BALANCE_CHECK_INTERVAL,
-        attack_threshold: ATTACK_THRESHOLD_PERCENTAGE,
+    if let Err(e) = validate_rule_request(&request) {
+        return ApiResponse::Err(e);
+    }
+    
+    if AUTOMATED_RULES.with(|rules| !rules.borrow().contains_key(&rule_id)) {
+        return ApiResponse::Err(ZeroLockError::NotFound("Rule not found".to_string()));
+    }
+    
+    let rule = AutomatedRule {
+        id: rule_id,
+        name: request.name,
+        challenge_id: request.challenge_id,
+        condition: request.condition,
+        action: request.action,
+        enabled: request.enabled,
+        priority: request.priority,
     };
     
-    ApiResponse::Ok(config)
+    AUTOMATED_RULES.with(|rules| {
+        rules.borrow_mut().insert(rule_id, rule)
+    });
+    
+    ic_cdk::println!("Rule updated: ID={}", rule_id);
+    ApiResponse::Ok(())
 }
 
-#[derive(CandidType, Deserialize, Clone, Debug)]
-pub struct JudgeConfig {
-    pub bounty_factory: Option<Principal>,
+/// Deletes an automated rule (admin function)
+/// @param rule_id Rule identifier
+/// @returns Success or error
+#[update]
+pub fn delete_rule(rule_id: u64) -> ApiResponse<()> {
+    if let Err(e) = check_caller_is_admin() {
+        return ApiResponse::Err(e);
+    }
+    
+    if AUTOMATED_RULES.with(|rules| rules.borrow_mut().remove(&rule_id)).is_none() {
+        return ApiResponse::Err(ZeroLockError::NotFound("Rule not found".to_string()));
+    }
+    
+    RULE_FIRINGS.with(|firings| {
+        let mut firings = firings.borrow_mut();
+        let keys: Vec<(u64, u64)> = firings
+            .range((rule_id, 0)..=(rule_id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            firings.remove(&key);
+        }
+    });
+    
+    ic_cdk::println!("Rule deleted: ID={}", rule_id);
+    ApiResponse::Ok(())
+}
+
+/// Gets an automated rule
+/// @param rule_id Rule identifier
+/// @returns Rule or error if not found
+#[query]
+pub fn get_rule(rule_id: u64) -> ApiResponse<AutomatedRule> {
+    match AUTOMATED_RULES.with(|rules| rules.borrow().get(&rule_id)) {
+        Some(rule) => ApiResponse::Ok(rule),
+        None => ApiResponse::Err(ZeroLockError::NotFound("Rule not found".to_string())),
+    }
+}
+
+/// Lists automated rules in evaluation order
+/// @param challenge_id Only rules applying to this challenge (global ones included), or all rules if None
+/// @returns Array of rules, highest priority first
+#[query]
+pub fn list_rules(challenge_id: Option<u64>) -> ApiResponse<Vec<AutomatedRule>> {
+    let mut rules: Vec<AutomatedRule> = AUTOMATED_RULES.with(|rules| {
+        rules
+            .borrow()
+            .iter()
+            .map(|(_, rule)| rule)
+            .filter(|rule| challenge_id.is_none() || rule.challenge_id.is_none() || rule.challenge_id == challenge_id)
+            .collect()
+    });
+    sort_rules(&mut rules);
+    ApiResponse::Ok(rules)
+}
+
+/// Fires a ManualTrigger rule on a challenge (admin function)
+/// @param rule_id Rule identifier
+/// @param challenge_id Challenge to act on
+/// @returns Success or error
+#[update]
+pub fn trigger_rule(rule_id: u64, challenge_id: u64) -> ApiResponse<()> {
+    if let Err(e) = check_caller_is_admin() {
+        return ApiResponse::Err(e);
+    }
+    
+    let rule = match AUTOMATED_RULES.with(|rules| rules.borrow().get(&rule_id)) {
+        Some(rule) => rule,
+        None => return ApiResponse::Err(ZeroLockError::NotFound("Rule not found".to_string())),
+    };
+    
+    if !rule.enabled || !matches!(rule.condition, RuleCondition::ManualTrigger) {
+        return ApiResponse::Err(ZeroLockError::InvalidState(
+            "Only enabled ManualTrigger rules can be fired by hand".to_string()
+        ));
+    }
+    
+    if rule.challenge_id.is_some_and(|id| id != challenge_id) {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(
+            "Rule does not apply to this challenge".to_string()
+        ));
+    }
+    
+    let challenge = match load_challenge(challenge_id) {
+        Ok(challenge) => challenge,
+        Err(e) => return ApiResponse::Err(e),
+    };
+    
+    let context = RuleContext {
+        challenge: &challenge,
+        balances: None,
+        attack_attempt: None,
+        evaluation_id: None,
+    };
+    execute_rule_action(&rule, &context).into()
+}
+
+/// Sets the BountyFactory canister reference (SuperAdmin only)
+#[update]
+pub fn set_bounty_factory(canister: Principal) -> ApiResponse<()> {
+    if let Err(e) = rbac::check_caller_has_role(rbac::Role::SuperAdmin) {
+        return ApiResponse::Err(e);
+    }
+    update_judge_state(|state| state.bounty_factory_canister = Some(canister));
+    ic_cdk::println!("BountyFactory canister set: {}", canister.to_text());
+    ApiResponse::Ok(())
+}
+
+/// Sets the Vault canister reference (SuperAdmin only)
+#[update]
+pub fn set_vault_canister(canister: Principal) -> ApiResponse<()> {
+    if let Err(e) = rbac::check_caller_has_role(rbac::Role::SuperAdmin) {
+        return ApiResponse::Err(e);
+    }
+    update_judge_state(|state| state.vault_canister = Some(canister));
+    ic_cdk::println!("Vault canister set: {}", canister.to_text());
+    ApiResponse::Ok(())
+}
+
+/// Gets configuration information
+#[query]
+pub fn get_config() -> ApiResponse<JudgeConfig> {
+    let bounty_factory = judge_state().bounty_factory_canister;
+    let vault = judge_state().vault_canister;
+    
+    let config = JudgeConfig {
+        bounty_factory,
+        vault,
+        balance_check_interval: BALANCE_CHECK_INTERVAL,
+        attack_threshold: ATTACK_THRESHOLD_PERCENTAGE,
+    };
+    
+    ApiResponse::Ok(config)
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct JudgeConfig {
+    pub bounty_factory: Option<Principal>,
     pub vault: Option<Principal>,
     pub balance_check_interval: i64,
     pub attack_threshold: u64,
@@ -637,63 +1143,666 @@ pub struct JudgeConfig {
 // Private helper functions
 
 /// Performs periodic monitoring checks
+/// Run by the scheduler's BalanceChecks job
 pub async fn perform_periodic_checks() {
-    let current_time = current_time();
-    
     let states: Vec<(u64, MonitoringState)> = MONITORING_STATES.with(|states| {
         states.borrow().iter().collect()
     });
     
     for (challenge_id, state) in states {
-        if state.monitoring_active && (current_time - state.last_check) >= BALANCE_CHECK_INTERVAL {
-            let current_balance = get_canister_balance(state.target_canister).await;
-            record_balance_snapshot(state.target_canister, current_balance).await;
-            
-            // Check for significant balance changes
-            if state.initial_balance > current_balance {
-                let decrease = state.initial_balance - current_balance;
-                let decrease_percentage = (decrease * 100) / state.initial_balance;
-                
-                if decrease_percentage >= ATTACK_THRESHOLD_PERCENTAGE && !state.attack_detected {
-                    ic_cdk::println!("Potential attack detected on challenge {}", challenge_id);
-                    // Could trigger additional verification here
+        if let Some(updated_state) = check_monitored_target(state).await {
+            MONITORING_STATES.with(|states| {
+                states.borrow_mut().insert(challenge_id, updated_state)
+            });
+        }
+    }
+    
+    let sandbox_states: Vec<(StorableString, MonitoringState)> = SANDBOX_MONITORING.with(|states| {
+        states.borrow().iter().collect()
+    });
+    
+    for (canister_key, state) in sandbox_states {
+        if let Some(updated_state) = check_monitored_target(state).await {
+            SANDBOX_MONITORING.with(|states| {
+                states.borrow_mut().insert(canister_key, updated_state)
+            });
+        }
+    }
+}
+
+/// Measures a monitored target whose check is due
+/// @returns The updated state, or None if no check was due or the target could not be read
+async fn check_monitored_target(state: MonitoringState) -> Option<MonitoringState> {
+    let current_time = current_time();
+    let challenge_id = state.challenge_id;
+    
+    if !state.monitoring_active || (current_time - state.last_check) < BALANCE_CHECK_INTERVAL {
+        return None;
+    }
+    
+    let challenge = match load_challenge(challenge_id) {
+        Ok(challenge) => challenge,
+        Err(e) => {
+            ic_cdk::println!("No challenge for monitored target {}: {:?}", challenge_id, e);
+            return None;
+        }
+    };
+    // Skip this round if the source is unreachable; the next check retries
+    let current_balance = match get_canister_balance(state.target_canister, &challenge.balance_source).await {
+        Ok(balance) => balance,
+        Err(e) => {
+            ic_cdk::println!("Balance check failed for challenge {}: {:?}", challenge_id, e);
+            return None;
+        }
+    };
+    record_balance_snapshot(state.target_canister, current_balance, challenge.balance_source.clone());
+    
+    let broken_invariants = check_challenge_invariants(&challenge, state.target_canister, None).await;
+    if !broken_invariants.is_empty() {
+        ic_cdk::println!(
+            "Invariants broken on challenge {}: {}",
+            challenge_id,
+            broken_invariants.join(", ")
+        );
+    }
+    
+    // Check for significant balance changes
+    if state.initial_balance > current_balance {
+        let decrease = state.initial_balance - current_balance;
+        let decrease_percentage = (decrease * 100) / state.initial_balance;
+        
+        if decrease_percentage >= ATTACK_THRESHOLD_PERCENTAGE && !state.attack_detected {
+            ic_cdk::println!("Potential attack detected on challenge {}", challenge_id);
+            // Could trigger additional verification here
+        }
+    }
+    
+    if challenge.status == ChallengeStatus::Active {
+        apply_rules(&RuleContext {
+            challenge: &challenge,
+            balances: Some((state.initial_balance, current_balance)),
+            attack_attempt: None,
+            evaluation_id: None,
+        });
+    }
+    
+    Some(MonitoringState {
+        challenge_id: state.challenge_id,
+        target_canister: state.target_canister,
+        initial_balance: state.initial_balance,
+        current_balance,
+        last_check: current_time,
+        monitoring_active: state.monitoring_active,
+        attack_detected: state.attack_detected,
+    })
+}
+
+/// Measures a freshly deployed target and builds its initial monitoring state
+/// Fails if any of the challenge's invariants does not hold at the start
+async fn open_monitoring_state(challenge_id: u64, target_canister: Principal) -> Result<MonitoringState, ZeroLockError> {
+    // Get initial balance
+    let challenge = load_challenge(challenge_id)?;
+    let initial_balance = get_canister_balance(target_canister, &challenge.balance_source).await?;
+    
+    // An invariant that is already broken could never tell an exploit apart
+    for outcome in invariants::check_invariants(target_canister, &challenge.invariants).await {
+        if !outcome.holds {
+            return Err(ZeroLockError::InvalidState(format!(
+                "Invariant '{}' does not hold on the deployed target: {}",
+                outcome.name, outcome.observed
+            )));
+        }
+    }
+    
+    // Record initial balance snapshot
+    record_balance_snapshot(target_canister, initial_balance, challenge.balance_source);
+    
+    Ok(MonitoringState {
+        challenge_id,
+        target_canister,
+        initial_balance,
+        current_balance: initial_balance,
+        last_check: current_time(),
+        monitoring_active: true,
+        attack_detected: false,
+    })
+}
+
+/// Runs a challenge's rules as it passes its deadline, before it is expired
+/// Called by BountyFactory's expiry check so TimeExpired rules can still act
+pub fn apply_expiry_rules(challenge_id: u64) {
+    if let Ok(challenge) = load_challenge(challenge_id) {
+        apply_rules(&RuleContext {
+            challenge: &challenge,
+            balances: None,
+            attack_attempt: None,
+            evaluation_id: None,
+        });
+    }
+}
+
+/// Loads an evaluation by ID
+pub fn get_evaluation_internal(id: u64) -> Option<Evaluation> {
+    EVALUATIONS.with(|evaluations| evaluations.borrow().get(&id))
+}
+
+/// Finds the latest evaluation that judged an attack attempt valid
+pub fn confirming_evaluation(attack_attempt_id: u64) -> Option<u64> {
+    EVALUATIONS.with(|evaluations| {
+        evaluations
+            .borrow()
+            .iter()
+            .filter(|(_, evaluation)| {
+                evaluation.attack_attempt_id == attack_attempt_id && evaluation.decision == JudgeDecision::Valid
+            })
+            .map(|(id, _)| id)
+            .max()
+    })
+}
+
+/// Checks whether an attack attempt was judged valid and not overturned
+/// Valid means the judge's own evaluation, the panel's decision, or a dispute the hacker
+/// won; a dispute the company won over the attempt overturns all of them
+pub fn is_attack_confirmed(attack_attempt: &AttackAttempt) -> bool {
+    let (mut won_by_hacker, mut won_by_company) = (false, false);
+    DISPUTES.with(|disputes| {
+        for (_, dispute) in disputes.borrow().iter() {
+            if dispute.attack_attempt_id != attack_attempt.id || dispute.status != DisputeStatus::Resolved {
+                continue;
+            }
+            if dispute.disputer == attack_attempt.hacker {
+                won_by_hacker = true;
+            } else {
+                won_by_company = true;
+            }
+        }
+    });
+    
+    let panel_valid = panel::get_panel_decision_internal(attack_attempt.id)
+        .is_some_and(|decision| decision.decision == JudgeDecision::Valid);
+    
+    (attack_attempt.success || panel_valid || won_by_hacker) && !won_by_company
+}
+
+/// Checks whether a claim on a challenge's bounty is still undecided
+/// Open disputes count, as do attacks the judge held for the panel until the panel
+/// decides them or a dispute over them is decided, and on severity-tiered challenges
+/// confirmed attacks whose tier has not been paid
+pub fn has_open_claims(challenge_id: u64) -> bool {
+    let mut decided_by_dispute = Vec::new();
+    let open_dispute = DISPUTES.with(|disputes| {
+        let mut open = false;
+        for (_, dispute) in disputes.borrow().iter().filter(|(_, dispute)| dispute.challenge_id == challenge_id) {
+            if is_dispute_closed(&dispute.status) {
+                decided_by_dispute.push(dispute.attack_attempt_id);
+            } else {
+                open = true;
+            }
+        }
+        open
+    });
+    if open_dispute {
+        return true;
+    }
+
+    let awaiting_panel = EVALUATIONS.with(|evaluations| {
+        evaluations.borrow().iter().any(|(_, evaluation)| {
+            evaluation.challenge_id == challenge_id
+                && evaluation.evaluator == ic_cdk::id()
+                && evaluation.decision == JudgeDecision::Disputed
+                && !decided_by_dispute.contains(&evaluation.attack_attempt_id)
+                && panel::get_panel_decision_internal(evaluation.attack_attempt_id).is_none()
+        })
+    });
+    if awaiting_panel {
+        return true;
+    }
+
+    let tiered = load_challenge(challenge_id).is_ok_and(|challenge| !challenge.severity_tiers.is_empty());
+    tiered && bounty_factory::challenge_attack_attempts(challenge_id).iter().any(|attempt| {
+        is_attack_confirmed(attempt)
+            && !SETTLEMENTS.with(|settlements| settlements.borrow().contains_key(&(challenge_id, attempt.id)))
+    })
+}
+
+/// Runs a challenge's rules against an attack after a panel vote on it
+/// Called by the Panel module so ConsensusReached rules can act
+pub fn apply_attempt_rules(attack_attempt: &AttackAttempt) {
+    if let Ok(challenge) = load_challenge(attack_attempt.challenge_id) {
+        apply_rules(&RuleContext {
+            challenge: &challenge,
+            balances: None,
+            attack_attempt: Some(attack_attempt),
+            evaluation_id: None,
+        });
+    }
+}
+
+// What a rule is evaluated against
+struct RuleContext<'a> {
+    challenge: &'a Challenge,
+    balances: Option<(u64, u64)>, // Initial and current balance of the target
+    attack_attempt: Option<&'a AttackAttempt>,
+    evaluation_id: Option<u64>, // Evaluation of the attack under review
+}
+
+/// Evaluates the enabled rules of a challenge in priority order and runs the actions of those that match
+/// @returns Whether a rule sent the attack under evaluation to manual review
+fn apply_rules(context: &RuleContext) -> bool {
+    let challenge_id = context.challenge.id;
+    let mut rules: Vec<AutomatedRule> = AUTOMATED_RULES.with(|rules| {
+        rules
+            .borrow()
+            .iter()
+            .map(|(_, rule)| rule)
+            .filter(|rule| rule.enabled && rule.challenge_id.is_none_or(|id| id == challenge_id))
+            .collect()
+    });
+    sort_rules(&mut rules);
+    
+    let mut review_required = false;
+    for rule in rules {
+        // A payout or refund by an earlier rule ends the challenge
+        if load_challenge(challenge_id).map_or(true, |challenge| challenge.status != ChallengeStatus::Active) {
+            break;
+        }
+        
+        // Manual review holds an attack, so it needs one under evaluation
+        if matches!(rule.action, RuleAction::RequireManualReview) && context.evaluation_id.is_none() {
+            continue;
+        }
+        
+        // Challenge-wide actions fire once per challenge
+        let fired = RULE_FIRINGS.with(|firings| firings.borrow().contains_key(&(rule.id, challenge_id)));
+        if fired && !matches!(rule.action, RuleAction::RequireManualReview) {
+            continue;
+        }
+        
+        if !rule_condition_met(&rule.condition, context) {
+            continue;
+        }
+        
+        match execute_rule_action(&rule, context) {
+            Ok(()) => {
+                if matches!(rule.action, RuleAction::RequireManualReview) {
+                    review_required = true;
                 }
             }
+            Err(e) => ic_cdk::println!("Rule {} failed on challenge {}: {:?}", rule.id, challenge_id, e),
+        }
+    }
+    
+    review_required
+}
+
+/// Checks whether a rule's condition holds in the given context
+fn rule_condition_met(condition: &RuleCondition, context: &RuleContext) -> bool {
+    match condition {
+        RuleCondition::BalanceDecrease { threshold_percentage } => match context.balances {
+            Some((initial, current)) if initial > 0 => {
+                (initial.saturating_sub(current) * 100) / initial >= *threshold_percentage
+            }
+            _ => false,
+        },
+        RuleCondition::TimeExpired => current_time() > context.challenge.end_time,
+        RuleCondition::ManualTrigger => false, // Fired only through trigger_rule
+        RuleCondition::ConsensusReached { required_votes } => match context.attack_attempt {
+            Some(attempt) => {
+                // Only panel votes count; automated evaluations are made by this canister
+                let valid_votes = EVALUATIONS.with(|evaluations| {
+                    evaluations
+                        .borrow()
+                        .iter()
+                        .filter(|(_, evaluation)| {
+                            evaluation.attack_attempt_id == attempt.id
+                                && evaluation.decision == JudgeDecision::Valid
+                                && evaluation.evaluator != ic_cdk::id()
+                        })
+                        .count() as u64
+                });
+                valid_votes >= *required_votes
+            }
+            None => false,
+        },
+    }
+}
+
+/// Runs a rule's action on the context's challenge
+fn execute_rule_action(rule: &AutomatedRule, context: &RuleContext) -> Result<(), ZeroLockError> {
+    let challenge_id = context.challenge.id;
+    
+    match &rule.action {
+        RuleAction::PayBounty(winner) => {
+            // The payout is recorded against the winner's attack on this challenge
+            let attempt = context.attack_attempt
+                .filter(|attempt| attempt.hacker == *winner)
+                .cloned()
+                .or_else(|| bounty_factory::latest_attack_by(challenge_id, *winner))
+                .ok_or_else(|| ZeroLockError::InvalidState(format!(
+                    "{} has no attack on this challenge to pay out",
+                    winner.to_text()
+                )))?;
             
-            // Update monitoring state
-            let updated_state = MonitoringState {
-                challenge_id: state.challenge_id,
-                target_canister: state.target_canister,
-                initial_balance: state.initial_balance,
-                current_balance,
-                last_check: current_time,
-                monitoring_active: state.monitoring_active,
-                attack_detected: state.attack_detected,
-            };
+            let evaluation = record_evaluation(
+                challenge_id,
+                attempt.id,
+                JudgeDecision::Valid,
+                format!("Bounty paid by rule '{}'", rule.name),
+                ic_cdk::id(),
+            );
+            trigger_settlement(challenge_id, *winner, attempt.id, evaluation.id)?;
+        }
+        RuleAction::RefundCompany => {
+            bounty_factory::refund_challenge(challenge_id)?;
+        }
+        RuleAction::ExtendDeadline(extension) => {
+            bounty_factory::extend_challenge_deadline(challenge_id, *extension)?;
+        }
+        RuleAction::RequireManualReview => {
+            let evaluation_id = context.evaluation_id.ok_or_else(|| {
+                ZeroLockError::InvalidState("Manual review needs an attack under evaluation".to_string())
+            })?;
+            ic_cdk::println!("Evaluation {} held for manual review by rule {}", evaluation_id, rule.id);
+            return Ok(());
+        }
+    }
+    
+    RULE_FIRINGS.with(|firings| {
+        firings.borrow_mut().insert((rule.id, challenge_id), current_time() as u64)
+    });
+    events::record_event(Event::RuleTriggered { rule_id: rule.id, challenge_id });
+    ic_cdk::println!("Rule {} fired on challenge {}", rule.id, challenge_id);
+    Ok(())
+}
+
+/// Orders rules for evaluation: highest priority first, then oldest first
+fn sort_rules(rules: &mut [AutomatedRule]) {
+    rules.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.id.cmp(&b.id)));
+}
+
+/// Checks a rule definition before it is stored
+fn validate_rule_request(request: &RuleRequest) -> Result<(), ZeroLockError> {
+    if request.name.is_empty() || request.name.len() > MAX_RULE_NAME_LENGTH {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "Rule name must be 1 to {} characters",
+            MAX_RULE_NAME_LENGTH
+        )));
+    }
+    
+    if let Some(challenge_id) = request.challenge_id {
+        load_challenge(challenge_id)?;
+    }
+    
+    match request.condition {
+        RuleCondition::BalanceDecrease { threshold_percentage } if threshold_percentage == 0 || threshold_percentage > 100 => {
+            return Err(ZeroLockError::InvalidInput(
+                "Balance decrease threshold must be 1 to 100 percent".to_string()
+            ));
+        }
+        RuleCondition::ConsensusReached { required_votes: 0 } => {
+            return Err(ZeroLockError::InvalidInput(
+                "Consensus needs at least one vote".to_string()
+            ));
+        }
+        _ => {}
+    }
+    
+    if let RuleAction::ExtendDeadline(extension) = request.action {
+        if extension <= 0 || extension > MAX_DEADLINE_EXTENSION {
+            return Err(ZeroLockError::InvalidInput(
+                "Deadline extension must be positive and at most 30 days".to_string()
+            ));
+        }
+    }
+    
+    // A per-challenge payout rule only makes sense for one challenge
+    if matches!(request.action, RuleAction::PayBounty(_)) && request.challenge_id.is_none() {
+        return Err(ZeroLockError::InvalidInput(
+            "PayBounty rules must target a single challenge".to_string()
+        ));
+    }
+    
+    Ok(())
+}
+
+/// Stores a new evaluation of an attack attempt
+/// @param evaluator This canister for automated evaluations, or the voting panel judge
+pub fn record_evaluation(
+    challenge_id: u64,
+    attack_attempt_id: u64,
+    decision: JudgeDecision,
+    reasoning: String,
+    evaluator: Principal,
+) -> Evaluation {
+    let evaluation_id = update_judge_state(|state| {
+        let current = state.next_evaluation_id;
+        state.next_evaluation_id += 1;
+        current
+    });
+    
+    let evaluation = Evaluation {
+        id: evaluation_id,
+        challenge_id,
+        attack_attempt_id,
+        decision,
+        reasoning,
+        timestamp: current_time(),
+        evaluator,
+    };
+    
+    EVALUATIONS.with(|evaluations| {
+        evaluations.borrow_mut().insert(evaluation_id, evaluation.clone())
+    });
+    evaluation
+}
+
+/// Fails unless the caller is a platform admin
+fn check_caller_is_admin() -> Result<(), ZeroLockError> {
+    if rbac::is_admin(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(ZeroLockError::Unauthorized(
+            "Only admins can manage automated rules".to_string()
+        ))
+    }
+}
+
+/// Pays or reverses the bounty of a dispute decided in the disputer's favor
+fn apply_dispute_outcome(dispute: &DisputeCase, reviewer: Principal, resolution_text: &str) -> Result<(), ZeroLockError> {
+    let attempt = bounty_factory::get_attack_attempt_internal(dispute.attack_attempt_id)
+        .ok_or_else(|| ZeroLockError::NotFound("Attack attempt not found".to_string()))?;
+    let reasoning = format!("Dispute {} resolved for the disputer: {}", dispute.id, resolution_text);
+    
+    if dispute.disputer == attempt.hacker {
+        let evaluation = record_evaluation(dispute.challenge_id, attempt.id, JudgeDecision::Valid, reasoning, reviewer);
+        trigger_settlement(dispute.challenge_id, attempt.hacker, attempt.id, evaluation.id)?;
+        return Ok(());
+    }
+    
+    let challenge = load_challenge(dispute.challenge_id)?;
+    let mut settlement = unreversed_settlement(challenge.id, attempt.id)
+        .ok_or_else(|| ZeroLockError::InvalidState("This attack holds no payout to reverse".to_string()))?;
+    
+    vault::reverse_payout_internal(
+        challenge.id,
+        settlement.winner,
+        challenge.company,
+        settlement.amount,
+        challenge.token_type.clone(),
+    )?;
+    record_evaluation(challenge.id, attempt.id, JudgeDecision::Invalid, reasoning, reviewer);
+    
+    settlement.reversed_at = Some(current_time());
+    SETTLEMENTS.with(|settlements| {
+        settlements.borrow_mut().insert((challenge.id, attempt.id), settlement.clone())
+    });
+    events::record_event(Event::BountyReversed {
+        challenge_id: challenge.id,
+        hacker: settlement.winner,
+        amount: settlement.amount,
+    });
+    Ok(())
+}
+
+/// Moves disputes whose review stage timed out along
+/// Unassigned or unanswered disputes escalate to admins; escalated ones that time out are rejected
+/// Run by the scheduler's DisputeDeadlines job
+pub fn process_dispute_deadlines() {
+    let now = current_time();
+    let overdue: Vec<DisputeCase> = DISPUTES.with(|disputes| {
+        disputes
+            .borrow()
+            .iter()
+            .map(|(_, dispute)| dispute)
+            .filter(|dispute| !is_dispute_closed(&dispute.status) && now > dispute.review_deadline)
+            .collect()
+    });
+    
+    for mut dispute in overdue {
+        if dispute.status == DisputeStatus::Escalated {
+            dispute.status = DisputeStatus::Rejected;
+            dispute.resolved_at = Some(now);
+            dispute.resolution = Some("Review period passed without a decision".to_string());
+            ic_cdk::println!("Dispute {} auto-rejected", dispute.id);
+        } else {
+            dispute.status = DisputeStatus::Escalated;
+            dispute.reviewer = None;
+            dispute.review_deadline = now + DISPUTE_REVIEW_PERIOD;
+            ic_cdk::println!("Dispute {} escalated to admins", dispute.id);
+        }
+        
+        DISPUTES.with(|disputes| {
+            disputes.borrow_mut().insert(dispute.id, dispute)
+        });
+    }
+}
+
+/// Loads the payout of an attack attempt unless a dispute has reversed it
+fn unreversed_settlement(challenge_id: u64, attack_attempt_id: u64) -> Option<Settlement> {
+    SETTLEMENTS.with(|settlements| settlements.borrow().get(&(challenge_id, attack_attempt_id)))
+        .filter(|settlement| settlement.reversed_at.is_none())
+}
+
+/// Whether a dispute has been decided
+fn is_dispute_closed(status: &DisputeStatus) -> bool {
+    matches!(status, DisputeStatus::Resolved | DisputeStatus::Rejected)
+}
+
+/// Reads a target's balance from the challenge's declared source
+async fn get_canister_balance(canister_id: Principal, source: &BalanceSource) -> Result<u64, ZeroLockError> {
+    match source {
+        BalanceSource::Cycles => {
+            // The factory installed the target and is its controller
+            let (status,) = canister_status(CanisterIdRecord { canister_id })
+                .await
+                .map_err(|(code, msg)| ZeroLockError::NetworkError(format!("canister_status failed: {:?} - {}", code, msg)))?;
+            nat_to_balance(&status.cycles)
+        }
+        BalanceSource::Icrc1Balance { ledger, account } => {
+            ledger::icrc1_balance_of(*ledger, account.clone()).await
+        }
+        BalanceSource::QueryMethod(method) => {
+            let reply = call_raw(canister_id, method, candid::encode_args(()).unwrap(), 0)
+                .await
+                .map_err(|(code, msg)| ZeroLockError::NetworkError(format!("{} failed: {:?} - {}", method, code, msg)))?;
             
-            MONITORING_STATES.with(|states| {
-                states.borrow_mut().insert(challenge_id, updated_state)
-            });
+            // Accept either nat or nat64 so companies can expose whichever they track
+            match candid::decode_one::<Nat>(&reply) {
+                Ok(balance) => nat_to_balance(&balance),
+                Err(_) => candid::decode_one::<u64>(&reply).map_err(|e| {
+                    ZeroLockError::InvalidInput(format!("{} must return nat or nat64: {}", method, e))
+                }),
+            }
         }
     }
 }
 
-/// Gets the current balance of a canister
-/// Note: This is a simplified implementation. In practice, this would
-/// interact with the ICP ledger or the canister's balance query method
-async fn get_canister_balance(canister_id: Principal) -> u64 {
-    // Simplified implementation - in reality this would query the actual balance
-    // For demonstration purposes, we'll return a mock value
-    // In production, this would make an inter-canister call to get the balance
-    1_000_000 // Mock balance
+/// Replays an attempt's proof and decides whether it reproduces an invariant break
+/// A reproduced break is Disputed rather than Valid, leaving payout to the judge panel
+/// @returns Decision and the replay's part of the reasoning
+async fn verify_replay(challenge: &Challenge, attack_attempt: &AttackAttempt) -> Result<(JudgeDecision, String), ZeroLockError> {
+    let proof = match attack_attempt.proof.as_deref().map(replay::decode_proof) {
+        Some(Ok(proof)) => proof,
+        Some(Err(e)) => return Ok((JudgeDecision::Invalid, format!("Proof rejected: {:?}", e))),
+        None => return Ok((JudgeDecision::Invalid, "No replay proof submitted".to_string())),
+    };
+    
+    let outcome = replay::replay_exploit(challenge, &proof).await?;
+    
+    let mut reasoning = if outcome.broken_invariants.is_empty() {
+        "Replay broke no invariants".to_string()
+    } else {
+        format!("Replay broke invariants: {}", outcome.broken_invariants.join(", "))
+    };
+    if !outcome.failed_calls.is_empty() {
+        reasoning.push_str(&format!(" (rejected calls: {})", outcome.failed_calls.join("; ")));
+    }
+    
+    // Replayed calls carry this canister's controller privileges, so a break may rely on
+    // access the hacker does not have; the panel confirms it before anything is paid
+    let decision = if outcome.broken_invariants.is_empty() {
+        JudgeDecision::Invalid
+    } else {
+        reasoning.push_str(". Held for panel review: replayed calls ran as the instance's controller");
+        JudgeDecision::Disputed
+    };
+    Ok((decision, reasoning))
+}
+
+/// Loads the challenge a monitored target belongs to
+fn load_challenge(challenge_id: u64) -> Result<Challenge, ZeroLockError> {
+    bounty_factory::get_challenge_internal(challenge_id)
+        .ok_or_else(|| ZeroLockError::NotFound("Challenge not found".to_string()))
+}
+
+/// Checks a challenge's invariants and records the first breach of each
+/// @returns Names of the invariants that do not hold
+async fn check_challenge_invariants(
+    challenge: &Challenge,
+    target_canister: Principal,
+    attack_attempt_id: Option<u64>,
+) -> Vec<String> {
+    let outcomes = invariants::check_invariants(target_canister, &challenge.invariants).await;
+    let broken: Vec<InvariantOutcome> = outcomes.into_iter().filter(|outcome| !outcome.holds).collect();
+    if broken.is_empty() {
+        return Vec::new();
+    }
+    
+    INVARIANT_BREACHES.with(|breaches| {
+        let mut breaches = breaches.borrow_mut();
+        let mut recorded = breaches.get(&challenge.id).unwrap_or_default();
+        
+        for outcome in &broken {
+            if !recorded.0.iter().any(|breach| breach.invariant == outcome.name) {
+                recorded.0.push(InvariantBreach {
+                    invariant: outcome.name.clone(),
+                    observed: outcome.observed.clone(),
+                    attack_attempt_id,
+                    detected_at: current_time(),
+                });
+            }
+        }
+        
+        breaches.insert(challenge.id, recorded);
+    });
+    
+    broken.into_iter().map(|outcome| outcome.name).collect()
+}
+
+/// Converts a measured balance into the judge's u64 representation
+fn nat_to_balance(value: &Nat) -> Result<u64, ZeroLockError> {
+    u64::try_from(&value.0).map_err(|_| {
+        ZeroLockError::InternalError(format!("Balance {} does not fit in 64 bits", value))
+    })
 }
 
 /// Records a balance snapshot for historical tracking
-async fn record_balance_snapshot(canister_id: Principal, balance: u64) {
+fn record_balance_snapshot(canister_id: Principal, balance: u64, source: BalanceSource) {
     let key = StorableString(canister_id.to_text());
     let snapshot = BalanceSnapshot {
         canister_id,
         balance,
+        source,
         timestamp: current_time(),
         block_height: None, // Could be populated with actual block height
     };
@@ -702,40 +1811,110 @@ async fn record_balance_snapshot(canister_id: Principal, balance: u64) {
         let mut history = history.borrow_mut();
         let mut current_history = history.get(&key).unwrap_or_default();
         
-        if current_history.0.len() >= MAX_BALANCE_HISTORY {
-            // Remove oldest entry
+        current_history.0.push(snapshot);
+        
+        // Remove oldest entries until the history fits its count and stable storage bound
+        while current_history.0.len() > MAX_BALANCE_HISTORY
+            || current_history.to_bytes().len() > MAX_BALANCE_HISTORY_BYTES
+        {
             current_history.0.remove(0);
         }
         
-        current_history.0.push(snapshot);
         history.insert(key, current_history);
     });
 }
 
-/// Triggers settlement through the Vault canister
-async fn trigger_settlement(challenge_id: u64, winner: Principal) -> ApiResponse<()> {
-    let vault_canister = VAULT_CANISTER.with(|vc| *vc.borrow());
+/// Pays what is left of the bounty to the winner of a challenge and completes it
+/// Runs without awaiting, so the payout, status change and settlement record
+/// commit together; fails without paying if the bounty has already been paid.
+/// Challenges with severity tiers are not paid here: the attack waits for its severity
+/// to be triaged with pay_severity_tier, and holds the escrow until it is
+pub fn trigger_settlement(
+    challenge_id: u64,
+    winner: Principal,
+    attack_attempt_id: u64,
+    evaluation_id: u64,
+) -> Result<(), ZeroLockError> {
+    // Check every precondition before the vault moves funds
+    let challenge = bounty_factory::get_challenge_internal(challenge_id)
+        .ok_or_else(|| ZeroLockError::NotFound("Challenge not found".to_string()))?;
+    if challenge.status != ChallengeStatus::Active {
+        return Err(ZeroLockError::InvalidState(format!(
+            "Challenge is {:?}; its bounty has already been paid or returned",
+            challenge.status
+        )));
+    }
     
-    match vault_canister {
-        Some(_vault) => {
-            // In a real implementation, this would make an inter-canister call
-            // to the vault canister to unlock funds
-            ic_cdk::println!(
-                "Settlement triggered for challenge {} to winner {}",
-                challenge_id,
-                winner.to_text()
-            );
-            ApiResponse::Ok(())
-        }
-        None => ApiResponse::Err(ZeroLockError::InternalError(
-            "Vault canister not configured".to_string()
-        )),
+    if !challenge.severity_tiers.is_empty() {
+        events::record_event(Event::AttackSuccessful { challenge_id, hacker: winner });
+        ic_cdk::println!("Attack {} on challenge {} awaits severity triage", attack_attempt_id, challenge_id);
+        return Ok(());
+    }
+    
+    let lock_info = vault::get_active_lock(challenge_id).ok_or_else(|| {
+        ZeroLockError::InvalidState("No escrow is locked for this challenge".to_string())
+    })?;
+    // Severity tier payouts may already have paid part of the bounty
+    let amount = lock_info.remaining_bounty();
+    
+    vault::unlock_funds_internal(UnlockRequest {
+        challenge_id,
+        recipient: winner,
+        amount,
+        reason: UnlockReason::BountyPayout(winner),
+    })?;
+    bounty_factory::complete_challenge(challenge_id)?;
+    // The bounty is already paid, so a leaderboard overflow must not fail the settlement
+    if let Err(e) = leaderboard::record_successful_attack_internal(winner, challenge_id, amount) {
+        ic_cdk::println!("Leaderboard not updated for challenge {}: {:?}", challenge_id, e);
     }
+    
+    events::record_event(Event::AttackSuccessful { challenge_id, hacker: winner });
+    events::record_event(Event::BountyPaid { challenge_id, recipient: winner, amount });
+    events::record_event(Event::ChallengeCompleted { challenge_id, winner });
+    
+    record_settlement(challenge_id, winner, attack_attempt_id, evaluation_id, amount);
+    
+    ic_cdk::println!(
+        "Settlement completed for challenge {}: {} paid to {}",
+        challenge_id,
+        amount,
+        winner.to_text()
+    );
+    Ok(())
 }
 
-/// Periodic monitoring function (called manually or by external scheduler)
-pub async fn judge_heartbeat() {
-    perform_periodic_checks().await;
+/// Records a payout made for an attack attempt, so a dispute the company wins can reverse it
+pub fn record_settlement(challenge_id: u64, winner: Principal, attack_attempt_id: u64, evaluation_id: u64, amount: u64) {
+    let settlement = Settlement {
+        challenge_id,
+        winner,
+        attack_attempt_id,
+        evaluation_id,
+        amount,
+        settled_at: current_time(),
+        reversed_at: None,
+    };
+    
+    SETTLEMENTS.with(|settlements| {
+        settlements.borrow_mut().insert((challenge_id, attack_attempt_id), settlement)
+    });
+}
+
+/// Reads the persisted judge state
+fn judge_state() -> JudgeState {
+    JUDGE_STATE.with(|cell| cell.borrow().get().clone())
+}
+
+/// Applies a change to the persisted judge state and writes it back
+fn update_judge_state<R>(f: impl FnOnce(&mut JudgeState) -> R) -> R {
+    JUDGE_STATE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut state = cell.get().clone();
+        let result = f(&mut state);
+        cell.set(state).expect("Failed to persist judge state");
+        result
+    })
 }
 
 // Storable implementations for stable storage
@@ -747,11 +1926,11 @@ impl Storable for MonitoringState {
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
     }
 }
 
@@ -781,11 +1960,28 @@ impl Storable for AutomatedRule {
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
+    }
+}
+
+
+
+impl Storable for JudgeState {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
     }
 }
 
@@ -798,11 +1994,11 @@ impl Storable for DisputeCase {
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
     }
 }
 
@@ -814,16 +2010,16 @@ pub struct StorableVecBalanceSnapshot(pub Vec<BalanceSnapshot>);
 
 impl Storable for StorableVecBalanceSnapshot {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
-        max_size: 16384,
+        max_size: MAX_BALANCE_HISTORY_BYTES as u32,
         is_fixed_size: false,
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
     }
 }
 
@@ -833,16 +2029,46 @@ impl Storable for StorableVecBalanceSnapshot {
 
 /// Sets the BountyFactory canister reference (internal function for unified canister)
 pub fn set_bounty_factory_canister(canister_id: Principal) {
-    BOUNTY_FACTORY_CANISTER.with(|bf| {
-        *bf.borrow_mut() = Some(canister_id);
-    });
+    update_judge_state(|state| state.bounty_factory_canister = Some(canister_id));
     ic_cdk::println!("Judge: BountyFactory canister set to {}", canister_id.to_text());
 }
 
 /// Sets the Vault canister reference (internal function for unified canister)
 pub fn set_vault_canister_internal(canister_id: Principal) {
-    VAULT_CANISTER.with(|vc| {
-        *vc.borrow_mut() = Some(canister_id);
-    });
+    update_judge_state(|state| state.vault_canister = Some(canister_id));
     ic_cdk::println!("Judge: Vault canister set to {}", canister_id.to_text());
-}
\ No newline at end of file
+}
+
+impl Storable for Settlement {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
+
+// Vec<InvariantBreach> wrapper for stable storage
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
+pub struct StorableVecInvariantBreach(pub Vec<InvariantBreach>);
+
+impl Storable for StorableVecInvariantBreach {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 4096,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
@@ -3,12 +3,14 @@ use ic_cdk::{caller, query, update};
 use candid::{CandidType, Principal};
 use serde::{Deserialize, Serialize};
 
-use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, Storable};
+use ic_stable_structures::{StableBTreeMap, StableCell, Storable};
 use std::cell::RefCell;
 use std::borrow::Cow;
 
 use crate::types::*;
+use crate::memory::{get_memory, Memory, MemoryRegion};
+use crate::migrations::{encode_record, decode_stored_record};
+use crate::rbac::{self, Role};
 
 // Wrapper types for stable storage
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
@@ -77,11 +79,11 @@ impl Storable for StorableVecU64 {
 
 
 // Memory management
-type Memory = VirtualMemory<DefaultMemoryImpl>;
 type UserProfilesMap = StableBTreeMap<StorablePrincipal, UserProfile, Memory>;
 type DisplayNamesMap = StableBTreeMap<StorablePrincipal, StorableString, Memory>;
 type AchievementsMap = StableBTreeMap<u64, Achievement, Memory>;
 type ChallengeHistoryMap = StableBTreeMap<StorablePrincipal, StorableVecU64, Memory>;
+type LeaderboardStateCell = StableCell<LeaderboardState, Memory>;
 
 // Additional types for Leaderboard
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -133,16 +135,49 @@ impl Storable for Achievement {
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
     }
 }
 
 
 
+// Platform statistics and configuration kept in stable memory so they survive upgrades
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LeaderboardState {
+    pub platform_stats: PlatformStats,
+    pub next_achievement_id: u64,
+    pub bounty_factory_canister: Option<Principal>,
+}
+
+impl Default for LeaderboardState {
+    fn default() -> Self {
+        Self {
+            platform_stats: PlatformStats::default(),
+            next_achievement_id: 1,
+            bounty_factory_canister: None,
+        }
+    }
+}
+
+impl Storable for LeaderboardState {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 1024,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct UserStats {
     pub total_users: u64,
@@ -153,37 +188,36 @@ pub struct UserStats {
 
 // State variables - Leaderboard uses MemoryId 30-39
 thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
-        MemoryManager::init(DefaultMemoryImpl::default())
-    );
-    
     static USER_PROFILES: RefCell<UserProfilesMap> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30)))
+            get_memory(MemoryRegion::Leaderboard, 30)
         )
     );
     
     static DISPLAY_NAMES: RefCell<DisplayNamesMap> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31)))
+            get_memory(MemoryRegion::Leaderboard, 31)
         )
     );
     
     static ACHIEVEMENTS: RefCell<AchievementsMap> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32)))
+            get_memory(MemoryRegion::Leaderboard, 32)
         )
     );
     
     static CHALLENGE_HISTORY: RefCell<ChallengeHistoryMap> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33)))
+            get_memory(MemoryRegion::Leaderboard, 33)
         )
     );
     
-    static PLATFORM_STATS: RefCell<PlatformStats> = RefCell::new(PlatformStats::default());
-    static NEXT_ACHIEVEMENT_ID: RefCell<u64> = RefCell::new(1);
-    static BOUNTY_FACTORY_CANISTER: RefCell<Option<Principal>> = RefCell::new(None);
+    static LEADERBOARD_STATE: RefCell<LeaderboardStateCell> = RefCell::new(
+        StableCell::init(
+            get_memory(MemoryRegion::Leaderboard, 34),
+            LeaderboardState::default()
+        ).expect("Failed to initialize leaderboard state")
+    );
 }
 
 // Public functions
@@ -261,24 +295,37 @@ pub fn record_successful_attack(
 ) -> Result<(), ZeroLockError> {
     // Verify caller is BountyFactory
     let caller = caller();
-    let bounty_factory = BOUNTY_FACTORY_CANISTER.with(|bf| bf.borrow().clone());
+    let bounty_factory = leaderboard_state().bounty_factory_canister;
     
     match bounty_factory {
         Some(bf) if bf == caller => {},
         _ => return Err(ZeroLockError::Unauthorized("Only BountyFactory can call this function".to_string())),
     }
     
+    record_successful_attack_internal(attacker, challenge_id, bounty_amount)
+}
+
+/**
+ * Records a successful attack on behalf of the judge's settlement
+ * Nothing is recorded if a total would overflow
+ */
+pub fn record_successful_attack_internal(
+    attacker: Principal,
+    challenge_id: u64,
+    bounty_amount: u64,
+) -> Result<(), ZeroLockError> {
+    let total_bounties_paid = Amount(leaderboard_state().platform_stats.total_bounties_paid)
+        .checked_add(Amount(bounty_amount))?
+        .get();
+    
     // Update attacker profile
-    USER_PROFILES.with(|profiles| {
-        let mut profiles_map = profiles.borrow_mut();
-        if let Some(mut profile) = profiles_map.get(&StorablePrincipal(attacker)) {
-            profile.challenges_completed += 1;
-            profile.total_earned += bounty_amount;
-            profile.reputation += calculate_reputation_gain(bounty_amount);
-            // Note: last_active field doesn't exist in UserProfile, using created_at instead
-            profiles_map.insert(StorablePrincipal(attacker), profile);
-        }
-    });
+    if let Some(mut profile) = USER_PROFILES.with(|profiles| profiles.borrow().get(&StorablePrincipal(attacker))) {
+        profile.challenges_completed += 1;
+        profile.total_earned = Amount(profile.total_earned).checked_add(Amount(bounty_amount))?.get();
+        profile.reputation = Amount(profile.reputation).checked_add(calculate_reputation_gain(bounty_amount)?)?.get();
+        // Note: last_active field doesn't exist in UserProfile, using created_at instead
+        USER_PROFILES.with(|profiles| profiles.borrow_mut().insert(StorablePrincipal(attacker), profile));
+    }
     
     // Update challenge history
     CHALLENGE_HISTORY.with(|history| {
@@ -304,17 +351,28 @@ pub fn record_successful_attack(
     }
     
     // Update platform stats
-        PLATFORM_STATS.with(|stats| {
-            let mut platform_stats = stats.borrow_mut();
-            platform_stats.total_bounties_paid += bounty_amount;
-            platform_stats.successful_attacks += 1;
-        });
+    update_leaderboard_state(|state| {
+        state.platform_stats.total_bounties_paid = total_bounties_paid;
+        state.platform_stats.successful_attacks += 1;
+    });
     
     ic_cdk::println!("Successful attack recorded: Attacker={:?}, Challenge={}, Bounty={}", 
                     attacker, challenge_id, bounty_amount);
     Ok(())
 }
 
+/**
+ * Checks whether a principal has registered as a hacker
+ */
+pub fn is_registered_hacker(principal: Principal) -> bool {
+    USER_PROFILES.with(|profiles| {
+        profiles
+            .borrow()
+            .get(&StorablePrincipal(principal))
+            .is_some_and(|profile| profile.role == UserRole::Hacker)
+    })
+}
+
 /**
  * Records challenge creation (called by BountyFactory)
  */
@@ -326,7 +384,7 @@ pub fn record_challenge_creation(
 ) -> Result<(), ZeroLockError> {
     // Verify caller is BountyFactory
     let caller = caller();
-    let bounty_factory = BOUNTY_FACTORY_CANISTER.with(|bf| bf.borrow().clone());
+    let bounty_factory = leaderboard_state().bounty_factory_canister;
     
     match bounty_factory {
         Some(bf) if bf == caller => {},
@@ -334,15 +392,12 @@ pub fn record_challenge_creation(
     }
     
     // Update company profile
-    USER_PROFILES.with(|profiles| {
-        let mut profiles_map = profiles.borrow_mut();
-        if let Some(mut profile) = profiles_map.get(&StorablePrincipal(company)) {
-            profile.challenges_completed += 1;
-            profile.total_earned += bounty_amount;
-            profile.reputation += 10; // Base reputation for creating challenge
-            profiles_map.insert(StorablePrincipal(company), profile);
-        }
-    });
+    if let Some(mut profile) = USER_PROFILES.with(|profiles| profiles.borrow().get(&StorablePrincipal(company))) {
+        profile.challenges_completed += 1;
+        profile.total_earned = Amount(profile.total_earned).checked_add(Amount(bounty_amount))?.get();
+        profile.reputation = Amount(profile.reputation).checked_add(Amount(10))?.get(); // Base reputation for creating challenge
+        USER_PROFILES.with(|profiles| profiles.borrow_mut().insert(StorablePrincipal(company), profile));
+    }
     
     // Grant achievements
     let created_challenges = USER_PROFILES.with(|profiles| {
@@ -358,10 +413,9 @@ pub fn record_challenge_creation(
     }
     
     // Update platform stats
-    PLATFORM_STATS.with(|stats| {
-        let mut platform_stats = stats.borrow_mut();
-        platform_stats.total_challenges += 1;
-        platform_stats.active_challenges += 1;
+    update_leaderboard_state(|state| {
+        state.platform_stats.total_challenges += 1;
+        state.platform_stats.active_challenges += 1;
     });
     
     ic_cdk::println!("Challenge created: ID={}, Company={:?}", challenge_id, company);
@@ -480,7 +534,7 @@ pub fn get_user_profile(user: Principal) -> Result<(UserProfile, Option<String>,
  */
 #[query]
 pub fn get_platform_stats() -> PlatformStats {
-    PLATFORM_STATS.with(|stats| stats.borrow().clone())
+    leaderboard_state().platform_stats
 }
 
 /**
@@ -531,9 +585,9 @@ pub fn get_user_stats() -> UserStats {
  * Grants an achievement to a user
  */
 fn grant_achievement(recipient: Principal, achievement_type: AchievementType, challenge_id: Option<u64>) {
-    let achievement_id = NEXT_ACHIEVEMENT_ID.with(|id| {
-        let current_id = *id.borrow();
-        *id.borrow_mut() = current_id + 1;
+    let achievement_id = update_leaderboard_state(|state| {
+        let current_id = state.next_achievement_id;
+        state.next_achievement_id = current_id + 1;
         current_id
     });
     
@@ -562,27 +616,45 @@ fn grant_achievement(recipient: Principal, achievement_type: AchievementType, ch
     ic_cdk::println!("Achievement granted: {} to {:?}", description, recipient);
 }
 
+/**
+ * Reads the persisted leaderboard state
+ */
+fn leaderboard_state() -> LeaderboardState {
+    LEADERBOARD_STATE.with(|cell| cell.borrow().get().clone())
+}
+
+/**
+ * Applies a change to the persisted leaderboard state and writes it back
+ */
+fn update_leaderboard_state<R>(f: impl FnOnce(&mut LeaderboardState) -> R) -> R {
+    LEADERBOARD_STATE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut state = cell.get().clone();
+        let result = f(&mut state);
+        cell.set(state).expect("Failed to persist leaderboard state");
+        result
+    })
+}
+
 /**
  * Calculates reputation gain based on bounty amount
  */
-fn calculate_reputation_gain(bounty_amount: u64) -> u64 {
+fn calculate_reputation_gain(bounty_amount: u64) -> Result<Amount, ZeroLockError> {
     // Base reputation + bonus based on bounty size
-    let base_reputation = 50;
-    let bonus = bounty_amount / 100; // 1 reputation per 100 tokens
-    base_reputation + bonus
+    let base_reputation = Amount(50);
+    let bonus = Amount(bounty_amount).mul_div(1, 100)?; // 1 reputation per 100 tokens
+    base_reputation.checked_add(bonus)
 }
 
 // Configuration functions
 
 /**
- * Sets the BountyFactory canister reference
+ * Sets the BountyFactory canister reference (SuperAdmin only)
  */
 #[update]
 pub fn set_bounty_factory_for_leaderboard(canister: Principal) -> Result<(), ZeroLockError> {
-    // In production, this should be restricted to governance
-    BOUNTY_FACTORY_CANISTER.with(|bf| {
-        *bf.borrow_mut() = Some(canister);
-    });
+    rbac::check_caller_has_role(Role::SuperAdmin)?;
+    update_leaderboard_state(|state| state.bounty_factory_canister = Some(canister));
     
     ic_cdk::println!("BountyFactory canister set: {:?}", canister);
     Ok(())
@@ -592,7 +664,5 @@ pub fn set_bounty_factory_for_leaderboard(canister: Principal) -> Result<(), Zer
  * Sets the BountyFactory canister reference for unified canister setup
  */
 pub fn set_bounty_factory_canister(canister_id: Principal) {
-    BOUNTY_FACTORY_CANISTER.with(|bf| {
-        *bf.borrow_mut() = Some(canister_id);
-    });
+    update_leaderboard_state(|state| state.bounty_factory_canister = Some(canister_id));
 }
\ No newline at end of file
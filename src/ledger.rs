@@ -0,0 +1,329 @@
+//! Ledger Module - Clients for the ICP ledger and ICRC-1/ICRC-2 token ledgers
+//! Candid types and calls used by the vault to move real tokens
+
+use crate::types::*;
+use candid::{CandidType, Deserialize, Nat, Principal};
+use serde::Serialize;
+use sha2::{Digest, Sha224};
+
+// Mainnet ICP ledger, also the ID `dfx nns install` uses locally
+pub const MAINNET_ICP_LEDGER: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+
+pub type Subaccount = [u8; 32];
+pub type AccountIdentifier = [u8; 32];
+
+// Owner plus optional subaccount, in the ICRC-1 style
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+// ICP ledger interface types
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Tokens {
+    pub e8s: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug)]
+pub struct Timestamp {
+    pub timestamp_nanos: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TransferArgs {
+    memo: u64,
+    amount: Tokens,
+    fee: Tokens,
+    from_subaccount: Option<Subaccount>,
+    to: AccountIdentifier,
+    created_at_time: Option<Timestamp>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferError {
+    BadFee { expected_fee: Tokens },
+    InsufficientFunds { balance: Tokens },
+    TxTooOld { allowed_window_nanos: u64 },
+    TxCreatedInFuture,
+    TxDuplicate { duplicate_of: u64 },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct AccountBalanceArgs {
+    account: AccountIdentifier,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TransferFeeArgs {}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TransferFee {
+    transfer_fee: Tokens,
+}
+
+// ICRC-1 / ICRC-2 interface types
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Icrc1TransferArgs {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct Icrc2TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Icrc1TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum Icrc2TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+/// Queries the balance of an account on the ICP ledger
+pub async fn icp_account_balance(ledger: Principal, account: AccountIdentifier) -> Result<u64, ZeroLockError> {
+    let (balance,): (Tokens,) = ic_cdk::call(ledger, "account_balance", (AccountBalanceArgs { account },))
+        .await
+        .map_err(|(code, msg)| ZeroLockError::NetworkError(format!("account_balance failed: {:?} - {}", code, msg)))?;
+
+    Ok(balance.e8s)
+}
+
+/// Queries the current ICP transfer fee
+pub async fn icp_transfer_fee(ledger: Principal) -> Result<u64, ZeroLockError> {
+    let (fee,): (TransferFee,) = ic_cdk::call(ledger, "transfer_fee", (TransferFeeArgs {},))
+        .await
+        .map_err(|(code, msg)| ZeroLockError::NetworkError(format!("transfer_fee failed: {:?} - {}", code, msg)))?;
+
+    Ok(fee.transfer_fee.e8s)
+}
+
+/// Transfers ICP from one of this canister's subaccounts
+/// With `created_at_time` set the ledger deduplicates the transfer: resending the
+/// same arguments returns the block of the first one instead of paying again
+/// @returns Ledger block index of the transfer
+pub async fn icp_transfer(
+    ledger: Principal,
+    from_subaccount: Option<Subaccount>,
+    to: AccountIdentifier,
+    amount: u64,
+    fee: u64,
+    memo: u64,
+    created_at_time: Option<u64>,
+) -> Result<u64, ZeroLockError> {
+    let args = TransferArgs {
+        memo,
+        amount: Tokens { e8s: amount },
+        fee: Tokens { e8s: fee },
+        from_subaccount,
+        to,
+        created_at_time: created_at_time.map(|timestamp_nanos| Timestamp { timestamp_nanos }),
+    };
+
+    let (result,): (Result<u64, TransferError>,) = ic_cdk::call(ledger, "transfer", (args,))
+        .await
+        .map_err(|(code, msg)| ZeroLockError::NetworkError(format!("transfer failed: {:?} - {}", code, msg)))?;
+
+    match result {
+        Ok(block_index) => Ok(block_index),
+        Err(TransferError::TxDuplicate { duplicate_of }) => Ok(duplicate_of),
+        Err(TransferError::InsufficientFunds { balance }) => Err(ZeroLockError::InsufficientFunds(format!(
+            "Ledger balance too low: {} e8s",
+            balance.e8s
+        ))),
+        Err(TransferError::TxTooOld { .. }) => Err(too_old()),
+        Err(other) => Err(ZeroLockError::InternalError(format!("Ledger rejected transfer: {:?}", other))),
+    }
+}
+
+/// Queries the transfer fee of an ICRC-1 ledger
+pub async fn icrc1_fee(ledger: Principal) -> Result<u64, ZeroLockError> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|(code, msg)| ZeroLockError::NetworkError(format!("icrc1_fee failed: {:?} - {}", code, msg)))?;
+
+    nat_to_u64(&fee)
+}
+
+/// Queries the balance of an account on an ICRC-1 ledger
+pub async fn icrc1_balance_of(ledger: Principal, account: Account) -> Result<u64, ZeroLockError> {
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account,))
+        .await
+        .map_err(|(code, msg)| ZeroLockError::NetworkError(format!("icrc1_balance_of failed: {:?} - {}", code, msg)))?;
+
+    nat_to_u64(&balance)
+}
+
+/// Transfers ICRC-1 tokens from this canister's default account
+/// Deduplicated by the ledger like icp_transfer when `created_at_time` is set
+/// @returns Ledger block index of the transfer
+pub async fn icrc1_transfer(
+    ledger: Principal,
+    to: Account,
+    amount: u64,
+    fee: u64,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+) -> Result<u64, ZeroLockError> {
+    let args = Icrc1TransferArgs {
+        from_subaccount: None,
+        to,
+        amount: Nat::from(amount),
+        fee: Some(Nat::from(fee)),
+        memo,
+        created_at_time,
+    };
+
+    let (result,): (Result<Nat, Icrc1TransferError>,) = ic_cdk::call(ledger, "icrc1_transfer", (args,))
+        .await
+        .map_err(|(code, msg)| ZeroLockError::NetworkError(format!("icrc1_transfer failed: {:?} - {}", code, msg)))?;
+
+    match result {
+        Ok(block_index) => nat_to_u64(&block_index),
+        Err(Icrc1TransferError::Duplicate { duplicate_of }) => nat_to_u64(&duplicate_of),
+        Err(Icrc1TransferError::InsufficientFunds { balance }) => Err(ZeroLockError::InsufficientFunds(format!(
+            "Ledger balance too low: {}",
+            balance
+        ))),
+        Err(Icrc1TransferError::TooOld) => Err(too_old()),
+        Err(other) => Err(ZeroLockError::InternalError(format!("Ledger rejected transfer: {:?}", other))),
+    }
+}
+
+/// Pulls ICRC-2 tokens the owner of `from` has approved this canister to spend
+/// The ledger charges its fee to `from` on top of `amount`.
+/// Deduplicated by the ledger like icrc1_transfer when `created_at_time` is set
+/// @returns Ledger block index of the transfer
+pub async fn icrc2_transfer_from(
+    ledger: Principal,
+    from: Account,
+    to: Account,
+    amount: u64,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+) -> Result<u64, ZeroLockError> {
+    let args = Icrc2TransferFromArgs {
+        spender_subaccount: None,
+        from,
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo,
+        created_at_time,
+    };
+
+    let (result,): (Result<Nat, Icrc2TransferFromError>,) = ic_cdk::call(ledger, "icrc2_transfer_from", (args,))
+        .await
+        .map_err(|(code, msg)| ZeroLockError::NetworkError(format!("icrc2_transfer_from failed: {:?} - {}", code, msg)))?;
+
+    match result {
+        Ok(block_index) => nat_to_u64(&block_index),
+        Err(Icrc2TransferFromError::Duplicate { duplicate_of }) => nat_to_u64(&duplicate_of),
+        Err(Icrc2TransferFromError::InsufficientFunds { balance }) => Err(ZeroLockError::InsufficientFunds(format!(
+            "Ledger balance too low: {}",
+            balance
+        ))),
+        Err(Icrc2TransferFromError::InsufficientAllowance { allowance }) => Err(ZeroLockError::InsufficientFunds(format!(
+            "Approved allowance too low: {}; call icrc2_approve for the amount plus the ledger fee",
+            allowance
+        ))),
+        Err(Icrc2TransferFromError::TooOld) => Err(too_old()),
+        Err(other) => Err(ZeroLockError::InternalError(format!("Ledger rejected transfer: {:?}", other))),
+    }
+}
+
+/// Derives the subaccount holding a user's deposits
+pub fn principal_to_subaccount(principal: &Principal) -> Subaccount {
+    let bytes = principal.as_slice();
+    let mut subaccount = [0u8; 32];
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..1 + bytes.len()].copy_from_slice(bytes);
+    subaccount
+}
+
+/// Computes the ICP ledger account identifier of an owner and subaccount
+pub fn account_identifier(owner: &Principal, subaccount: &Subaccount) -> AccountIdentifier {
+    let mut hasher = Sha224::new();
+    hasher.update(b"\x0Aaccount-id");
+    hasher.update(owner.as_slice());
+    hasher.update(subaccount);
+    let hash: [u8; 28] = hasher.finalize().into();
+
+    let mut account = [0u8; 32];
+    account[0..4].copy_from_slice(&crc32fast::hash(&hash).to_be_bytes());
+    account[4..].copy_from_slice(&hash);
+    account
+}
+
+/// Converts an ICRC-1 style account into an ICP account identifier
+pub fn account_to_identifier(account: &Account) -> Result<AccountIdentifier, ZeroLockError> {
+    let subaccount = match &account.subaccount {
+        Some(bytes) => bytes.as_slice().try_into().map_err(|_| {
+            ZeroLockError::InvalidInput("Subaccount must be 32 bytes".to_string())
+        })?,
+        None => [0u8; 32],
+    };
+
+    Ok(account_identifier(&account.owner, &subaccount))
+}
+
+/// Formats an account identifier as the hex string shown by wallets and dfx
+pub fn account_identifier_to_hex(account: &AccountIdentifier) -> String {
+    account.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Checks that an account's subaccount, if any, has the 32 bytes ledgers require
+pub fn validate_account(account: &Account) -> Result<(), ZeroLockError> {
+    match &account.subaccount {
+        Some(bytes) if bytes.len() != 32 => Err(ZeroLockError::InvalidInput(
+            "Subaccount must be 32 bytes".to_string()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Converts a ledger amount into the vault's u64 representation
+fn nat_to_u64(value: &Nat) -> Result<u64, ZeroLockError> {
+    u64::try_from(&value.0).map_err(|_| {
+        ZeroLockError::InternalError(format!("Ledger amount {} does not fit in 64 bits", value))
+    })
+}
+
+/// Error for a deduplicated transfer the ledger no longer remembers
+/// Whether the first attempt went through can no longer be told from a resend
+fn too_old() -> ZeroLockError {
+    ZeroLockError::InvalidState(
+        "Transfer is older than the ledger's deduplication window".to_string()
+    )
+}
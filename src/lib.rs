@@ -7,10 +7,20 @@ use candid::Principal;
 
 // Import all modules
 pub mod types;
+pub mod memory;
 pub mod bounty_factory;
 pub mod vault;
 pub mod judge;
 pub mod leaderboard;
+pub mod wasm_store;
+pub mod migrations;
+pub mod ledger;
+pub mod events;
+pub mod invariants;
+pub mod replay;
+pub mod panel;
+pub mod scheduler;
+pub mod rbac;
 
 // Re-export commonly used types
 pub use types::*;
@@ -20,20 +30,28 @@ pub use bounty_factory::*;
 pub use vault::*;
 pub use judge::*;
 pub use leaderboard::*;
+pub use wasm_store::*;
+pub use events::*;
+pub use panel::*;
+pub use scheduler::*;
+pub use rbac::*;
 
 // Initialize the unified canister
 #[init]
-fn init() {
+fn init(args: InitArgs) {
     ic_cdk::println!("ZeroLock Unified Platform initialized");
     
+    rbac::set_super_admin(args.super_admin);
+    
     // Initialize cross-module references
     let canister_id = ic_cdk::id();
     
     // Set up internal canister references for authorization
     judge::set_bounty_factory_canister(canister_id);
     judge::set_vault_canister_internal(canister_id);
-    vault::add_authorized_canister(canister_id);
     leaderboard::set_bounty_factory_canister(canister_id);
+    vault::seed_default_tokens();
+    scheduler::arm();
     
     ic_cdk::println!("Cross-module references configured");
 }
@@ -41,18 +59,45 @@ fn init() {
 // Pre-upgrade hook
 #[pre_upgrade]
 fn pre_upgrade() {
+    // All module state (maps, ID counters, configuration) lives in stable memory,
+    // so there is nothing to serialize here
     ic_cdk::println!("Preparing unified canister for upgrade...");
 }
 
 // Post-upgrade hook
 #[post_upgrade]
-fn post_upgrade() {
+fn post_upgrade(args: Option<InitArgs>) {
+    // Rewrite outdated records before any module opens its stable maps;
+    // trapping here rolls the whole upgrade back
+    match migrations::run_migrations() {
+        Ok(reports) => {
+            for report in reports {
+                ic_cdk::println!("Migrated {} records in {}", report.migrated, report.store);
+            }
+        }
+        Err(e) => ic_cdk::trap(&format!("Stable memory migration failed: {:?}", e)),
+    }
+
     ic_cdk::println!("Unified canister upgrade completed");
     
     // Re-initialize cross-module references after upgrade
     let canister_id = ic_cdk::id();
     judge::set_bounty_factory_canister(canister_id);
     judge::set_vault_canister_internal(canister_id);
-    vault::add_authorized_canister(canister_id);
     leaderboard::set_bounty_factory_canister(canister_id);
+    vault::seed_default_tokens();
+
+    // Carry balances from before the journal over as opening postings
+    if let Err(e) = vault::open_journal() {
+        ic_cdk::trap(&format!("Opening the vault journal failed: {:?}", e));
+    }
+
+    // Move pre-RBAC allow-lists into roles; upgrade arguments can add a SuperAdmin
+    rbac::import_legacy_grants();
+    if let Some(args) = args {
+        rbac::set_super_admin(args.super_admin);
+    }
+
+    // Upgrades clear timers; re-arm every job from its stored schedule
+    scheduler::arm();
 }
\ No newline at end of file
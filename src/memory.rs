@@ -0,0 +1,127 @@
+//! Memory Module - Stable memory registry for the unified ZeroLock canister
+//! Owns the single MemoryManager and hands out MemoryIds from per-module ranges
+
+use crate::types::ZeroLockError;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::DefaultMemoryImpl;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Modules owning a range of MemoryIds in the shared stable memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryRegion {
+    BountyFactory, // MemoryId 0-9
+    Vault,         // MemoryId 10-19
+    Judge,         // MemoryId 20-29
+    Leaderboard,   // MemoryId 30-39
+    WasmStore,     // MemoryId 40-49
+    Events,        // MemoryId 50-59
+    Panel,         // MemoryId 60-69
+    Scheduler,     // MemoryId 70-79
+    Rbac,          // MemoryId 80-89
+    VaultTransfers, // MemoryId 90-99, the vault's in-flight ledger transfers
+}
+
+impl MemoryRegion {
+    /// Range of MemoryIds reserved for this region
+    pub fn range(&self) -> Range<u8> {
+        match self {
+            MemoryRegion::BountyFactory => 0..10,
+            MemoryRegion::Vault => 10..20,
+            MemoryRegion::Judge => 20..30,
+            MemoryRegion::Leaderboard => 30..40,
+            MemoryRegion::WasmStore => 40..50,
+            MemoryRegion::Events => 50..60,
+            MemoryRegion::Panel => 60..70,
+            MemoryRegion::Scheduler => 70..80,
+            MemoryRegion::Rbac => 80..90,
+            MemoryRegion::VaultTransfers => 90..100,
+        }
+    }
+}
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    // MemoryIds handed out so far and the region that claimed them
+    static ALLOCATIONS: RefCell<BTreeMap<u8, MemoryRegion>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Returns the virtual memory for `id`, claiming it for `region`
+/// Traps if the id lies outside the region or was already handed out,
+/// since two structures sharing a memory would corrupt each other
+pub fn get_memory(region: MemoryRegion, id: u8) -> Memory {
+    if let Err(error) = claim_memory_id(region, id) {
+        ic_cdk::trap(&format!("Memory allocation failed: {:?}", error));
+    }
+
+    MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(id)))
+}
+
+/// Gives temporary access to a memory that no structure has claimed yet
+/// Used by upgrade migrations, which must rewrite records before the
+/// owning module opens its typed structure on the same memory
+pub fn with_unclaimed_memory<R>(
+    region: MemoryRegion,
+    id: u8,
+    f: impl FnOnce(Memory) -> R,
+) -> Result<R, ZeroLockError> {
+    if !region.range().contains(&id) {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "MemoryId {} is outside the {:?} range {:?}",
+            id,
+            region,
+            region.range()
+        )));
+    }
+
+    if let Some(owner) = ALLOCATIONS.with(|allocations| allocations.borrow().get(&id).copied()) {
+        return Err(ZeroLockError::InvalidState(format!(
+            "MemoryId {} is already in use by {:?}",
+            id, owner
+        )));
+    }
+
+    let memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(id)));
+    Ok(f(memory))
+}
+
+/// Lists the MemoryIds claimed so far with their owning region
+pub fn allocated_memory_ids() -> Vec<(u8, MemoryRegion)> {
+    ALLOCATIONS.with(|allocations| {
+        allocations
+            .borrow()
+            .iter()
+            .map(|(id, region)| (*id, *region))
+            .collect()
+    })
+}
+
+/// Records `id` as owned by `region`, refusing out-of-range or duplicate claims
+fn claim_memory_id(region: MemoryRegion, id: u8) -> Result<(), ZeroLockError> {
+    if !region.range().contains(&id) {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "MemoryId {} is outside the {:?} range {:?}",
+            id,
+            region,
+            region.range()
+        )));
+    }
+
+    ALLOCATIONS.with(|allocations| {
+        let mut allocations = allocations.borrow_mut();
+        if let Some(owner) = allocations.get(&id) {
+            return Err(ZeroLockError::AlreadyExists(format!(
+                "MemoryId {} is already allocated to {:?}",
+                id, owner
+            )));
+        }
+
+        allocations.insert(id, region);
+        Ok(())
+    })
+}
@@ -0,0 +1,573 @@
+//! Migrations Module - Schema-versioned encoding for records in stable memory
+//! Wraps stored records in a versioned envelope and steps old records forward on upgrade
+//!
+//! Envelope layout: b"ZLR" magic, u16 little-endian schema version, candid payload.
+//! Records written before envelopes existed are bare candid and count as version 0.
+//!
+//! To change a stored type: freeze its current shape as a `<Type>V<n>` struct below,
+//! append a step converting version n payloads to version n + 1, and keep the
+//! Storable bound unchanged so existing maps still load.
+//! Values held in a StableCell are migrated when the cell loads and rewritten on the next set.
+
+use crate::types::*;
+use crate::memory::{with_unclaimed_memory, Memory, MemoryRegion};
+use crate::wasm_store::{self, WasmModule};
+use crate::bounty_factory::{FactoryState, Sandbox, TierPayout};
+use crate::vault::{self, DedupEntry, LockInfo, LockStatus, PendingDeposit, PendingWithdrawal, Posting, TokenInfo, VaultState, WithdrawalPolicy, WithdrawalTracker};
+use crate::events::EventRecord;
+use crate::panel::PanelDecision;
+use crate::scheduler::JobState;
+use crate::judge::{self, AutomatedRule, DisputeCase, DisputeStatus, JudgeState, MonitoringState, Settlement, StorableVecBalanceSnapshot, StorableVecInvariantBreach};
+use crate::leaderboard::{Achievement, LeaderboardState};
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+const ENVELOPE_MAGIC: &[u8; 3] = b"ZLR";
+const ENVELOPE_HEADER_SIZE: usize = 5;
+
+/// Converts a candid payload from schema version n to version n + 1
+pub type MigrationStep = fn(Vec<u8>) -> Result<Vec<u8>, ZeroLockError>;
+
+/// A record type stored in stable memory under a versioned envelope
+pub trait VersionedRecord: CandidType + DeserializeOwned {
+    const RECORD_NAME: &'static str;
+
+    /// Step i upgrades a payload from version i to version i + 1
+    const MIGRATIONS: &'static [MigrationStep];
+
+    /// Version written by encode_record
+    const SCHEMA_VERSION: u16 = Self::MIGRATIONS.len() as u16;
+}
+
+/// Encodes a record at its current schema version
+pub fn encode_record<T: VersionedRecord>(record: &T) -> Vec<u8> {
+    let payload = candid::encode_one(record).unwrap();
+    let mut bytes = Vec::with_capacity(ENVELOPE_HEADER_SIZE + payload.len());
+    bytes.extend_from_slice(ENVELOPE_MAGIC);
+    bytes.extend_from_slice(&T::SCHEMA_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+/// Decodes a record, stepping older payloads forward one version at a time
+pub fn decode_record<T: VersionedRecord>(bytes: &[u8]) -> Result<T, ZeroLockError> {
+    let (mut version, payload) = split_envelope(bytes);
+
+    if version > T::SCHEMA_VERSION {
+        return Err(ZeroLockError::InternalError(format!(
+            "{} record has schema version {}, newer than supported version {}",
+            T::RECORD_NAME, version, T::SCHEMA_VERSION
+        )));
+    }
+
+    let mut payload = payload.to_vec();
+    while version < T::SCHEMA_VERSION {
+        payload = T::MIGRATIONS[version as usize](payload).map_err(|e| {
+            ZeroLockError::InternalError(format!(
+                "Failed to migrate {} record from version {}: {:?}",
+                T::RECORD_NAME, version, e
+            ))
+        })?;
+        version += 1;
+    }
+
+    candid::decode_one(&payload).map_err(|e| {
+        ZeroLockError::InternalError(format!(
+            "Failed to decode {} record at version {}: {}",
+            T::RECORD_NAME, version, e
+        ))
+    })
+}
+
+/// Decodes a record inside Storable::from_bytes, which cannot return an error
+/// Post-upgrade migrations rewrite every record first, so this only traps on corruption
+pub fn decode_stored_record<T: VersionedRecord>(bytes: &[u8]) -> T {
+    decode_record(bytes).unwrap_or_else(|e| ic_cdk::trap(&format!("{:?}", e)))
+}
+
+/// Returns the schema version and candid payload of an encoded record
+fn split_envelope(bytes: &[u8]) -> (u16, &[u8]) {
+    if bytes.len() >= ENVELOPE_HEADER_SIZE && bytes.starts_with(ENVELOPE_MAGIC) {
+        let version = u16::from_le_bytes([bytes[3], bytes[4]]);
+        (version, &bytes[ENVELOPE_HEADER_SIZE..])
+    } else {
+        (0, bytes)
+    }
+}
+
+// Migration registry
+
+// Result of migrating one stable store
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct MigrationReport {
+    pub store: String,
+    pub migrated: u64,
+}
+
+struct StoreMigration {
+    store: &'static str,
+    region: MemoryRegion,
+    memory_id: u8,
+    run: fn(Memory) -> Result<u64, ZeroLockError>,
+}
+
+/// Stores holding versioned records, with the MemoryIds their modules open them on
+/// WASM modules come first: migrating version 0 challenges imports their code into the store
+fn registered_stores() -> Vec<StoreMigration> {
+    vec![
+        StoreMigration {
+            store: "wasm_modules",
+            region: MemoryRegion::WasmStore,
+            memory_id: 41,
+            run: migrate_store::<[u8; 32], WasmModule>,
+        },
+        StoreMigration {
+            store: "challenges",
+            region: MemoryRegion::BountyFactory,
+            memory_id: 0,
+            run: migrate_store::<u64, Challenge>,
+        },
+        StoreMigration {
+            store: "transactions",
+            region: MemoryRegion::Vault,
+            memory_id: 10,
+            run: migrate_store::<u64, Transaction>,
+        },
+        StoreMigration {
+            store: "balances",
+            region: MemoryRegion::Vault,
+            memory_id: 11,
+            run: migrate_store::<StorableString, Balance>,
+        },
+        StoreMigration {
+            store: "locks",
+            region: MemoryRegion::Vault,
+            memory_id: 12,
+            run: migrate_store::<u64, LockInfo>,
+        },
+        StoreMigration {
+            store: "token_registry",
+            region: MemoryRegion::Vault,
+            memory_id: 15,
+            run: migrate_store::<StorableString, TokenInfo>,
+        },
+        StoreMigration {
+            store: "evaluations",
+            region: MemoryRegion::Judge,
+            memory_id: 20,
+            run: migrate_store::<u64, Evaluation>,
+        },
+        StoreMigration {
+            store: "monitoring_states",
+            region: MemoryRegion::Judge,
+            memory_id: 21,
+            run: migrate_store::<u64, MonitoringState>,
+        },
+        StoreMigration {
+            store: "disputes",
+            region: MemoryRegion::Judge,
+            memory_id: 22,
+            run: migrate_store::<u64, DisputeCase>,
+        },
+        StoreMigration {
+            store: "automated_rules",
+            region: MemoryRegion::Judge,
+            memory_id: 23,
+            run: migrate_store::<u64, AutomatedRule>,
+        },
+        StoreMigration {
+            store: "balance_history",
+            region: MemoryRegion::Judge,
+            memory_id: 24,
+            run: migrate_store::<StorableString, StorableVecBalanceSnapshot>,
+        },
+        StoreMigration {
+            store: "settlements",
+            region: MemoryRegion::Judge,
+            memory_id: 26,
+            run: migrate_store::<(u64, u64), Settlement>,
+        },
+        StoreMigration {
+            store: "sandbox_monitoring",
+            region: MemoryRegion::Judge,
+            memory_id: 28,
+            run: migrate_store::<StorableString, MonitoringState>,
+        },
+        StoreMigration {
+            store: "user_profiles",
+            region: MemoryRegion::Leaderboard,
+            memory_id: 30,
+            run: migrate_store::<crate::leaderboard::StorablePrincipal, UserProfile>,
+        },
+        StoreMigration {
+            store: "achievements",
+            region: MemoryRegion::Leaderboard,
+            memory_id: 32,
+            run: migrate_store::<u64, Achievement>,
+        },
+    ]
+}
+
+/// Rewrites every outdated record at its current schema version
+/// Must run in post_upgrade before any module opens the registered stores
+pub fn run_migrations() -> Result<Vec<MigrationReport>, ZeroLockError> {
+    let mut reports = Vec::new();
+
+    for migration in registered_stores() {
+        let migrated = with_unclaimed_memory(migration.region, migration.memory_id, migration.run)??;
+        reports.push(MigrationReport {
+            store: migration.store.to_string(),
+            migrated,
+        });
+    }
+
+    Ok(reports)
+}
+
+/// Migrates one StableBTreeMap by reading its values as raw envelopes
+fn migrate_store<K, T>(memory: Memory) -> Result<u64, ZeroLockError>
+where
+    K: Storable + Ord + Clone,
+    T: VersionedRecord + Storable,
+{
+    let mut store: StableBTreeMap<K, RawRecord<T>, Memory> = StableBTreeMap::init(memory);
+
+    let outdated: Vec<(K, RawRecord<T>)> = store
+        .iter()
+        .filter(|(_, raw)| split_envelope(&raw.bytes).0 != T::SCHEMA_VERSION)
+        .collect();
+
+    let mut migrated = 0;
+    for (key, raw) in outdated {
+        let record: T = decode_record(&raw.bytes)?;
+        store.insert(key, RawRecord::new(encode_record(&record)));
+        migrated += 1;
+    }
+
+    Ok(migrated)
+}
+
+// Encoded record read without decoding, sharing the bound of the record type
+struct RawRecord<T> {
+    bytes: Vec<u8>,
+    _record: PhantomData<T>,
+}
+
+impl<T> RawRecord<T> {
+    fn new(bytes: Vec<u8>) -> Self {
+        RawRecord { bytes, _record: PhantomData }
+    }
+}
+
+impl<T: Storable> Storable for RawRecord<T> {
+    const BOUND: Bound = T::BOUND;
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Borrowed(&self.bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        RawRecord::new(bytes.into_owned())
+    }
+}
+
+// Schema versions and migration steps per record type
+
+/// Version 0 records are bare candid with the same shape as version 1
+fn unchanged(payload: Vec<u8>) -> Result<Vec<u8>, ZeroLockError> {
+    Ok(payload)
+}
+
+impl VersionedRecord for Challenge {
+    const RECORD_NAME: &'static str = "Challenge";
+    const MIGRATIONS: &'static [MigrationStep] = &[migrate_challenge_v0_to_v1];
+}
+
+impl VersionedRecord for Transaction {
+    const RECORD_NAME: &'static str = "Transaction";
+    const MIGRATIONS: &'static [MigrationStep] = &[unchanged];
+}
+
+impl VersionedRecord for Balance {
+    const RECORD_NAME: &'static str = "Balance";
+    const MIGRATIONS: &'static [MigrationStep] = &[unchanged];
+}
+
+impl VersionedRecord for Evaluation {
+    const RECORD_NAME: &'static str = "Evaluation";
+    const MIGRATIONS: &'static [MigrationStep] = &[unchanged];
+}
+
+impl VersionedRecord for UserProfile {
+    const RECORD_NAME: &'static str = "UserProfile";
+    const MIGRATIONS: &'static [MigrationStep] = &[unchanged];
+}
+
+impl VersionedRecord for AttackAttempt {
+    const RECORD_NAME: &'static str = "AttackAttempt";
+    const MIGRATIONS: &'static [MigrationStep] = &[unchanged];
+}
+
+impl VersionedRecord for MonitoringState {
+    const RECORD_NAME: &'static str = "MonitoringState";
+    const MIGRATIONS: &'static [MigrationStep] = &[unchanged];
+}
+
+impl VersionedRecord for AutomatedRule {
+    const RECORD_NAME: &'static str = "AutomatedRule";
+    const MIGRATIONS: &'static [MigrationStep] = &[unchanged]; // challenge_id was added as an opt field
+}
+
+impl VersionedRecord for JudgeState {
+    const RECORD_NAME: &'static str = "JudgeState";
+    const MIGRATIONS: &'static [MigrationStep] = &[unchanged];
+}
+
+impl VersionedRecord for Achievement {
+    const RECORD_NAME: &'static str = "Achievement";
+    const MIGRATIONS: &'static [MigrationStep] = &[unchanged];
+}
+
+impl VersionedRecord for LeaderboardState {
+    const RECORD_NAME: &'static str = "LeaderboardState";
+    const MIGRATIONS: &'static [MigrationStep] = &[unchanged];
+}
+
+impl VersionedRecord for WasmModule {
+    const RECORD_NAME: &'static str = "WasmModule";
+    const MIGRATIONS: &'static [MigrationStep] = &[unchanged];
+}
+
+impl VersionedRecord for FactoryState {
+    const RECORD_NAME: &'static str = "FactoryState";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for LockInfo {
+    const RECORD_NAME: &'static str = "LockInfo";
+    const MIGRATIONS: &'static [MigrationStep] = &[migrate_lock_info_v0_to_v1];
+}
+
+impl VersionedRecord for VaultState {
+    const RECORD_NAME: &'static str = "VaultState";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for Posting {
+    const RECORD_NAME: &'static str = "Posting";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for TierPayout {
+    const RECORD_NAME: &'static str = "TierPayout";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for DedupEntry {
+    const RECORD_NAME: &'static str = "DedupEntry";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for WithdrawalPolicy {
+    const RECORD_NAME: &'static str = "WithdrawalPolicy";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for PendingWithdrawal {
+    const RECORD_NAME: &'static str = "PendingWithdrawal";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for PendingDeposit {
+    const RECORD_NAME: &'static str = "PendingDeposit";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for WithdrawalTracker {
+    const RECORD_NAME: &'static str = "WithdrawalTracker";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for TokenInfo {
+    const RECORD_NAME: &'static str = "TokenInfo";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for Sandbox {
+    const RECORD_NAME: &'static str = "Sandbox";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for PanelDecision {
+    const RECORD_NAME: &'static str = "PanelDecision";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for EventRecord {
+    const RECORD_NAME: &'static str = "EventRecord";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for JobState {
+    const RECORD_NAME: &'static str = "JobState";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for Settlement {
+    const RECORD_NAME: &'static str = "Settlement";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for DisputeCase {
+    const RECORD_NAME: &'static str = "DisputeCase";
+    const MIGRATIONS: &'static [MigrationStep] = &[migrate_dispute_case_v0_to_v1];
+}
+
+impl VersionedRecord for StorableVecInvariantBreach {
+    const RECORD_NAME: &'static str = "InvariantBreaches";
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+}
+
+impl VersionedRecord for StorableVecBalanceSnapshot {
+    const RECORD_NAME: &'static str = "BalanceHistory";
+    const MIGRATIONS: &'static [MigrationStep] = &[migrate_balance_history_v0_to_v1];
+}
+
+// Challenge as stored before target modules moved to the WASM store
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct ChallengeV0 {
+    id: u64,
+    company: Principal,
+    target_canister: Option<Principal>,
+    wasm_code: Vec<u8>,
+    candid_interface: String,
+    bounty_amount: u64,
+    token_type: TokenType,
+    start_time: i64,
+    end_time: i64,
+    status: ChallengeStatus,
+    description: String,
+    difficulty_level: u8,
+    created_at: i64,
+    updated_at: i64,
+}
+
+/// Moves inline WASM code into the WASM store and references it by hash
+/// Existing challenges measure their shared target's cycles, declare no invariants
+/// and pay their whole bounty to a single winner
+fn migrate_challenge_v0_to_v1(payload: Vec<u8>) -> Result<Vec<u8>, ZeroLockError> {
+    let legacy: ChallengeV0 = candid::decode_one(&payload)
+        .map_err(|e| ZeroLockError::InternalError(e.to_string()))?;
+
+    let wasm_hash = wasm_store::import_wasm_module(&legacy.wasm_code, legacy.company)?;
+
+    let challenge = Challenge {
+        id: legacy.id,
+        company: legacy.company,
+        target_canister: legacy.target_canister,
+        wasm_hash,
+        candid_interface: legacy.candid_interface,
+        bounty_amount: legacy.bounty_amount,
+        token_type: legacy.token_type,
+        start_time: legacy.start_time,
+        end_time: legacy.end_time,
+        status: legacy.status,
+        description: legacy.description,
+        difficulty_level: legacy.difficulty_level,
+        balance_source: BalanceSource::Cycles,
+        invariants: Vec::new(),
+        sandboxed: false,
+        severity_tiers: Vec::new(),
+        created_at: legacy.created_at,
+        updated_at: legacy.updated_at,
+    };
+
+    candid::encode_one(&challenge).map_err(|e| ZeroLockError::InternalError(e.to_string()))
+}
+
+/// Clears balance histories recorded before balance sources existed
+/// Those snapshots came from a placeholder that always reported 1_000_000,
+/// so they say nothing about the target and would skew new measurements
+fn migrate_balance_history_v0_to_v1(_payload: Vec<u8>) -> Result<Vec<u8>, ZeroLockError> {
+    candid::encode_one(StorableVecBalanceSnapshot::default())
+        .map_err(|e| ZeroLockError::InternalError(e.to_string()))
+}
+
+// LockInfo as stored when the platform fee was taken out of the bounty at payout
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct LockInfoV0 {
+    challenge_id: u64,
+    company: Principal,
+    amount: u64,
+    token_type: TokenType,
+    locked_at: i64,
+    expires_at: i64,
+    status: LockStatus,
+}
+
+/// Splits the fee that used to be deducted at payout out of the locked amount
+/// The total held by the lock is unchanged, so balances stay consistent. Existing
+/// locks have paid nothing yet: active ones still hold everything, and released
+/// ones are closed, so their counters are never read
+fn migrate_lock_info_v0_to_v1(payload: Vec<u8>) -> Result<Vec<u8>, ZeroLockError> {
+    let legacy: LockInfoV0 = candid::decode_one(&payload)
+        .map_err(|e| ZeroLockError::InternalError(e.to_string()))?;
+
+    let platform_fee = vault::platform_fee_for(legacy.amount)?;
+    let lock_info = LockInfo {
+        challenge_id: legacy.challenge_id,
+        company: legacy.company,
+        amount: Amount(legacy.amount).checked_sub(Amount(platform_fee))?.get(),
+        platform_fee,
+        paid_out: 0,
+        fee_paid: 0,
+        token_type: legacy.token_type,
+        locked_at: legacy.locked_at,
+        expires_at: legacy.expires_at,
+        status: legacy.status,
+    };
+
+    candid::encode_one(&lock_info).map_err(|e| ZeroLockError::InternalError(e.to_string()))
+}
+
+// DisputeCase as stored before disputes had reviewers and review deadlines
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct DisputeCaseV0 {
+    id: u64,
+    challenge_id: u64,
+    attack_attempt_id: u64,
+    disputer: Principal,
+    reason: String,
+    evidence: Vec<Vec<u8>>,
+    status: DisputeStatus,
+    created_at: i64,
+    resolved_at: Option<i64>,
+    resolution: Option<String>,
+}
+
+/// Gives existing disputes a first review period counted from their creation
+fn migrate_dispute_case_v0_to_v1(payload: Vec<u8>) -> Result<Vec<u8>, ZeroLockError> {
+    let legacy: DisputeCaseV0 = candid::decode_one(&payload)
+        .map_err(|e| ZeroLockError::InternalError(e.to_string()))?;
+
+    let dispute = DisputeCase {
+        id: legacy.id,
+        challenge_id: legacy.challenge_id,
+        attack_attempt_id: legacy.attack_attempt_id,
+        disputer: legacy.disputer,
+        reason: legacy.reason,
+        evidence: legacy.evidence,
+        status: legacy.status,
+        reviewer: None,
+        review_deadline: legacy.created_at + judge::DISPUTE_REVIEW_PERIOD,
+        created_at: legacy.created_at,
+        resolved_at: legacy.resolved_at,
+        resolution: legacy.resolution,
+    };
+
+    candid::encode_one(&dispute).map_err(|e| ZeroLockError::InternalError(e.to_string()))
+}
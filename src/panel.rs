@@ -0,0 +1,448 @@
+//! Panel Module - Human judge panel and consensus voting on attack attempts
+//! Panel judges vote on attempts; each vote is stored as its own Evaluation and the
+//! decision is final once the challenge's quorum is reached with a clear majority
+
+use crate::types::*;
+use crate::memory::{get_memory, Memory, MemoryRegion};
+use crate::migrations::{encode_record, decode_stored_record};
+use crate::bounty_factory::{self, StorablePrincipal};
+use crate::judge;
+use crate::rbac::{self, Role};
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde::Serialize;
+use std::cell::RefCell;
+
+use std::borrow::Cow;
+
+// Memory management
+type PanelStorage = StableBTreeMap<StorablePrincipal, u64, Memory>;
+type ChallengeQuorumStorage = StableBTreeMap<u64, u64, Memory>;
+type DifficultyQuorumStorage = StableBTreeMap<u8, u64, Memory>;
+type VoteStorage = StableBTreeMap<(u64, StorablePrincipal), u64, Memory>;
+type PanelDecisionStorage = StableBTreeMap<u64, PanelDecision, Memory>;
+
+// Configuration constants
+const DEFAULT_QUORUM: u64 = 3;
+const MAX_QUORUM: u64 = 15;
+const MAX_PANEL_SIZE: u64 = 50;
+
+// Final outcome of the panel's vote on an attack attempt
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PanelDecision {
+    pub attack_attempt_id: u64,
+    pub challenge_id: u64,
+    pub decision: JudgeDecision,
+    pub valid_votes: u64,
+    pub invalid_votes: u64,
+    pub quorum: u64,
+    pub finalized_at: i64,
+}
+
+// Global state - Panel uses MemoryId 60-69
+thread_local! {
+    // Panel judges from before RBAC and when they were added, moved into Judge grants on upgrade
+    static PANEL_JUDGES: RefCell<PanelStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Panel, 60)
+        )
+    );
+
+    static CHALLENGE_QUORUMS: RefCell<ChallengeQuorumStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Panel, 61)
+        )
+    );
+
+    static DIFFICULTY_QUORUMS: RefCell<DifficultyQuorumStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Panel, 62)
+        )
+    );
+
+    // Evaluation ID of each vote, keyed by (attack_attempt_id, judge)
+    static VOTES: RefCell<VoteStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Panel, 63)
+        )
+    );
+
+    static PANEL_DECISIONS: RefCell<PanelDecisionStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Panel, 64)
+        )
+    );
+}
+
+/// Adds a judge to the panel (admin function)
+/// Panel membership is the Judge role
+/// @param judge Principal of the judge
+/// @returns Success or error
+#[update]
+pub fn add_panel_judge(judge: Principal) -> ApiResponse<()> {
+    if rbac::role_members(Role::Judge).len() as u64 >= MAX_PANEL_SIZE {
+        return ApiResponse::Err(ZeroLockError::ResourceLimit(
+            "Maximum panel size reached".to_string()
+        ));
+    }
+
+    rbac::grant_role_internal(ic_cdk::caller(), judge, Role::Judge).into()
+}
+
+/// Removes a judge from the panel (admin function)
+/// Votes already cast are kept
+/// @param judge Principal of the judge
+/// @returns Success or error
+#[update]
+pub fn remove_panel_judge(judge: Principal) -> ApiResponse<()> {
+    rbac::revoke_role_internal(ic_cdk::caller(), judge, Role::Judge).into()
+}
+
+/// Lists the panel judges
+/// @returns Principals of all panel judges
+#[query]
+pub fn get_panel_judges() -> ApiResponse<Vec<Principal>> {
+    ApiResponse::Ok(rbac::role_members(Role::Judge))
+}
+
+/// Sets or clears the quorum of one challenge (admin function)
+/// @param challenge_id Challenge identifier
+/// @param quorum Votes needed, or None to fall back to the difficulty level's quorum
+/// @returns Success or error
+#[update]
+pub fn set_challenge_quorum(challenge_id: u64, quorum: Option<u64>) -> ApiResponse<()> {
+    if let Err(e) = check_caller_is_admin() {
+        return ApiResponse::Err(e);
+    }
+
+    if bounty_factory::get_challenge_internal(challenge_id).is_none() {
+        return ApiResponse::Err(ZeroLockError::NotFound("Challenge not found".to_string()));
+    }
+
+    match quorum {
+        Some(quorum) => {
+            if let Err(e) = validate_quorum(quorum) {
+                return ApiResponse::Err(e);
+            }
+            CHALLENGE_QUORUMS.with(|quorums| quorums.borrow_mut().insert(challenge_id, quorum));
+        }
+        None => {
+            CHALLENGE_QUORUMS.with(|quorums| quorums.borrow_mut().remove(&challenge_id));
+        }
+    }
+
+    ApiResponse::Ok(())
+}
+
+/// Sets the quorum of challenges at a difficulty level (admin function)
+/// @param difficulty_level Difficulty level (1-5)
+/// @param quorum Votes needed
+/// @returns Success or error
+#[update]
+pub fn set_difficulty_quorum(difficulty_level: u8, quorum: u64) -> ApiResponse<()> {
+    if let Err(e) = check_caller_is_admin() {
+        return ApiResponse::Err(e);
+    }
+
+    if !is_valid_difficulty_level(difficulty_level) {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(
+            "Difficulty level must be between 1 and 5".to_string()
+        ));
+    }
+
+    if let Err(e) = validate_quorum(quorum) {
+        return ApiResponse::Err(e);
+    }
+
+    DIFFICULTY_QUORUMS.with(|quorums| quorums.borrow_mut().insert(difficulty_level, quorum));
+    ApiResponse::Ok(())
+}
+
+/// Gets the number of votes that decides an attempt on a challenge
+/// @param challenge_id Challenge identifier
+/// @returns Challenge quorum, else the difficulty level's quorum, else the default
+#[query]
+pub fn get_quorum(challenge_id: u64) -> ApiResponse<u64> {
+    match bounty_factory::get_challenge_internal(challenge_id) {
+        Some(challenge) => ApiResponse::Ok(quorum_for(&challenge)),
+        None => ApiResponse::Err(ZeroLockError::NotFound("Challenge not found".to_string())),
+    }
+}
+
+/// Casts a panel judge's vote on an attack attempt
+/// @param attempt_id Attack attempt identifier
+/// @param decision Valid or Invalid
+/// @param reasoning Why the judge decided so
+/// @returns The vote, stored as an evaluation by the judge
+#[update]
+pub fn cast_vote(attempt_id: u64, decision: JudgeDecision, reasoning: String) -> ApiResponse<Evaluation> {
+    cast_vote_internal(ic_cdk::caller(), attempt_id, decision, reasoning).into()
+}
+
+/// Gets the panel's final decision on an attack attempt
+/// @param attempt_id Attack attempt identifier
+/// @returns Decision or error if the panel has not reached quorum
+#[query]
+pub fn get_panel_decision(attempt_id: u64) -> ApiResponse<PanelDecision> {
+    match get_panel_decision_internal(attempt_id) {
+        Some(decision) => ApiResponse::Ok(decision),
+        None => ApiResponse::Err(ZeroLockError::NotFound("No panel decision for this attempt".to_string())),
+    }
+}
+
+/// Finalizes an attempt whose votes reached quorum but whose payout failed (panel judge or admin)
+/// A Valid decision is only stored once its settlement succeeds, so this retries it
+/// @param attempt_id Attack attempt identifier
+/// @returns The panel's decision
+#[update]
+pub fn finalize_panel_decision(attempt_id: u64) -> ApiResponse<PanelDecision> {
+    finalize_panel_decision_internal(ic_cdk::caller(), attempt_id).into()
+}
+
+// Public functions for other modules
+
+/// Checks whether a principal is on the judge panel
+/// Only an explicit Judge grant counts; a SuperAdmin does not vote by default
+pub fn is_panel_judge(principal: Principal) -> bool {
+    rbac::holds_role(&principal, Role::Judge)
+}
+
+/// Gets the panel's final decision on an attack attempt, if quorum was reached
+pub fn get_panel_decision_internal(attempt_id: u64) -> Option<PanelDecision> {
+    PANEL_DECISIONS.with(|decisions| decisions.borrow().get(&attempt_id))
+}
+
+/// Empties the pre-RBAC panel registry
+/// @returns Each judge with the time they were added
+pub fn take_legacy_panel_judges() -> Vec<(Principal, u64)> {
+    PANEL_JUDGES.with(|panel| {
+        let mut panel = panel.borrow_mut();
+        let legacy: Vec<(StorablePrincipal, u64)> = panel.iter().collect();
+        for (judge, _) in &legacy {
+            panel.remove(judge);
+        }
+        legacy.into_iter().map(|(judge, added_at)| (judge.0, added_at)).collect()
+    })
+}
+
+// Private helper functions
+
+/// Records a vote and finalizes the attempt's decision once quorum is reached
+fn cast_vote_internal(
+    judge: Principal,
+    attempt_id: u64,
+    decision: JudgeDecision,
+    reasoning: String,
+) -> Result<Evaluation, ZeroLockError> {
+    if !is_panel_judge(judge) {
+        return Err(ZeroLockError::Unauthorized(
+            "Only panel judges can vote".to_string()
+        ));
+    }
+
+    if decision != JudgeDecision::Valid && decision != JudgeDecision::Invalid {
+        return Err(ZeroLockError::InvalidInput(
+            "Votes must be Valid or Invalid".to_string()
+        ));
+    }
+
+    if reasoning.is_empty() || reasoning.len() > MAX_DESCRIPTION_LENGTH {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "Vote reasoning must be 1 to {} characters",
+            MAX_DESCRIPTION_LENGTH
+        )));
+    }
+
+    let attempt = bounty_factory::get_attack_attempt_internal(attempt_id)
+        .ok_or_else(|| ZeroLockError::NotFound("Attack attempt not found".to_string()))?;
+    let challenge = bounty_factory::get_challenge_internal(attempt.challenge_id)
+        .ok_or_else(|| ZeroLockError::NotFound("Challenge not found".to_string()))?;
+
+    if judge == attempt.hacker || judge == challenge.company {
+        return Err(ZeroLockError::Unauthorized(
+            "Judges cannot vote on their own challenges or attacks".to_string()
+        ));
+    }
+
+    if challenge.status != ChallengeStatus::Active {
+        return Err(ZeroLockError::InvalidState(
+            "Votes are only taken on active challenges".to_string()
+        ));
+    }
+
+    if PANEL_DECISIONS.with(|decisions| decisions.borrow().contains_key(&attempt_id)) {
+        return Err(ZeroLockError::InvalidState(
+            "The panel has already decided this attempt".to_string()
+        ));
+    }
+
+    let vote_key = (attempt_id, StorablePrincipal(judge));
+    if VOTES.with(|votes| votes.borrow().contains_key(&vote_key)) {
+        return Err(ZeroLockError::AlreadyExists(
+            "Judge has already voted on this attempt".to_string()
+        ));
+    }
+
+    let evaluation = judge::record_evaluation(challenge.id, attempt_id, decision, reasoning, judge);
+    VOTES.with(|votes| votes.borrow_mut().insert(vote_key, evaluation.id));
+
+    ic_cdk::println!(
+        "Vote cast: Attempt={}, Judge={}, Decision={:?}",
+        attempt_id,
+        judge.to_text(),
+        evaluation.decision
+    );
+
+    // The vote stands either way; finalize_panel_decision retries a failed payout
+    if let Err(e) = finalize_if_quorum(&challenge, &attempt) {
+        ic_cdk::println!("Panel decision on attempt {} not finalized: {:?}", attempt_id, e);
+    }
+    judge::apply_attempt_rules(&attempt);
+
+    Ok(evaluation)
+}
+
+/// Retries finalizing an attempt's decision for a panel judge or admin
+fn finalize_panel_decision_internal(caller: Principal, attempt_id: u64) -> Result<PanelDecision, ZeroLockError> {
+    if !is_panel_judge(caller) && !rbac::is_admin(&caller) {
+        return Err(ZeroLockError::Unauthorized(
+            "Only panel judges or admins can finalize panel decisions".to_string()
+        ));
+    }
+
+    if let Some(decision) = get_panel_decision_internal(attempt_id) {
+        return Ok(decision);
+    }
+
+    let attempt = bounty_factory::get_attack_attempt_internal(attempt_id)
+        .ok_or_else(|| ZeroLockError::NotFound("Attack attempt not found".to_string()))?;
+    let challenge = bounty_factory::get_challenge_internal(attempt.challenge_id)
+        .ok_or_else(|| ZeroLockError::NotFound("Challenge not found".to_string()))?;
+
+    if challenge.status != ChallengeStatus::Active {
+        return Err(ZeroLockError::InvalidState(
+            "Votes are only taken on active challenges".to_string()
+        ));
+    }
+
+    finalize_if_quorum(&challenge, &attempt)?.ok_or_else(|| {
+        ZeroLockError::InvalidState("The votes on this attempt have not reached a decision".to_string())
+    })
+}
+
+/// Finalizes the attempt's decision once enough votes agree, paying out a valid attack
+/// A Valid decision is stored only after its settlement succeeds, so a failed payout
+/// leaves the attempt undecided. A tie is left open until another vote breaks it
+/// @returns The decision, or None while the votes have not decided the attempt
+fn finalize_if_quorum(challenge: &Challenge, attempt: &AttackAttempt) -> Result<Option<PanelDecision>, ZeroLockError> {
+    let (valid_votes, invalid_votes) = tally_votes(attempt.id);
+    let quorum = quorum_for(challenge);
+
+    if valid_votes + invalid_votes < quorum || valid_votes == invalid_votes {
+        return Ok(None);
+    }
+
+    let decision = if valid_votes > invalid_votes {
+        JudgeDecision::Valid
+    } else {
+        JudgeDecision::Invalid
+    };
+
+    if decision == JudgeDecision::Valid {
+        // The settlement is recorded against the latest Valid vote
+        let evaluation_id = judge::confirming_evaluation(attempt.id).ok_or_else(|| {
+            ZeroLockError::InternalError("Valid decision without a Valid vote".to_string())
+        })?;
+        judge::trigger_settlement(challenge.id, attempt.hacker, attempt.id, evaluation_id)?;
+    }
+
+    let panel_decision = PanelDecision {
+        attack_attempt_id: attempt.id,
+        challenge_id: challenge.id,
+        decision: decision.clone(),
+        valid_votes,
+        invalid_votes,
+        quorum,
+        finalized_at: current_time(),
+    };
+    PANEL_DECISIONS.with(|decisions| decisions.borrow_mut().insert(attempt.id, panel_decision.clone()));
+
+    ic_cdk::println!(
+        "Panel decided attempt {}: {:?} ({} valid, {} invalid)",
+        attempt.id,
+        decision,
+        valid_votes,
+        invalid_votes
+    );
+    Ok(Some(panel_decision))
+}
+
+/// Counts the Valid and Invalid votes cast on an attempt
+fn tally_votes(attempt_id: u64) -> (u64, u64) {
+    let lowest = StorablePrincipal(Principal::from_slice(&[]));
+    let evaluation_ids: Vec<u64> = VOTES.with(|votes| {
+        votes
+            .borrow()
+            .range((attempt_id, lowest.clone())..(attempt_id + 1, lowest))
+            .map(|(_, evaluation_id)| evaluation_id)
+            .collect()
+    });
+
+    let mut valid_votes = 0;
+    let mut invalid_votes = 0;
+    for evaluation in evaluation_ids.into_iter().filter_map(judge::get_evaluation_internal) {
+        match evaluation.decision {
+            JudgeDecision::Valid => valid_votes += 1,
+            JudgeDecision::Invalid => invalid_votes += 1,
+            _ => {}
+        }
+    }
+    (valid_votes, invalid_votes)
+}
+
+/// Resolves the quorum of a challenge: its own, else its difficulty level's, else the default
+fn quorum_for(challenge: &Challenge) -> u64 {
+    CHALLENGE_QUORUMS.with(|quorums| quorums.borrow().get(&challenge.id))
+        .or_else(|| DIFFICULTY_QUORUMS.with(|quorums| quorums.borrow().get(&challenge.difficulty_level)))
+        .unwrap_or(DEFAULT_QUORUM)
+}
+
+/// Checks a quorum setting
+fn validate_quorum(quorum: u64) -> Result<(), ZeroLockError> {
+    if quorum == 0 || quorum > MAX_QUORUM {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "Quorum must be 1 to {} votes",
+            MAX_QUORUM
+        )));
+    }
+    Ok(())
+}
+
+/// Fails unless the caller is a platform admin
+fn check_caller_is_admin() -> Result<(), ZeroLockError> {
+    if rbac::is_admin(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(ZeroLockError::Unauthorized(
+            "Only admins can manage the judge panel".to_string()
+        ))
+    }
+}
+
+// Storable implementations for stable storage
+
+impl Storable for PanelDecision {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
@@ -0,0 +1,236 @@
+//! RBAC Module - Role-based access control shared by every module
+//! Privileged endpoints check the caller's roles here instead of keeping their own allow-lists
+//!
+//! SuperAdmin holds every role and manages all grants; admins manage judges.
+//! The first SuperAdmin is set by the init arguments.
+
+use crate::types::*;
+use crate::memory::{get_memory, Memory, MemoryRegion};
+use crate::bounty_factory::{self, StorablePrincipal};
+use crate::vault;
+use crate::panel;
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use ic_stable_structures::StableBTreeMap;
+use serde::Serialize;
+use std::cell::RefCell;
+
+// Memory management
+type GrantStorage = StableBTreeMap<(StorablePrincipal, u8), u64, Memory>;
+
+// Roles that can be granted to a principal
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    SuperAdmin, // Holds every role and manages all grants
+    Admin,      // Runs the platform: challenges, rules, disputes, tokens, jobs
+    Judge,      // Sits on the judge panel and reviews disputes
+    Treasurer,  // Moves escrow through the vault endpoints and sets the fee recipient
+    Pauser,     // Pauses and resumes the vault
+}
+
+const ALL_ROLES: [Role; 5] = [Role::SuperAdmin, Role::Admin, Role::Judge, Role::Treasurer, Role::Pauser];
+
+// Global state - RBAC uses MemoryId 80-89
+thread_local! {
+    // Grant time of each role, keyed by (principal, role)
+    static GRANTS: RefCell<GrantStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Rbac, 80)
+        )
+    );
+}
+
+/// Grants a role to a principal
+/// Only a SuperAdmin may grant roles other than Judge, which admins may also grant
+/// @param principal Principal receiving the role
+/// @param role Role to grant
+/// @returns Success or error
+#[update]
+pub fn grant_role(principal: Principal, role: Role) -> ApiResponse<()> {
+    grant_role_internal(ic_cdk::caller(), principal, role).into()
+}
+
+/// Revokes a role from a principal
+/// @param principal Principal losing the role
+/// @param role Role to revoke
+/// @returns Success or error
+#[update]
+pub fn revoke_role(principal: Principal, role: Role) -> ApiResponse<()> {
+    revoke_role_internal(ic_cdk::caller(), principal, role).into()
+}
+
+/// Lists the principals explicitly granted a role (admin function)
+/// @param role Role to list
+/// @returns Principals holding the role
+#[query]
+pub fn get_role_members(role: Role) -> ApiResponse<Vec<Principal>> {
+    if let Err(e) = check_caller_has_role(Role::Admin) {
+        return ApiResponse::Err(e);
+    }
+
+    ApiResponse::Ok(role_members(role))
+}
+
+/// Lists the roles granted to the caller
+/// @returns Roles held by the caller
+#[query]
+pub fn get_my_roles() -> ApiResponse<Vec<Role>> {
+    let caller = ic_cdk::caller();
+    let roles = ALL_ROLES
+        .into_iter()
+        .filter(|role| holds_role(&caller, *role))
+        .collect();
+    ApiResponse::Ok(roles)
+}
+
+// Public functions for other modules
+
+/// Checks if a principal holds a role, directly or as a SuperAdmin
+pub fn has_role(principal: &Principal, role: Role) -> bool {
+    holds_role(principal, role) || holds_role(principal, Role::SuperAdmin)
+}
+
+/// Checks if a principal is a platform admin
+pub fn is_admin(principal: &Principal) -> bool {
+    has_role(principal, Role::Admin)
+}
+
+/// Checks if a principal was granted a role itself, ignoring SuperAdmin
+pub fn holds_role(principal: &Principal, role: Role) -> bool {
+    GRANTS.with(|grants| grants.borrow().contains_key(&(StorablePrincipal(*principal), role.id())))
+}
+
+/// Fails unless the caller holds the role
+/// @returns The caller
+pub fn check_caller_has_role(role: Role) -> Result<Principal, ZeroLockError> {
+    let caller = ic_cdk::caller();
+    if has_role(&caller, role) {
+        Ok(caller)
+    } else {
+        Err(ZeroLockError::Unauthorized(format!("Requires the {:?} role", role)))
+    }
+}
+
+/// Lists the principals explicitly granted a role
+pub fn role_members(role: Role) -> Vec<Principal> {
+    GRANTS.with(|grants| {
+        grants
+            .borrow()
+            .iter()
+            .filter(|((_, role_id), _)| *role_id == role.id())
+            .map(|((principal, _), _)| principal.0)
+            .collect()
+    })
+}
+
+/// Grants a role on behalf of `granter`, who must be allowed to manage it
+pub fn grant_role_internal(granter: Principal, principal: Principal, role: Role) -> Result<(), ZeroLockError> {
+    check_can_manage(&granter, role)?;
+
+    if principal == Principal::anonymous() {
+        return Err(ZeroLockError::InvalidInput(
+            "Roles cannot be granted to the anonymous principal".to_string()
+        ));
+    }
+
+    if holds_role(&principal, role) {
+        return Err(ZeroLockError::AlreadyExists(format!(
+            "{} already holds the {:?} role",
+            principal.to_text(),
+            role
+        )));
+    }
+
+    insert_grant(principal, role, current_time() as u64);
+    ic_cdk::println!("Role granted: {:?} to {} by {}", role, principal.to_text(), granter.to_text());
+    Ok(())
+}
+
+/// Revokes a role on behalf of `revoker`, who must be allowed to manage it
+pub fn revoke_role_internal(revoker: Principal, principal: Principal, role: Role) -> Result<(), ZeroLockError> {
+    check_can_manage(&revoker, role)?;
+
+    if !holds_role(&principal, role) {
+        return Err(ZeroLockError::NotFound(format!(
+            "{} does not hold the {:?} role",
+            principal.to_text(),
+            role
+        )));
+    }
+
+    // Without a SuperAdmin nobody could grant admin roles again
+    if role == Role::SuperAdmin && role_members(Role::SuperAdmin).len() == 1 {
+        return Err(ZeroLockError::InvalidState(
+            "Cannot revoke the last SuperAdmin".to_string()
+        ));
+    }
+
+    GRANTS.with(|grants| grants.borrow_mut().remove(&(StorablePrincipal(principal), role.id())));
+    ic_cdk::println!("Role revoked: {:?} from {} by {}", role, principal.to_text(), revoker.to_text());
+    Ok(())
+}
+
+/// Makes a principal SuperAdmin; called with the init or upgrade arguments
+pub fn set_super_admin(principal: Principal) {
+    if principal == Principal::anonymous() {
+        ic_cdk::trap("The SuperAdmin cannot be the anonymous principal");
+    }
+
+    if !holds_role(&principal, Role::SuperAdmin) {
+        insert_grant(principal, Role::SuperAdmin, current_time() as u64);
+        ic_cdk::println!("SuperAdmin set: {}", principal.to_text());
+    }
+}
+
+/// Moves the allow-lists kept by modules before RBAC into role grants
+/// The first admin becomes SuperAdmin, later admins Admin, authorized canisters
+/// Treasurer and panel judges Judge. Each list is emptied, so this runs once
+pub fn import_legacy_grants() {
+    let now = current_time() as u64;
+
+    for (index, admin) in bounty_factory::take_legacy_admins().into_iter().enumerate() {
+        let role = if index == 0 && role_members(Role::SuperAdmin).is_empty() {
+            Role::SuperAdmin
+        } else {
+            Role::Admin
+        };
+        insert_grant(admin, role, now);
+    }
+
+    for canister in vault::take_legacy_authorized_canisters() {
+        insert_grant(canister, Role::Treasurer, now);
+    }
+
+    for (judge, added_at) in panel::take_legacy_panel_judges() {
+        insert_grant(judge, Role::Judge, added_at);
+    }
+}
+
+// Private helper functions
+
+impl Role {
+    fn id(&self) -> u8 {
+        *self as u8
+    }
+}
+
+fn insert_grant(principal: Principal, role: Role, granted_at: u64) {
+    GRANTS.with(|grants| grants.borrow_mut().insert((StorablePrincipal(principal), role.id()), granted_at));
+}
+
+/// Checks that `manager` may grant and revoke `role`
+fn check_can_manage(manager: &Principal, role: Role) -> Result<(), ZeroLockError> {
+    let allowed = match role {
+        Role::Judge => is_admin(manager),
+        _ => has_role(manager, Role::SuperAdmin),
+    };
+
+    if allowed {
+        Ok(())
+    } else {
+        Err(ZeroLockError::Unauthorized(format!(
+            "Not authorized to manage the {:?} role",
+            role
+        )))
+    }
+}
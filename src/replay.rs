@@ -0,0 +1,135 @@
+//! Replay Module - Deterministic verification of submitted exploits
+//! Replays a hacker's proof against a fresh instance of the challenge WASM and
+//! checks the company's invariants before and after, leaving the live target untouched
+//!
+//! Every replayed call is sent by this canister, which installed the instance and controls
+//! it, so Attacker calls run with owner privileges the real attacker would not have. An
+//! invariant break on replay therefore only sends the attempt to the judge panel; it never
+//! settles on its own.
+
+use crate::types::*;
+use crate::bounty_factory;
+use crate::invariants;
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::call::call_raw;
+use serde::Serialize;
+
+// Configuration constants
+const MAX_REPLAY_CALLS: usize = 16;
+const REPLAY_CANISTER_CYCLES: u128 = 300_000_000_000; // 0.3T cycles, enough to create, install and replay
+
+// Whose part a replayed call plays in the exploit
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum CallerRole {
+    Deployer, // Sets up state the way the deploying platform would
+    Attacker, // Performs the exploit
+}
+
+// One call of an exploit
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ReplayCall {
+    pub method: String,
+    pub args: Vec<u8>, // Candid-encoded arguments
+    pub caller_role: CallerRole,
+}
+
+// Proof format for submit_attack: the candid encoding of this record
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ReplayProof {
+    pub calls: Vec<ReplayCall>,
+}
+
+// Result of replaying a proof on a fresh instance
+#[derive(Clone, Debug)]
+pub struct ReplayOutcome {
+    pub broken_invariants: Vec<String>,
+    pub failed_calls: Vec<String>,
+}
+
+/// Decodes and checks a submitted proof
+pub fn decode_proof(proof: &[u8]) -> Result<ReplayProof, ZeroLockError> {
+    let proof: ReplayProof = candid::decode_one(proof).map_err(|e| {
+        ZeroLockError::InvalidInput(format!("Proof is not a candid-encoded ReplayProof: {}", e))
+    })?;
+
+    if proof.calls.is_empty() || proof.calls.len() > MAX_REPLAY_CALLS {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "A proof must contain 1 to {} calls",
+            MAX_REPLAY_CALLS
+        )));
+    }
+
+    if proof.calls.iter().all(|call| call.caller_role != CallerRole::Attacker) {
+        return Err(ZeroLockError::InvalidInput(
+            "A proof needs at least one Attacker call".to_string()
+        ));
+    }
+
+    if proof.calls.iter().any(|call| call.method.is_empty() || call.method.len() > MAX_BALANCE_METHOD_LENGTH) {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "Replayed method names must be 1 to {} characters",
+            MAX_BALANCE_METHOD_LENGTH
+        )));
+    }
+
+    Ok(proof)
+}
+
+/// Replays a proof on a fresh instance of the challenge WASM
+/// The instance is deleted afterwards whether or not the replay succeeded
+/// @returns Invariants the replay broke and the calls the instance rejected
+pub async fn replay_exploit(challenge: &Challenge, proof: &ReplayProof) -> Result<ReplayOutcome, ZeroLockError> {
+    if challenge.invariants.is_empty() {
+        return Err(ZeroLockError::InvalidState(
+            "Challenge declares no invariants to replay against".to_string()
+        ));
+    }
+
+    let instance = bounty_factory::deploy_wasm_instance(&challenge.wasm_hash, REPLAY_CANISTER_CYCLES).await?;
+    let outcome = replay_on_instance(instance, challenge, proof).await;
+
+    if let Err(e) = bounty_factory::delete_wasm_instance(instance).await {
+        ic_cdk::println!("Replay instance {} not deleted: {:?}", instance.to_text(), e);
+    }
+
+    outcome
+}
+
+// Private helper functions
+
+/// Checks invariants, replays the calls, and checks the invariants again
+async fn replay_on_instance(
+    instance: Principal,
+    challenge: &Challenge,
+    proof: &ReplayProof,
+) -> Result<ReplayOutcome, ZeroLockError> {
+    // A fresh instance must satisfy every invariant, or the replay proves nothing
+    for outcome in invariants::check_invariants(instance, &challenge.invariants).await {
+        if !outcome.holds {
+            return Err(ZeroLockError::InvalidState(format!(
+                "Invariant '{}' does not hold on a fresh instance: {}",
+                outcome.name, outcome.observed
+            )));
+        }
+    }
+
+    let mut failed_calls = Vec::new();
+    for (index, call) in proof.calls.iter().enumerate() {
+        // A rejected call is part of the record, not the end of the replay
+        if let Err((code, msg)) = call_raw(instance, &call.method, call.args.clone(), 0).await {
+            failed_calls.push(format!("#{} {}: {:?} - {}", index, call.method, code, msg));
+        }
+    }
+
+    let broken_invariants = invariants::check_invariants(instance, &challenge.invariants)
+        .await
+        .into_iter()
+        .filter(|outcome| !outcome.holds)
+        .map(|outcome| outcome.name)
+        .collect();
+
+    Ok(ReplayOutcome {
+        broken_invariants,
+        failed_calls,
+    })
+}
@@ -0,0 +1,282 @@
+//! Scheduler Module - Named recurring jobs on ic-cdk-timers interval timers
+//! Each job runs at its own configurable interval instead of on every round. Upgrades
+//! clear timers, so the schedule lives in stable memory and post_upgrade re-arms it
+//!
+//! ic-cdk-timers runs every timer in its own call, so a job that traps fails on its
+//! own without stopping the schedule.
+
+use crate::types::*;
+use crate::memory::{get_memory, Memory, MemoryRegion};
+use crate::migrations::{encode_record, decode_stored_record};
+use crate::bounty_factory;
+use crate::judge;
+use crate::vault;
+use crate::rbac;
+use candid::{CandidType, Deserialize};
+use ic_cdk_macros::*;
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use std::borrow::Cow;
+
+// Memory management
+type JobStorage = StableBTreeMap<u8, JobState, Memory>;
+
+// Configuration constants
+const MIN_JOB_INTERVAL_SECONDS: u64 = 10;
+const MAX_JOB_INTERVAL_SECONDS: u64 = 24 * 3600; // 1 day
+const SECOND: i64 = 1_000_000_000; // 1 second in nanoseconds
+
+// Recurring jobs run by the scheduler
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum JobKind {
+    ChallengeExpiry,    // Expires ended challenges and tears down their sandboxes
+    LockExpiry,         // Refunds escrow that outlived its challenge
+    BalanceChecks,      // Judge's monitoring of target balances
+    DisputeDeadlines,   // Escalates and closes disputes that timed out
+    DedupExpiry,        // Forgets vault idempotency keys past their window
+    WithdrawalRecovery, // Resends withdrawals whose ledger answer was lost
+    DepositRecovery,    // Resends deposits whose ledger answer was lost
+}
+
+const ALL_JOBS: [JobKind; 7] = [
+    JobKind::ChallengeExpiry,
+    JobKind::LockExpiry,
+    JobKind::BalanceChecks,
+    JobKind::DisputeDeadlines,
+    JobKind::DedupExpiry,
+    JobKind::WithdrawalRecovery,
+    JobKind::DepositRecovery,
+];
+
+// Schedule and run state of a job
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct JobState {
+    pub job: JobKind,
+    pub interval_seconds: u64,
+    pub enabled: bool,
+    pub next_run: i64,
+    pub last_run: Option<i64>,
+    pub running: bool,
+    pub run_count: u64,
+    pub last_error: Option<String>,
+}
+
+// Global state - Scheduler uses MemoryId 70-79
+thread_local! {
+    static JOBS: RefCell<JobStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Scheduler, 70)
+        )
+    );
+
+    // Interval timer of each enabled job; timers live on the heap only
+    static TIMERS: RefCell<BTreeMap<u8, TimerId>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Lists every job with its schedule and last run
+/// @returns Job states
+#[query]
+pub fn get_scheduled_jobs() -> ApiResponse<Vec<JobState>> {
+    ApiResponse::Ok(JOBS.with(|jobs| jobs.borrow().iter().map(|(_, state)| state).collect()))
+}
+
+/// Changes how often a job runs (admin function)
+/// The next run moves to one interval from now
+/// @param job Job to reschedule
+/// @param interval_seconds New interval in seconds
+/// @returns Updated job state
+#[update]
+pub fn set_job_interval(job: JobKind, interval_seconds: u64) -> ApiResponse<JobState> {
+    set_job_interval_internal(job, interval_seconds).into()
+}
+
+/// Enables or disables a job (admin function)
+/// A re-enabled job next runs one interval from now
+/// @param job Job to change
+/// @param enabled Whether the job should run
+/// @returns Updated job state
+#[update]
+pub fn set_job_enabled(job: JobKind, enabled: bool) -> ApiResponse<JobState> {
+    set_job_enabled_internal(job, enabled).into()
+}
+
+// Public functions for other modules
+
+/// Registers missing jobs and starts an interval timer for each enabled one
+/// Must run in init and post_upgrade, since upgrades clear timers and no job survives one mid-run
+pub fn arm() {
+    for job in ALL_JOBS {
+        let mut state = match JOBS.with(|jobs| jobs.borrow().get(&job.id())) {
+            Some(state) => JobState { running: false, ..state },
+            None => JobState {
+                job,
+                interval_seconds: job.default_interval_seconds(),
+                enabled: true,
+                next_run: 0,
+                last_run: None,
+                running: false,
+                run_count: 0,
+                last_error: None,
+            },
+        };
+        arm_job(&mut state);
+        JOBS.with(|jobs| jobs.borrow_mut().insert(job.id(), state));
+    }
+}
+
+// Private helper functions
+
+impl JobKind {
+    fn id(&self) -> u8 {
+        *self as u8
+    }
+
+    fn default_interval_seconds(&self) -> u64 {
+        match self {
+            JobKind::ChallengeExpiry => 60,
+            JobKind::LockExpiry => 3600,
+            JobKind::BalanceChecks => 60,
+            JobKind::DisputeDeadlines => 3600,
+            JobKind::DedupExpiry => 3600,
+            JobKind::WithdrawalRecovery => 600,
+            JobKind::DepositRecovery => 600,
+        }
+    }
+}
+
+fn set_job_interval_internal(job: JobKind, interval_seconds: u64) -> Result<JobState, ZeroLockError> {
+    check_caller_is_admin()?;
+
+    if !(MIN_JOB_INTERVAL_SECONDS..=MAX_JOB_INTERVAL_SECONDS).contains(&interval_seconds) {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "Job interval must be {} to {} seconds",
+            MIN_JOB_INTERVAL_SECONDS, MAX_JOB_INTERVAL_SECONDS
+        )));
+    }
+
+    update_job(job, |state| state.interval_seconds = interval_seconds)
+}
+
+fn set_job_enabled_internal(job: JobKind, enabled: bool) -> Result<JobState, ZeroLockError> {
+    check_caller_is_admin()?;
+
+    update_job(job, |state| state.enabled = enabled)
+}
+
+/// Applies a change to a job's state and re-arms its timer for the new schedule
+fn update_job(job: JobKind, change: impl FnOnce(&mut JobState)) -> Result<JobState, ZeroLockError> {
+    let mut state = JOBS.with(|jobs| jobs.borrow().get(&job.id()))
+        .ok_or_else(|| ZeroLockError::NotFound(format!("Job {:?} is not registered", job)))?;
+
+    change(&mut state);
+    arm_job(&mut state);
+    JOBS.with(|jobs| jobs.borrow_mut().insert(job.id(), state.clone()));
+
+    Ok(state)
+}
+
+/// Replaces a job's interval timer with one for its current schedule
+/// A disabled job is left without a timer
+fn arm_job(state: &mut JobState) {
+    let job = state.job;
+    if let Some(timer) = TIMERS.with(|timers| timers.borrow_mut().remove(&job.id())) {
+        ic_cdk_timers::clear_timer(timer);
+    }
+    if !state.enabled {
+        return;
+    }
+
+    let interval = Duration::from_secs(state.interval_seconds);
+    let timer = ic_cdk_timers::set_timer_interval(interval, move || ic_cdk::spawn(run_job(job)));
+    TIMERS.with(|timers| timers.borrow_mut().insert(job.id(), timer));
+    state.next_run = current_time() + state.interval_seconds as i64 * SECOND;
+}
+
+/// Runs a job when its timer fires
+/// A tick is skipped while the previous run is still awaiting
+async fn run_job(job: JobKind) {
+    let Some(state) = JOBS.with(|jobs| jobs.borrow().get(&job.id())) else {
+        return;
+    };
+    if !state.enabled || state.running {
+        return;
+    }
+
+    JOBS.with(|jobs| jobs.borrow_mut().insert(job.id(), JobState {
+        running: true,
+        next_run: current_time() + state.interval_seconds as i64 * SECOND,
+        ..state
+    }));
+    let _run = JobRun { job };
+
+    match job {
+        JobKind::ChallengeExpiry => bounty_factory::check_expired_challenges().await,
+        JobKind::LockExpiry => bounty_factory::release_expired_locks(),
+        JobKind::BalanceChecks => judge::perform_periodic_checks().await,
+        JobKind::DisputeDeadlines => judge::process_dispute_deadlines(),
+        JobKind::DedupExpiry => vault::prune_dedup_index(),
+        JobKind::WithdrawalRecovery => vault::recover_pending_withdrawals().await,
+        JobKind::DepositRecovery => vault::recover_pending_deposits().await,
+    }
+}
+
+// Records the end of a job run when dropped
+// ic-cdk drops a task that traps after an await, so such a run is still recorded,
+// with the error; a trap before any await rolls the whole run back instead
+struct JobRun {
+    job: JobKind,
+}
+
+impl Drop for JobRun {
+    fn drop(&mut self) {
+        let last_error = ic_cdk::api::call::is_recovering_from_trap().then(|| {
+            ic_cdk::println!("Scheduled job {:?} trapped", self.job);
+            "Trapped; changes after its last await were rolled back".to_string()
+        });
+
+        JOBS.with(|jobs| {
+            let mut jobs = jobs.borrow_mut();
+            if let Some(state) = jobs.get(&self.job.id()) {
+                jobs.insert(self.job.id(), JobState {
+                    running: false,
+                    last_run: Some(current_time()),
+                    run_count: state.run_count + 1,
+                    last_error,
+                    ..state
+                });
+            }
+        });
+    }
+}
+
+fn check_caller_is_admin() -> Result<(), ZeroLockError> {
+    if rbac::is_admin(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err(ZeroLockError::Unauthorized(
+            "Only admins can manage scheduled jobs".to_string()
+        ))
+    }
+}
+
+// Storable implementations for stable storage
+
+impl Storable for JobState {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 512,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
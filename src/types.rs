@@ -1,11 +1,13 @@
 //! Shared types and interfaces for ZeroLock platform
 //! Contains common data structures used across all canisters
 
-use candid::{CandidType, Deserialize, Principal};
+use candid::{CandidType, Deserialize, Int, Nat, Principal};
 use serde::Serialize;
 use std::collections::HashMap;
 use ic_stable_structures::Storable;
 use std::borrow::Cow;
+use crate::migrations::{encode_record, decode_stored_record};
+use crate::ledger::Account;
 
 // Token types supported by the platform
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -14,6 +16,43 @@ pub enum TokenType {
     ICRC1(Principal),
 }
 
+// Where the judge reads a challenge target's balance from
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum BalanceSource {
+    Cycles,                                          // Target's cycles via canister_status
+    Icrc1Balance { ledger: Principal, account: Account }, // icrc1_balance_of on a declared account
+    QueryMethod(String),                             // Company-declared `() -> (nat) query` on the target
+}
+
+// Value an invariant compares a query result against
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum InvariantValue {
+    Nat(Nat),
+    Int(Int),
+    Bool(bool),
+    Text(String),
+    Principal(Principal),
+}
+
+// Expected-value predicate on a Candid-decoded query result
+// Paths select record fields by name (or tuple position) and vector elements by index
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum InvariantPredicate {
+    Equals { path: Vec<String>, expected: InvariantValue },
+    AtLeast { path: Vec<String>, minimum: Int },
+    AtMost { path: Vec<String>, maximum: Int },
+    // Number at `total` equals the sum over the vector at `items`, read at `element` in each entry
+    SumEquals { total: Vec<String>, items: Vec<String>, element: Vec<String> },
+}
+
+// Property of the target that must hold until it is exploited
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Invariant {
+    pub name: String,
+    pub method: String, // `() -> (...) query` method on the target
+    pub predicate: InvariantPredicate,
+}
+
 // Challenge lifecycle states
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub enum ChallengeStatus {
@@ -24,13 +63,30 @@ pub enum ChallengeStatus {
     Cancelled,   // Challenge cancelled by company or admin
 }
 
+// Severity of a finding, which picks the bounty tier it is paid from
+// Declared from most to least severe, so the most severe sorts first
+#[derive(CandidType, Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Critical,
+    High,
+    Medium,
+    Low,
+}
+
+// Amount a challenge pays for one finding of a severity
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SeverityTier {
+    pub severity: Severity,
+    pub amount: u64,
+}
+
 // Core challenge data structure
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct Challenge {
     pub id: u64,
     pub company: Principal,
     pub target_canister: Option<Principal>,
-    pub wasm_code: Vec<u8>,
+    pub wasm_hash: Vec<u8>, // SHA-256 of the target module in the WasmStore
     pub candid_interface: String,
     pub bounty_amount: u64,
     pub token_type: TokenType,
@@ -39,6 +95,10 @@ pub struct Challenge {
     pub status: ChallengeStatus,
     pub description: String,
     pub difficulty_level: u8, // 1-5 scale
+    pub balance_source: BalanceSource,
+    pub invariants: Vec<Invariant>,
+    pub sandboxed: bool, // Each hacker attacks their own instance of the target
+    pub severity_tiers: Vec<SeverityTier>, // Empty: the whole bounty goes to one winner
     pub created_at: i64,
     pub updated_at: i64,
 }
@@ -51,7 +111,7 @@ pub struct AttackAttempt {
     pub hacker: Principal,
     pub timestamp: i64,
     pub success: bool,
-    pub proof: Option<Vec<u8>>, // Optional proof of successful attack
+    pub proof: Option<Vec<u8>>, // Candid-encoded replay::ReplayProof
     pub gas_used: u64,
 }
 
@@ -63,6 +123,8 @@ pub enum TransactionType {
     Payout,      // Pay bounty to successful hacker
     Refund,      // Refund to company
     Fee,         // Platform fee collection
+    Deposit,     // Tokens received from a ledger
+    Withdrawal,  // Tokens sent out to a ledger account
 }
 
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -170,9 +232,20 @@ pub enum Event {
     ChallengeExpired { challenge_id: u64 },
     FundsLocked { challenge_id: u64, amount: u64 },
     BountyPaid { challenge_id: u64, recipient: Principal, amount: u64 },
+    ChallengeCancelled { challenge_id: u64 },
+    DeadlineExtended { challenge_id: u64, end_time: i64 },
+    RuleTriggered { rule_id: u64, challenge_id: u64 },
+    BountyReversed { challenge_id: u64, hacker: Principal, amount: u64 },
 }
 
 // Configuration types
+
+// Canister install arguments
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct InitArgs {
+    pub super_admin: Principal, // First SuperAdmin, who grants every other role
+}
+
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct PlatformConfig {
     pub min_bounty_amount: u64,
@@ -204,6 +277,7 @@ pub const MIN_CHALLENGE_DURATION: i64 = 24 * 60 * 60 * 1_000_000_000; // 1 day i
 pub const MAX_CHALLENGE_DURATION: i64 = 365 * 24 * 60 * 60 * 1_000_000_000; // 1 year in nanoseconds
 pub const MAX_TRANSACTION_HISTORY_LIMIT: u64 = 1000;
 pub const MAX_BALANCE_HISTORY_LIMIT: u64 = 1000;
+pub const MAX_BALANCE_METHOD_LENGTH: usize = 64;
 
 // Pagination helper
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
@@ -273,6 +347,95 @@ pub struct Balance {
     pub total: u64,
 }
 
+impl Balance {
+    /// Adds to the available or locked part and keeps the total in step
+    /// The balance is left unchanged on error
+    pub fn credit(&mut self, locked_part: bool, amount: Amount) -> Result<(), ZeroLockError> {
+        let (available, locked) = (Amount(self.available), Amount(self.locked));
+        if locked_part {
+            self.set_parts(available, locked.checked_add(amount)?)
+        } else {
+            self.set_parts(available.checked_add(amount)?, locked)
+        }
+    }
+
+    /// Takes from the available or locked part and keeps the total in step
+    /// The balance is left unchanged on error
+    pub fn debit(&mut self, locked_part: bool, amount: Amount) -> Result<(), ZeroLockError> {
+        let (available, locked) = (Amount(self.available), Amount(self.locked));
+        let part = if locked_part { locked } else { available };
+        if part < amount {
+            return Err(ZeroLockError::InsufficientFunds(format!(
+                "Insufficient {} balance",
+                if locked_part { "locked" } else { "available" }
+            )));
+        }
+
+        if locked_part {
+            self.set_parts(available, locked.checked_sub(amount)?)
+        } else {
+            self.set_parts(available.checked_sub(amount)?, locked)
+        }
+    }
+
+    fn set_parts(&mut self, available: Amount, locked: Amount) -> Result<(), ZeroLockError> {
+        self.total = available.checked_add(locked)?.get();
+        self.available = available.get();
+        self.locked = locked.get();
+        Ok(())
+    }
+}
+
+// Token amount with checked arithmetic
+// Overflow and underflow become errors instead of trapping or wrapping; callers
+// validating user input map them to InvalidInput
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Amount(pub u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    /// Adds two amounts
+    /// @returns InternalError if the sum does not fit in 64 bits
+    pub fn checked_add(self, other: Amount) -> Result<Amount, ZeroLockError> {
+        self.0.checked_add(other.0).map(Amount).ok_or_else(|| {
+            ZeroLockError::InternalError(format!("Amount overflow: {} + {}", self.0, other.0))
+        })
+    }
+
+    /// Subtracts an amount
+    /// @returns InternalError if `other` is larger
+    pub fn checked_sub(self, other: Amount) -> Result<Amount, ZeroLockError> {
+        self.0.checked_sub(other.0).map(Amount).ok_or_else(|| {
+            ZeroLockError::InternalError(format!("Amount underflow: {} - {}", self.0, other.0))
+        })
+    }
+
+    /// Scales the amount by `numerator / denominator`, rounding down
+    /// @returns InternalError for a zero denominator or a result above 64 bits
+    pub fn mul_div(self, numerator: u64, denominator: u64) -> Result<Amount, ZeroLockError> {
+        if denominator == 0 {
+            return Err(ZeroLockError::InternalError("Amount divided by zero".to_string()));
+        }
+        let scaled = self.0 as u128 * numerator as u128 / denominator as u128;
+        u64::try_from(scaled).map(Amount).map_err(|_| {
+            ZeroLockError::InternalError(format!(
+                "Amount overflow: {} * {} / {}",
+                self.0, numerator, denominator
+            ))
+        })
+    }
+
+    /// Share of the amount given in basis points (1/10000), rounding down
+    pub fn basis_points(self, basis_points: u64) -> Result<Amount, ZeroLockError> {
+        self.mul_div(basis_points, 10_000)
+    }
+}
+
 // BoundedStorable implementations for stable storage
 impl Storable for Balance {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
@@ -281,11 +444,11 @@ impl Storable for Balance {
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
     }
 }
 
@@ -293,16 +456,16 @@ impl Storable for Balance {
 
 impl Storable for Challenge {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
-        max_size: 10240,
+        max_size: 16384,
         is_fixed_size: false,
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
     }
 }
 
@@ -315,11 +478,11 @@ impl Storable for UserProfile {
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
     }
 }
 
@@ -332,11 +495,11 @@ impl Storable for Transaction {
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
     }
 }
 
@@ -344,16 +507,16 @@ impl Storable for Transaction {
 
 impl Storable for AttackAttempt {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
-        max_size: 2048,
+        max_size: 8192,
         is_fixed_size: false,
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
     }
 }
 
@@ -366,11 +529,11 @@ impl Storable for Evaluation {
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
     }
 }
 
@@ -438,13 +601,6 @@ pub fn is_valid_difficulty_level(level: u8) -> bool {
     level >= 1 && level <= 5
 }
 
-pub fn is_valid_token_type(token: &TokenType) -> bool {
-    match token {
-        TokenType::ICP => true,
-        TokenType::ICRC1(_) => true,
-    }
-}
-
 pub fn is_final_status(status: &ChallengeStatus) -> bool {
     matches!(status, ChallengeStatus::Completed | ChallengeStatus::Expired | ChallengeStatus::Cancelled)
 }
@@ -453,6 +609,7 @@ pub fn can_transition_to(from: &ChallengeStatus, to: &ChallengeStatus) -> bool {
     match (from, to) {
         (ChallengeStatus::Created, ChallengeStatus::Active) => true,
         (ChallengeStatus::Created, ChallengeStatus::Cancelled) => true,
+        (ChallengeStatus::Created, ChallengeStatus::Expired) => true,
         (ChallengeStatus::Active, ChallengeStatus::Completed) => true,
         (ChallengeStatus::Active, ChallengeStatus::Expired) => true,
         (ChallengeStatus::Active, ChallengeStatus::Cancelled) => true,
@@ -464,10 +621,19 @@ pub fn can_transition_to(from: &ChallengeStatus, to: &ChallengeStatus) -> bool {
 pub type StableHashMap<K, V> = HashMap<K, V>;
 
 // Time utilities
+#[cfg(target_arch = "wasm32")]
 pub fn current_time() -> i64 {
     ic_cdk::api::time() as i64
 }
 
+// Host builds only run the unit tests, which have no replica clock
+#[cfg(not(target_arch = "wasm32"))]
+pub fn current_time() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as i64)
+}
+
 pub fn time_from_now(duration_ns: u64) -> i64 {
     current_time() + duration_ns as i64
 }
@@ -515,6 +681,24 @@ pub fn validate_candid_interface(interface: &str) -> Result<(), ZeroLockError> {
     Ok(())
 }
 
+pub fn validate_balance_source(source: &BalanceSource) -> Result<(), ZeroLockError> {
+    match source {
+        BalanceSource::Cycles => Ok(()),
+        BalanceSource::Icrc1Balance { ledger, account } => {
+            if *ledger == Principal::anonymous() {
+                return Err(ZeroLockError::InvalidInput("Balance ledger cannot be anonymous".to_string()));
+            }
+            crate::ledger::validate_account(account)
+        }
+        BalanceSource::QueryMethod(method) => {
+            if method.is_empty() || method.len() > MAX_BALANCE_METHOD_LENGTH {
+                return Err(ZeroLockError::InvalidInput(format!("Balance method name must be 1 to {} characters", MAX_BALANCE_METHOD_LENGTH)));
+            }
+            Ok(())
+        }
+    }
+}
+
 pub fn validate_time_range(start_time: i64, end_time: i64) -> Result<(), ZeroLockError> {
     let current = current_time();
     let duration = end_time - start_time;
@@ -2,25 +2,47 @@
 //! Handles fund locking, automatic settlements, and multi-token support
 
 use crate::types::*;
+use crate::memory::{get_memory, Memory, MemoryRegion};
+use crate::migrations::{encode_record, decode_stored_record};
+use crate::ledger::{self, Account};
+use crate::rbac::{self, Role};
 use candid::{CandidType, Deserialize, Principal};
 use ic_cdk_macros::*;
-use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, Storable};
+use ic_stable_structures::{StableBTreeMap, StableCell, Storable};
 use serde::Serialize;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
+use sha2::{Digest, Sha256};
 
 
 // Memory management
-type Memory = VirtualMemory<DefaultMemoryImpl>;
 type TransactionStorage = StableBTreeMap<u64, Transaction, Memory>;
 type BalanceStorage = StableBTreeMap<StorableString, Balance, Memory>;
 type LockStorage = StableBTreeMap<u64, LockInfo, Memory>;
 type AuthorizedCanisterStorage = StableBTreeMap<u64, StorablePrincipal, Memory>;
+type VaultStateCell = StableCell<VaultState, Memory>;
+type TokenRegistryStorage = StableBTreeMap<StorableString, TokenInfo, Memory>;
+type PostingStorage = StableBTreeMap<u64, Posting, Memory>;
+type DedupStorage = StableBTreeMap<StorableString, DedupEntry, Memory>;
+type WithdrawalTrackerStorage = StableBTreeMap<StorableString, WithdrawalTracker, Memory>;
+type WithdrawalPolicyStorage = StableBTreeMap<StorableString, WithdrawalPolicy, Memory>;
+type PendingWithdrawalStorage = StableBTreeMap<u64, PendingWithdrawal, Memory>;
+type PendingDepositStorage = StableBTreeMap<u64, PendingDeposit, Memory>;
 
 // Configuration constants
 const PLATFORM_FEE_BASIS_POINTS: u64 = 250; // 2.5%
 const MAX_LOCK_DURATION: i64 = 30 * 24 * 3600 * 1_000_000_000; // 30 days in nanoseconds
-const MIN_LOCK_AMOUNT: u64 = 1_000_000; // 0.01 ICP in e8s
+const ICP_MIN_BOUNTY: u64 = 1_000_000; // 0.01 ICP in e8s
+const ICP_TRANSFER_FEE: u64 = 10_000; // 0.0001 ICP in e8s
+const DEDUP_WINDOW: u64 = 24 * 3600 * 1_000_000_000; // 24 hours in nanoseconds, as in ICRC-1
+const PERMITTED_DRIFT: u64 = 2 * 60 * 1_000_000_000; // 2 minutes of client clock skew
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 32;
+const WITHDRAWAL_DAY: i64 = 24 * 3600 * 1_000_000_000; // 1 day in nanoseconds
+const MAX_WITHDRAWAL_COOLDOWN_SECONDS: u64 = 30 * 24 * 3600; // 30 days
+const MAX_TOKEN_SYMBOL_LENGTH: usize = 16;
+const MAX_TOKEN_DECIMALS: u8 = 18;
+const WITHDRAWAL_RECOVERY_DELAY: i64 = 5 * 60 * 1_000_000_000; // 5 minutes in nanoseconds
+const DEPOSIT_RECOVERY_DELAY: i64 = 5 * 60 * 1_000_000_000; // 5 minutes in nanoseconds
 
 // Vault-specific types
 #[derive(CandidType, Deserialize, Clone, Debug)]
@@ -48,6 +70,93 @@ pub enum UnlockReason {
     AdminOverride(String),
 }
 
+// Client-supplied identity of an update call, so a retry is not applied twice
+// Works like the ICRC-1 deduplication window: a repeat of the same call with the
+// same key within the window returns the original transaction ID
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct Idempotency {
+    pub idempotency_key: Vec<u8>, // 1 to 32 bytes chosen by the client
+    pub created_at_time: u64,     // Nanoseconds since the epoch when the client first made the call
+}
+
+// Dedup index entry of a call made with an idempotency key
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DedupEntry {
+    pub request_hash: Vec<u8>,       // SHA-256 of the method, arguments and created_at_time
+    pub created_at_time: u64,
+    pub transaction_id: Option<u64>, // None while the first call is still running
+}
+
+// Ledger transfer of a withdrawal, kept until the ledger's answer is recorded
+// Sent with a fixed memo and created_at_time, so resending it cannot pay twice
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PendingWithdrawal {
+    pub transaction_id: u64,
+    pub owner: Principal,
+    pub token_type: TokenType,
+    pub to_account: Account,
+    pub amount: u64, // Debited from the owner; the ledger receives amount - fee
+    pub fee: u64,
+    pub created_at_time: u64,
+    pub needs_review: bool, // Set once the ledger's dedup window passed without an answer
+}
+
+// Ledger transfer of a deposit into the vault, kept until the ledger's answer is recorded
+// Sent with a fixed memo and created_at_time, so resending it cannot pull twice
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PendingDeposit {
+    pub transaction_id: u64,
+    pub owner: Principal,
+    pub token_type: TokenType,
+    pub amount: u64, // Credited to the owner once the ledger confirms it
+    pub fee: u64,    // ICP ledger fee paid from the deposit account; unused for ICRC-2
+    pub created_at_time: u64,
+    pub needs_review: bool, // Set once the ledger's dedup window passed without an answer
+}
+
+// Withdrawal limits of one token, set by admins
+// A token without a policy has no limits
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
+pub struct WithdrawalPolicy {
+    pub daily_limit: Option<u64>,            // Most one principal may withdraw per day
+    pub large_payout_threshold: Option<u64>, // Payouts of at least this much start a cooldown
+    pub cooldown_seconds: u64,               // How long the recipient cannot withdraw after a large payout
+}
+
+// A principal's withdrawals of one token within the current day
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
+pub struct WithdrawalTracker {
+    pub day_started_at: i64,
+    pub withdrawn_today: u64, // Pending and completed withdrawals since day_started_at
+    pub cooldown_until: i64,  // Set by large payouts; 0 if none
+}
+
+// What a principal may currently withdraw of one token
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct WithdrawalAllowance {
+    pub remaining_today: Option<u64>, // None if the token has no daily limit
+    pub cooldown_until: Option<i64>,  // Set while a large payout's cooldown runs
+}
+
+// Allow-listed token and the metadata UIs need to render amounts
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct TokenInfo {
+    pub token_type: TokenType,
+    pub symbol: String,
+    pub decimals: u8,
+    pub transfer_fee: u64, // Refreshed from the ledger on withdrawals
+    pub min_bounty: u64,
+    pub enabled: bool,
+    pub updated_at: i64,
+}
+
+// Ledger account a user funds before calling deposit
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct DepositAccount {
+    pub account: Account,
+    pub account_id: String, // Hex account identifier, as accepted by `dfx ledger transfer`
+}
+
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
 pub struct VaultStats {
     pub total_locked: u64,
@@ -61,13 +170,28 @@ pub struct VaultStats {
 pub struct LockInfo {
     pub challenge_id: u64,
     pub company: Principal,
-    pub amount: u64,
+    pub amount: u64,       // Bounty held for payout
+    pub platform_fee: u64, // Fee locked on top of the bounty
+    pub paid_out: u64,     // Bounty paid so far; a lock pays out until this reaches `amount`
+    pub fee_paid: u64,     // Platform fee collected so far
     pub token_type: TokenType,
     pub locked_at: i64,
     pub expires_at: i64,
     pub status: LockStatus,
 }
 
+impl LockInfo {
+    /// Bounty still available for payouts
+    pub fn remaining_bounty(&self) -> u64 {
+        self.amount.saturating_sub(self.paid_out)
+    }
+    
+    /// Platform fee not yet collected
+    pub fn remaining_fee(&self) -> u64 {
+        self.platform_fee.saturating_sub(self.fee_paid)
+    }
+}
+
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub enum LockStatus {
     Active,
@@ -75,6 +199,104 @@ pub enum LockStatus {
     Expired,
 }
 
+// Account in the vault's double-entry journal
+// An account's balance is its credits minus its debits. Holdings is the contra
+// account for tokens on the ledgers, so it runs negative by what the vault holds
+// and all accounts together always sum to zero
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum JournalAccount {
+    Holdings,             // Tokens the vault holds on the token ledgers
+    Available(Principal), // Spendable part of a principal's balance
+    Locked(Principal),    // Part of a principal's balance held in bounty locks
+}
+
+// Journal line moving `amount` out of the debited account into the credited one
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct Posting {
+    pub id: u64,
+    pub transaction_id: u64, // 0 for opening balances carried over from before the journal
+    pub debit: JournalAccount,
+    pub credit: JournalAccount,
+    pub amount: u64,
+    pub token_type: TokenType,
+    pub timestamp: i64,
+}
+
+// Result of reconciling one token's balances against the journal and the locks
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VaultInvariantReport {
+    pub token_type: TokenType,
+    pub holdings: u64,                       // Held on the token ledger according to the journal
+    pub total_balances: u64,                 // Available plus locked over every balance
+    pub total_locked: u64,                   // Locked over every balance
+    pub active_locks: u64,                   // Bounty plus fee over every active lock
+    pub mismatched_balances: Vec<Principal>, // Balances that differ from their postings
+    pub holds: bool,
+}
+
+// Reconciliation sums of one token, wide enough that corrupt data cannot overflow them
+struct TokenTally {
+    token_type: TokenType,
+    holdings: i128,
+    total_balances: i128,
+    total_locked: i128,
+    active_locks: i128,
+    mismatched_balances: Vec<Principal>,
+}
+
+// Posting about to be written
+struct JournalEntry {
+    debit: JournalAccount,
+    credit: JournalAccount,
+    amount: u64,
+    token_type: TokenType,
+}
+
+// Ledger changes of a lock, worked out before anything is written
+struct LockPlan {
+    entry: JournalEntry, // Moves bounty plus fee from available to locked
+    lock_info: LockInfo,
+}
+
+// Journal entry of one unlock share and the transaction it is recorded as
+struct PlannedPosting {
+    transaction_type: TransactionType,
+    from: Principal,
+    to: Principal,
+    entry: JournalEntry,
+}
+
+// Ledger changes of an unlock, worked out before anything is written
+struct UnlockPlan {
+    postings: Vec<PlannedPosting>,
+    lock_info: LockInfo, // The lock after the unlock
+    payout: u64,
+    platform_fee: u64,
+    refund: u64,
+}
+
+// Counters and configuration kept in stable memory so they survive upgrades
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct VaultState {
+    pub next_transaction_id: u64,
+    pub next_canister_id: u64,
+    pub platform_fee_recipient: Principal,
+    pub is_paused: bool,
+    pub icp_ledger_canister: Principal,
+}
+
+impl Default for VaultState {
+    fn default() -> Self {
+        Self {
+            next_transaction_id: 1,
+            next_canister_id: 1,
+            platform_fee_recipient: Principal::anonymous(),
+            is_paused: false,
+            icp_ledger_canister: Principal::from_text(ledger::MAINNET_ICP_LEDGER).unwrap(),
+        }
+    }
+}
+
 // Wrapper for Principal to implement BoundedStorable
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct StorablePrincipal(pub Principal);
@@ -108,678 +330,2072 @@ impl Storable for StorablePrincipal {
 
 
 
-// Global state - Vault uses MemoryId 10-19
+// Global state - Vault uses MemoryId 10-19 and 90-99
 thread_local! {
-    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
-        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
-    
     static TRANSACTIONS: RefCell<TransactionStorage> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10)))
+            get_memory(MemoryRegion::Vault, 10)
         )
     );
     
     static BALANCES: RefCell<BalanceStorage> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11)))
+            get_memory(MemoryRegion::Vault, 11)
         )
     );
     
     static LOCKS: RefCell<LockStorage> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12)))
+            get_memory(MemoryRegion::Vault, 12)
         )
     );
     
+    // Canisters trusted before RBAC, moved into Treasurer grants on upgrade
     static AUTHORIZED_CANISTERS: RefCell<AuthorizedCanisterStorage> = RefCell::new(
         StableBTreeMap::init(
-            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13)))
+            get_memory(MemoryRegion::Vault, 13)
+        )
+    );
+    
+    static VAULT_STATE: RefCell<VaultStateCell> = RefCell::new(
+        StableCell::init(
+            get_memory(MemoryRegion::Vault, 14),
+            VaultState::default()
+        ).expect("Failed to initialize vault state")
+    );
+
+    static TOKEN_REGISTRY: RefCell<TokenRegistryStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Vault, 15)
+        )
+    );
+
+    // Double-entry journal; balances are projections of these postings
+    static POSTINGS: RefCell<PostingStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Vault, 16)
+        )
+    );
+    
+    // Idempotency keys seen within the dedup window, keyed by caller and key
+    static DEDUP_INDEX: RefCell<DedupStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Vault, 17)
         )
     );
     
-    static NEXT_TRANSACTION_ID: RefCell<u64> = RefCell::new(1);
-    static NEXT_CANISTER_ID: RefCell<u64> = RefCell::new(1);
-    static PLATFORM_FEE_RECIPIENT: RefCell<Principal> = RefCell::new(Principal::anonymous());
-    static IS_PAUSED: RefCell<bool> = RefCell::new(false);
+    // Daily withdrawal usage and payout cooldowns, keyed like balances
+    static WITHDRAWAL_TRACKERS: RefCell<WithdrawalTrackerStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Vault, 18)
+        )
+    );
+    
+    static WITHDRAWAL_POLICIES: RefCell<WithdrawalPolicyStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::Vault, 19)
+        )
+    );
+    
+    // Withdrawals sent to a ledger whose outcome is not yet recorded
+    static PENDING_WITHDRAWALS: RefCell<PendingWithdrawalStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::VaultTransfers, 90)
+        )
+    );
+    
+    // Deposits pulled from a ledger whose outcome is not yet recorded
+    static PENDING_DEPOSITS: RefCell<PendingDepositStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::VaultTransfers, 91)
+        )
+    );
 }
 
 /// Deposits funds into the vault
+/// ICP must first be sent to the caller's deposit account (see get_deposit_account);
+/// ICRC-1 tokens must be approved for the vault with icrc2_approve (amount plus fee).
+/// The vault then moves `amount` into its main account and credits it once the ledger confirms.
+/// If the ledger's answer is lost the deposit stays Pending and the DepositRecovery job resends it;
+/// one still unanswered when the ledger's deduplication window ends waits for resolve_deposit.
 /// @param token_type Type of token being deposited
 /// @param amount Amount to deposit
+/// @param idempotency Optional dedup key; a repeat within the window returns the original transaction
 /// @returns Transaction ID on success
 #[update]
-pub async fn deposit(token_type: TokenType, amount: u64) -> ApiResponse<u64> {
+pub async fn deposit(token_type: TokenType, amount: u64, idempotency: Option<Idempotency>) -> ApiResponse<u64> {
     let caller = ic_cdk::caller();
+    let request_hash = request_hash("deposit", &(&token_type, amount), &idempotency);
+    run_idempotent(caller, idempotency, request_hash, |dedup| deposit_internal(caller, token_type, amount, dedup)).await.into()
+}
+
+/// Withdraws available funds to a ledger account
+/// The ledger fee is taken from the withdrawn amount. The withdrawal is Pending until
+/// the ledger confirms it and Failed, with the funds re-credited, if the ledger rejects it.
+/// If the ledger's answer is lost it stays Pending and the WithdrawalRecovery job resends it;
+/// one still unanswered when the ledger's deduplication window ends waits for resolve_withdrawal.
+/// The token's withdrawal policy may cap each day's withdrawals and hold them after a large payout
+/// @param token_type Type of token to withdraw
+/// @param amount Amount debited from the caller's available balance
+/// @param to_account Destination account on the token ledger
+/// @param idempotency Optional dedup key; a repeat within the window returns the original transaction
+/// @returns Transaction ID on success
+#[update]
+pub async fn withdraw(
+    token_type: TokenType,
+    amount: u64,
+    to_account: Account,
+    idempotency: Option<Idempotency>,
+) -> ApiResponse<u64> {
+    let caller = ic_cdk::caller();
+    let request_hash = request_hash("withdraw", &(&token_type, amount, &to_account), &idempotency);
+    run_idempotent(caller, idempotency, request_hash, |dedup| withdraw_internal(caller, token_type, amount, to_account, dedup)).await.into()
+}
+
+/// Gets the ledger account where the caller sends ICP before calling deposit
+/// @returns Deposit account and its hex account identifier
+#[query]
+pub fn get_deposit_account() -> ApiResponse<DepositAccount> {
+    let caller = match check_caller_not_anonymous() {
+        Ok(c) => c,
+        Err(e) => return ApiResponse::Err(e),
+    };
     
-    if caller == Principal::anonymous() {
-        return ApiResponse::Err(ZeroLockError::Unauthorized(
-            "Anonymous principals cannot deposit".to_string()
-        ));
-    }
-    
-    if amount == 0 {
-        return ApiResponse::Err(ZeroLockError::InvalidInput(
-            "Deposit amount must be greater than zero".to_string()
-        ));
-    }
+    let subaccount = ledger::principal_to_subaccount(&caller);
+    let account_id = ledger::account_identifier(&ic_cdk::id(), &subaccount);
     
-    // Check if vault is paused
-    if IS_PAUSED.with(|p| *p.borrow()) {
-        return ApiResponse::Err(ZeroLockError::InvalidState(
-            "Vault operations are currently paused".to_string()
-        ));
-    }
+    ApiResponse::Ok(DepositAccount {
+        account: Account {
+            owner: ic_cdk::id(),
+            subaccount: Some(subaccount.to_vec()),
+        },
+        account_id: ledger::account_identifier_to_hex(&account_id),
+    })
+}
+
+/// Locks funds for a challenge
+/// @param request Lock request parameters
+/// @param idempotency Optional dedup key; a repeat within the window returns the original transaction
+/// @returns ID of the Lock transaction
+#[update]
+pub async fn lock_funds(request: LockRequest, idempotency: Option<Idempotency>) -> ApiResponse<u64> {
+    let caller = match rbac::check_caller_has_role(Role::Treasurer) {
+        Ok(caller) => caller,
+        Err(e) => return ApiResponse::Err(e),
+    };
     
-    // In a real implementation, this would interact with ICP ledger or ICRC-1 tokens
-    // For now, we'll simulate the deposit
+    let request_hash = request_hash("lock_funds", &request, &idempotency);
+    run_idempotent(caller, idempotency, request_hash, |_| async { lock_funds_internal(request) }).await.into()
+}
+
+/// Unlocks and transfers funds based on challenge outcome
+/// @param request Unlock request parameters
+/// @param idempotency Optional dedup key; a repeat within the window returns the original transaction
+/// @returns ID of the first transaction posted: the Payout, or the Refund if nothing was paid
+#[update]
+pub async fn unlock_funds(request: UnlockRequest, idempotency: Option<Idempotency>) -> ApiResponse<u64> {
+    let caller = match rbac::check_caller_has_role(Role::Treasurer) {
+        Ok(caller) => caller,
+        Err(e) => return ApiResponse::Err(e),
+    };
     
-    let balance_key = make_balance_key(&caller, &token_type);
-    let current_balance = BALANCES.with(|balances| {
+    let request_hash = request_hash("unlock_funds", &request, &idempotency);
+    run_idempotent(caller, idempotency, request_hash, |_| async { unlock_funds_internal(request) }).await.into()
+}
+
+/// Gets balance for a user and token type
+/// @param user Principal of the user
+/// @param token_type Type of token
+/// @returns Balance information
+#[query]
+pub fn get_balance(user: Principal, token_type: TokenType) -> ApiResponse<Balance> {
+    let balance_key = make_balance_key(&user, &token_type);
+    let balance = BALANCES.with(|balances| {
         balances.borrow().get(&balance_key).unwrap_or(Balance {
-            owner: caller,
-            token_type: token_type.clone(),
+            owner: user,
+            token_type,
             available: 0,
             locked: 0,
             total: 0,
         })
     });
     
-    let new_balance = Balance {
-        owner: current_balance.owner,
-        token_type: current_balance.token_type,
-        available: current_balance.available + amount,
-        locked: current_balance.locked,
-        total: current_balance.total + amount,
+    ApiResponse::Ok(balance)
+}
+
+/// Gets lock information for a challenge
+/// @param challenge_id Challenge identifier
+/// @returns Lock information
+#[query]
+pub fn get_lock_info(challenge_id: u64) -> ApiResponse<LockInfo> {
+    LOCKS.with(|locks| {
+        match locks.borrow().get(&challenge_id) {
+            Some(lock_info) => ApiResponse::Ok(lock_info),
+            None => ApiResponse::Err(ZeroLockError::NotFound(
+                "No lock found for this challenge".to_string()
+            )),
+        }
+    })
+}
+
+/// Reconciles every token's balances against the journal and the active locks
+/// Holds when the balances add up to the journal's holdings, each balance matches
+/// its postings, and locked balances add up to the active locks
+/// @returns One report per token
+#[query]
+pub fn check_vault_invariants() -> ApiResponse<Vec<VaultInvariantReport>> {
+    ApiResponse::Ok(reconcile_vault())
+}
+
+/// Gets transaction history for a user
+/// @param user Principal of the user
+/// @param offset Pagination offset
+/// @param limit Maximum number of results
+/// @returns Paginated transaction history
+#[query]
+pub fn get_transaction_history(user: Principal, offset: u64, limit: u64) -> ApiResponse<PaginatedResult<Transaction>> {
+    // Validate pagination parameters
+    let validated_limit = match validate_pagination_params(offset, limit.min(MAX_TRANSACTION_HISTORY_LIMIT)) {
+        Ok(l) => l,
+        Err(e) => return ApiResponse::Err(e),
     };
     
-    BALANCES.with(|balances| {
-        balances.borrow_mut().insert(balance_key, new_balance)
-    });
+    TRANSACTIONS.with(|transactions| {
+        let mut user_transactions: Vec<Transaction> = transactions
+            .borrow()
+            .iter()
+            .filter_map(|(_, transaction)| {
+                if transaction.from == user || transaction.to == user {
+                    Some(transaction)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        
+        // Sort by timestamp (newest first)
+        user_transactions.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        
+        let total = user_transactions.len() as u64;
+        
+        // Apply pagination
+        let start = offset as usize;
+        if start >= user_transactions.len() {
+            return ApiResponse::Ok(PaginatedResult {
+                data: Vec::new(),
+                total,
+                offset,
+                limit: validated_limit,
+                has_more: false,
+            });
+        }
+        
+        let end = std::cmp::min(start + validated_limit as usize, user_transactions.len());
+        let data = user_transactions[start..end].to_vec();
+        let has_more = end < user_transactions.len();
+        
+        ApiResponse::Ok(PaginatedResult {
+            data,
+            total,
+            offset,
+            limit: validated_limit,
+            has_more,
+        })
+    })
+}
+
+/// Gets vault statistics
+/// @returns Vault statistics
+#[query]
+pub fn get_vault_stats() -> ApiResponse<VaultStats> {
+    let mut stats = VaultStats::default();
     
-    // Record transaction
-    let transaction_id = NEXT_TRANSACTION_ID.with(|id| {
-        let mut id = id.borrow_mut();
-        let current = *id;
-        *id += 1;
-        current
+    LOCKS.with(|locks| {
+        for (_, lock_info) in locks.borrow().iter() {
+            if lock_info.status == LockStatus::Active {
+                stats.total_locked = stats.total_locked.saturating_add(lock_info.remaining_bounty());
+                stats.active_locks += 1;
+            }
+        }
     });
     
-    let transaction = Transaction {
-        id: transaction_id,
-        transaction_type: TransactionType::Lock, // Using Lock as deposit type
-        challenge_id: 0, // No specific challenge for deposits
-        from: caller,
-        to: ic_cdk::id(),
-        amount,
-        token_type,
-        timestamp: current_time(),
-        status: TransactionStatus::Completed,
-    };
-    
     TRANSACTIONS.with(|transactions| {
-        transactions.borrow_mut().insert(transaction_id, transaction)
+        stats.total_transactions = transactions.borrow().len();
+        for (_, transaction) in transactions.borrow().iter() {
+            if transaction.status == TransactionStatus::Completed {
+                stats.total_volume = stats.total_volume.saturating_add(transaction.amount);
+            }
+        }
     });
     
-    ic_cdk::println!("Deposit completed: User={}, Amount={}", caller.to_text(), amount);
-    ApiResponse::Ok(transaction_id)
+    ApiResponse::Ok(stats)
 }
 
-/// Locks funds for a challenge
-/// @param request Lock request parameters
+/// Authorizes a canister to lock and unlock funds (SuperAdmin only)
+/// Kept for existing clients; grants the Treasurer role
+#[update]
+pub fn add_authorized_canister(canister: Principal) -> ApiResponse<()> {
+    rbac::grant_role_internal(ic_cdk::caller(), canister, Role::Treasurer).into()
+}
+
+/// Gets list of principals allowed to lock and unlock funds (admin function)
+#[query]
+pub fn get_authorized_canisters() -> ApiResponse<Vec<Principal>> {
+    if let Err(e) = rbac::check_caller_has_role(Role::Admin) {
+        return ApiResponse::Err(e);
+    }
+    
+    ApiResponse::Ok(rbac::role_members(Role::Treasurer))
+}
+
+/// Emergency function to pause all operations (Pauser role)
+#[update]
+pub fn set_pause_status(paused: bool) -> ApiResponse<()> {
+    if let Err(e) = rbac::check_caller_has_role(Role::Pauser) {
+        return ApiResponse::Err(e);
+    }
+    
+    update_vault_state(|state| state.is_paused = paused);
+    ic_cdk::println!("Vault pause status set to: {}", paused);
+    ApiResponse::Ok(())
+}
+
+/// Gets pause status
+#[query]
+pub fn is_paused() -> ApiResponse<bool> {
+    ApiResponse::Ok(vault_state().is_paused)
+}
+
+/// Sets platform fee recipient (Treasurer role)
+#[update]
+pub fn set_platform_fee_recipient(recipient: Principal) -> ApiResponse<()> {
+    if let Err(e) = rbac::check_caller_has_role(Role::Treasurer) {
+        return ApiResponse::Err(e);
+    }
+    
+    update_vault_state(|state| state.platform_fee_recipient = recipient);
+    ic_cdk::println!("Platform fee recipient set to: {}", recipient.to_text());
+    ApiResponse::Ok(())
+}
+
+/// Sets the ICP ledger canister used for deposits and withdrawals
+/// Point this at a locally deployed ledger for testing (SuperAdmin only)
+#[update]
+pub fn set_icp_ledger_canister(ledger_canister: Principal) -> ApiResponse<()> {
+    if let Err(e) = rbac::check_caller_has_role(Role::SuperAdmin) {
+        return ApiResponse::Err(e);
+    }
+    
+    update_vault_state(|state| state.icp_ledger_canister = ledger_canister);
+    ic_cdk::println!("ICP ledger canister set to: {}", ledger_canister.to_text());
+    ApiResponse::Ok(())
+}
+
+/// Gets the ICP ledger canister used by the vault
+#[query]
+pub fn get_icp_ledger_canister() -> ApiResponse<Principal> {
+    ApiResponse::Ok(vault_state().icp_ledger_canister)
+}
+
+/// Adds a token to the registry or replaces its metadata
+/// @param token Token metadata; the transfer fee is refreshed from the ledger later
 /// @returns Success or error
 #[update]
-pub async fn lock_funds(request: LockRequest) -> ApiResponse<()> {
+pub fn register_token(token: TokenInfo) -> ApiResponse<()> {
     let caller = ic_cdk::caller();
-    
-    // Verify caller is authorized (BountyFactory)
-    if !is_authorized_canister(&caller) {
+    if !rbac::is_admin(&caller) {
         return ApiResponse::Err(ZeroLockError::Unauthorized(
-            "Only authorized canisters can lock funds".to_string()
+            "Only admins can manage the token registry".to_string()
         ));
     }
     
-    // Check if vault is paused
-    if IS_PAUSED.with(|p| *p.borrow()) {
-        return ApiResponse::Err(ZeroLockError::InvalidState(
-            "Vault operations are currently paused".to_string()
-        ));
+    if token.symbol.is_empty() || token.symbol.len() > MAX_TOKEN_SYMBOL_LENGTH {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(format!(
+            "Token symbol must be 1 to {} characters",
+            MAX_TOKEN_SYMBOL_LENGTH
+        )));
     }
     
-    // Validate request
-    if request.amount < MIN_LOCK_AMOUNT {
-        return ApiResponse::Err(ZeroLockError::InvalidInput(
-            "Lock amount below minimum threshold".to_string()
-        ));
+    if token.decimals > MAX_TOKEN_DECIMALS {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(format!(
+            "Token decimals cannot exceed {}",
+            MAX_TOKEN_DECIMALS
+        )));
     }
     
-    if request.duration > MAX_LOCK_DURATION {
+    if token.min_bounty == 0 {
         return ApiResponse::Err(ZeroLockError::InvalidInput(
-            "Lock duration exceeds maximum allowed".to_string()
+            "Minimum bounty must be greater than zero".to_string()
         ));
     }
     
-    // Check if challenge already has locked funds
-    let existing_lock = LOCKS.with(|locks| locks.borrow().get(&request.challenge_id));
-    if let Some(lock_info) = existing_lock {
-        if lock_info.status == LockStatus::Active {
-            return ApiResponse::Err(ZeroLockError::InvalidState(
-                "Funds already locked for this challenge".to_string()
-            ));
-        }
+    let token_key = make_token_key(&token.token_type);
+    let symbol = token.symbol.clone();
+    TOKEN_REGISTRY.with(|registry| {
+        registry.borrow_mut().insert(token_key, TokenInfo {
+            updated_at: current_time(),
+            ..token
+        })
+    });
+    
+    ic_cdk::println!("Token registered: {}", symbol);
+    ApiResponse::Ok(())
+}
+
+/// Enables or disables a registered token
+/// Disabled tokens cannot be deposited or used for new bounties, but can still be withdrawn
+#[update]
+pub fn set_token_enabled(token_type: TokenType, enabled: bool) -> ApiResponse<()> {
+    let caller = ic_cdk::caller();
+    if !rbac::is_admin(&caller) {
+        return ApiResponse::Err(ZeroLockError::Unauthorized(
+            "Only admins can manage the token registry".to_string()
+        ));
     }
     
-    // Check company balance
-    let balance_key = make_balance_key(&request.company, &request.token_type);
-    let balance = BALANCES.with(|balances| balances.borrow().get(&balance_key));
-    
-    let balance = match balance {
-        Some(b) => b,
-        None => {
-            return ApiResponse::Err(ZeroLockError::InsufficientFunds(
-                "No balance found for this token type".to_string()
-            ));
+    let token_key = make_token_key(&token_type);
+    TOKEN_REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        match registry.get(&token_key) {
+            Some(token) => {
+                registry.insert(token_key, TokenInfo {
+                    enabled,
+                    updated_at: current_time(),
+                    ..token
+                });
+                ApiResponse::Ok(())
+            }
+            None => ApiResponse::Err(ZeroLockError::NotFound("Token not registered".to_string())),
         }
-    };
-    
-    if balance.available < request.amount {
-        return ApiResponse::Err(ZeroLockError::InsufficientFunds(
-            "Insufficient available balance".to_string()
+    })
+}
+
+/// Gets registry metadata for a token
+#[query]
+pub fn get_token_info(token_type: TokenType) -> ApiResponse<TokenInfo> {
+    TOKEN_REGISTRY.with(|registry| {
+        match registry.borrow().get(&make_token_key(&token_type)) {
+            Some(token) => ApiResponse::Ok(token),
+            None => ApiResponse::Err(ZeroLockError::NotFound("Token not registered".to_string())),
+        }
+    })
+}
+
+/// Lists all registered tokens, enabled or not
+#[query]
+pub fn list_tokens() -> ApiResponse<Vec<TokenInfo>> {
+    TOKEN_REGISTRY.with(|registry| {
+        ApiResponse::Ok(registry.borrow().iter().map(|(_, token)| token).collect())
+    })
+}
+
+/// Sets the withdrawal limits of a token (admin function)
+/// @param token_type Token the policy applies to
+/// @param policy Daily limit and large-payout cooldown
+/// @returns Success or error
+#[update]
+pub fn set_withdrawal_policy(token_type: TokenType, policy: WithdrawalPolicy) -> ApiResponse<()> {
+    if !rbac::is_admin(&ic_cdk::caller()) {
+        return ApiResponse::Err(ZeroLockError::Unauthorized(
+            "Only admins can set withdrawal limits".to_string()
         ));
     }
     
-    // Update balance (move from available to locked)
-    let updated_balance = Balance {
-        owner: balance.owner,
-        token_type: balance.token_type,
-        available: balance.available - request.amount,
-        locked: balance.locked + request.amount,
-        total: balance.total,
-    };
+    if policy.cooldown_seconds > MAX_WITHDRAWAL_COOLDOWN_SECONDS {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(format!(
+            "Withdrawal cooldown cannot exceed {} seconds",
+            MAX_WITHDRAWAL_COOLDOWN_SECONDS
+        )));
+    }
     
-    BALANCES.with(|balances| {
-        balances.borrow_mut().insert(balance_key, updated_balance)
+    if let Err(e) = enabled_token(&token_type) {
+        return ApiResponse::Err(e);
+    }
+    
+    WITHDRAWAL_POLICIES.with(|policies| {
+        policies.borrow_mut().insert(make_token_key(&token_type), policy)
     });
     
-    // Create lock record
-    let current_time = current_time();
-    let lock_info = LockInfo {
-        challenge_id: request.challenge_id,
-        company: request.company,
-        amount: request.amount,
-        token_type: request.token_type.clone(),
-        locked_at: current_time,
-        expires_at: current_time + request.duration,
-        status: LockStatus::Active,
-    };
+    ic_cdk::println!("Withdrawal policy set for {}", token_key_str(&token_type));
+    ApiResponse::Ok(())
+}
+
+/// Gets the withdrawal limits of a token
+/// @param token_type Token to look up
+/// @returns The token's policy; no limits if none was set
+#[query]
+pub fn get_withdrawal_policy(token_type: TokenType) -> ApiResponse<WithdrawalPolicy> {
+    ApiResponse::Ok(withdrawal_policy(&token_type))
+}
+
+/// Gets how much a user may withdraw of a token right now
+/// @param user Principal of the user
+/// @param token_type Token to check
+/// @returns Remaining daily allowance and any running payout cooldown
+#[query]
+pub fn get_withdrawal_allowance(user: Principal, token_type: TokenType) -> ApiResponse<WithdrawalAllowance> {
+    let now = current_time();
+    let tracker = withdrawal_tracker(&user, &token_type, now);
+    let remaining_today = withdrawal_policy(&token_type)
+        .daily_limit
+        .map(|limit| limit.saturating_sub(tracker.withdrawn_today));
     
-    LOCKS.with(|locks| {
-        locks.borrow_mut().insert(request.challenge_id, lock_info)
-    });
+    ApiResponse::Ok(WithdrawalAllowance {
+        remaining_today,
+        cooldown_until: (tracker.cooldown_until > now).then_some(tracker.cooldown_until),
+    })
+}
+
+/// Lists withdrawals whose ledger outcome could not be recovered (Treasurer role)
+/// The ledger's deduplication window passed before it answered, so a resend cannot
+/// tell whether the transfer was made; check the ledger and call resolve_withdrawal
+/// @returns Withdrawals waiting for review, oldest first
+#[query]
+pub fn get_unresolved_withdrawals() -> ApiResponse<Vec<PendingWithdrawal>> {
+    if let Err(e) = rbac::check_caller_has_role(Role::Treasurer) {
+        return ApiResponse::Err(e);
+    }
     
-    // Record transaction
-    let transaction_id = NEXT_TRANSACTION_ID.with(|id| {
-        let mut id = id.borrow_mut();
-        let current = *id;
-        *id += 1;
-        current
-    });
+    ApiResponse::Ok(PENDING_WITHDRAWALS.with(|pending| {
+        pending.borrow()
+            .iter()
+            .map(|(_, withdrawal)| withdrawal)
+            .filter(|withdrawal| withdrawal.needs_review)
+            .collect()
+    }))
+}
+
+/// Records the outcome of a withdrawal waiting for review (Treasurer role)
+/// @param transaction_id Withdrawal transaction
+/// @param block_index Ledger block of the transfer, or None if the ledger never made it,
+///                    which marks the withdrawal Failed and re-credits the owner
+/// @returns Success or error
+#[update]
+pub fn resolve_withdrawal(transaction_id: u64, block_index: Option<u64>) -> ApiResponse<()> {
+    if let Err(e) = rbac::check_caller_has_role(Role::Treasurer) {
+        return ApiResponse::Err(e);
+    }
     
-    let transaction = Transaction {
-        id: transaction_id,
-        transaction_type: TransactionType::Lock,
-        challenge_id: request.challenge_id,
-        from: request.company,
-        to: ic_cdk::id(),
-        amount: request.amount,
-        token_type: request.token_type,
-        timestamp: current_time,
-        status: TransactionStatus::Completed,
+    let withdrawal = match PENDING_WITHDRAWALS.with(|pending| pending.borrow().get(&transaction_id)) {
+        Some(withdrawal) if withdrawal.needs_review => withdrawal,
+        _ => return ApiResponse::Err(ZeroLockError::NotFound(
+            "No withdrawal is waiting for review under this transaction".to_string()
+        )),
     };
     
-    TRANSACTIONS.with(|transactions| {
-        transactions.borrow_mut().insert(transaction_id, transaction)
+    let result = block_index.ok_or_else(|| {
+        ZeroLockError::InvalidState("The ledger never made the transfer".to_string())
     });
+    settle_withdrawal(&withdrawal, &result);
     
-    ic_cdk::println!(
-        "Funds locked: Challenge={}, Amount={}",
-        request.challenge_id,
-        request.amount
-    );
+    ic_cdk::println!("Withdrawal {} resolved by review: {:?}", transaction_id, block_index);
     ApiResponse::Ok(())
 }
 
-/// Unlocks and transfers funds based on challenge outcome
-/// @param request Unlock request parameters
-/// @returns Success or error
-#[update]
-pub async fn unlock_funds(request: UnlockRequest) -> ApiResponse<()> {
-    let caller = ic_cdk::caller();
-    
-    // Verify caller is authorized (Judge canister)
-    if !is_authorized_canister(&caller) {
-        return ApiResponse::Err(ZeroLockError::Unauthorized(
-            "Only authorized canisters can unlock funds".to_string()
-        ));
+/// Lists deposits whose ledger outcome could not be recovered (Treasurer role)
+/// The ledger's deduplication window passed before it answered, so a resend cannot
+/// tell whether the tokens were pulled; check the ledger and call resolve_deposit
+/// @returns Deposits waiting for review, oldest first
+#[query]
+pub fn get_unresolved_deposits() -> ApiResponse<Vec<PendingDeposit>> {
+    if let Err(e) = rbac::check_caller_has_role(Role::Treasurer) {
+        return ApiResponse::Err(e);
     }
     
-    // Check if vault is paused
-    if IS_PAUSED.with(|p| *p.borrow()) {
-        return ApiResponse::Err(ZeroLockError::InvalidState(
-            "Vault operations are currently paused".to_string()
-        ));
+    ApiResponse::Ok(PENDING_DEPOSITS.with(|pending| {
+        pending.borrow()
+            .iter()
+            .map(|(_, deposit)| deposit)
+            .filter(|deposit| deposit.needs_review)
+            .collect()
+    }))
+}
+
+/// Records the outcome of a deposit waiting for review (Treasurer role)
+/// @param transaction_id Deposit transaction
+/// @param block_index Ledger block of the transfer, which credits the owner,
+///                    or None if the ledger never made it, which marks the deposit Failed
+/// @returns Success or error
+#[update]
+pub fn resolve_deposit(transaction_id: u64, block_index: Option<u64>) -> ApiResponse<()> {
+    if let Err(e) = rbac::check_caller_has_role(Role::Treasurer) {
+        return ApiResponse::Err(e);
     }
     
-    // Get lock information
-    let lock_info = LOCKS.with(|locks| locks.borrow().get(&request.challenge_id));
-    
-    let lock_info = match lock_info {
-        Some(info) => info,
-        None => {
-            return ApiResponse::Err(ZeroLockError::NotFound(
-                "No locked funds found for this challenge".to_string()
-            ));
-        }
+    let deposit = match PENDING_DEPOSITS.with(|pending| pending.borrow().get(&transaction_id)) {
+        Some(deposit) if deposit.needs_review => deposit,
+        _ => return ApiResponse::Err(ZeroLockError::NotFound(
+            "No deposit is waiting for review under this transaction".to_string()
+        )),
     };
     
-    if lock_info.status != LockStatus::Active {
-        return ApiResponse::Err(ZeroLockError::InvalidState(
-            "Lock is not active".to_string()
-        ));
-    }
+    let result = block_index.ok_or_else(|| {
+        ZeroLockError::InvalidState("The ledger never made the transfer".to_string())
+    });
+    settle_deposit(&deposit, &result);
     
-    if request.amount > lock_info.amount {
-        return ApiResponse::Err(ZeroLockError::InvalidInput(
-            "Unlock amount exceeds locked amount".to_string()
-        ));
+    ic_cdk::println!("Deposit {} resolved by review: {:?}", transaction_id, block_index);
+    ApiResponse::Ok(())
+}
+
+// Public functions for other modules
+
+/// Empties the pre-RBAC list of canisters allowed to lock and unlock funds
+pub fn take_legacy_authorized_canisters() -> Vec<Principal> {
+    AUTHORIZED_CANISTERS.with(|canisters| {
+        let mut canisters = canisters.borrow_mut();
+        let legacy: Vec<(u64, StorablePrincipal)> = canisters.iter().collect();
+        for (id, _) in &legacy {
+            canisters.remove(id);
+        }
+        legacy.into_iter().map(|(_, canister)| canister.0).collect()
+    })
+}
+
+/// Starts the journal from the balances kept before it existed
+/// Each balance becomes opening postings from Holdings; runs only while the journal is empty.
+/// The old balances are gone once this starts, so post_upgrade traps on an error to roll back
+pub fn open_journal() -> Result<(), ZeroLockError> {
+    if POSTINGS.with(|postings| !postings.borrow().is_empty()) {
+        return Ok(());
     }
     
-    // Calculate platform fee and net payout
-    let (net_amount, platform_fee) = match &request.reason {
-        UnlockReason::BountyPayout(_) => {
-            let fee = (request.amount * PLATFORM_FEE_BASIS_POINTS) / 10000;
-            (request.amount - fee, fee)
+    let balances: Vec<Balance> = BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        let existing: Vec<(StorableString, Balance)> = balances.iter().collect();
+        for (key, _) in &existing {
+            balances.remove(key);
         }
-        _ => (request.amount, 0), // No fee for refunds/cancellations
-    };
-    
-    // Update company balance (reduce locked amount)
-    let company_balance_key = make_balance_key(&lock_info.company, &lock_info.token_type);
-    let company_balance = BALANCES.with(|balances| balances.borrow().get(&company_balance_key));
+        existing.into_iter().map(|(_, balance)| balance).collect()
+    });
     
-    let company_balance = match company_balance {
-        Some(b) => b,
-        None => {
-            return ApiResponse::Err(ZeroLockError::InternalError(
-                "Company balance not found".to_string()
-            ));
+    for balance in balances {
+        let openings = [
+            (JournalAccount::Available(balance.owner), balance.available),
+            (JournalAccount::Locked(balance.owner), balance.locked),
+        ];
+        for (account, amount) in openings {
+            if amount == 0 {
+                continue;
+            }
+            let entry = JournalEntry {
+                debit: JournalAccount::Holdings,
+                credit: account,
+                amount,
+                token_type: balance.token_type.clone(),
+            };
+            apply_entry(&entry).map_err(|e| ZeroLockError::InternalError(format!(
+                "Opening balance of {} not posted: {:?}",
+                balance.owner.to_text(),
+                e
+            )))?;
+            append_posting(0, entry);
         }
-    };
+    }
+    Ok(())
+}
+
+/// Registers ICP on first install so the platform works out of the box
+pub fn seed_default_tokens() {
+    let token_key = make_token_key(&TokenType::ICP);
+    TOKEN_REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        if !registry.contains_key(&token_key) {
+            registry.insert(token_key, TokenInfo {
+                token_type: TokenType::ICP,
+                symbol: "ICP".to_string(),
+                decimals: 8,
+                transfer_fee: ICP_TRANSFER_FEE,
+                min_bounty: ICP_MIN_BOUNTY,
+                enabled: true,
+                updated_at: current_time(),
+            });
+        }
+    });
+}
+
+/// Gets a token's registry entry, failing unless it is registered and enabled
+pub fn enabled_token(token_type: &TokenType) -> Result<TokenInfo, ZeroLockError> {
+    let token = TOKEN_REGISTRY.with(|registry| registry.borrow().get(&make_token_key(token_type)))
+        .ok_or_else(|| ZeroLockError::InvalidInput(
+            "Token is not in the platform token registry".to_string()
+        ))?;
     
-    let updated_company_balance = Balance {
-        owner: company_balance.owner,
-        token_type: company_balance.token_type,
-        available: company_balance.available,
-        locked: company_balance.locked - request.amount,
-        total: company_balance.total - request.amount,
-    };
+    if !token.enabled {
+        return Err(ZeroLockError::InvalidState(format!(
+            "Token {} is currently disabled",
+            token.symbol
+        )));
+    }
     
-    BALANCES.with(|balances| {
-        balances.borrow_mut().insert(company_balance_key, updated_company_balance)
-    });
+    Ok(token)
+}
+
+/// Platform fee charged on top of a bounty
+pub fn platform_fee_for(bounty_amount: u64) -> Result<u64, ZeroLockError> {
+    Ok(Amount(bounty_amount).basis_points(PLATFORM_FEE_BASIS_POINTS)?.get())
+}
+
+/// Gets the lock for a challenge if it still holds funds
+pub fn get_active_lock(challenge_id: u64) -> Option<LockInfo> {
+    LOCKS.with(|locks| locks.borrow().get(&challenge_id))
+        .filter(|lock_info| lock_info.status == LockStatus::Active)
+}
+
+/// Lists active locks whose expiry time has passed
+pub fn expired_active_locks(now: i64) -> Vec<LockInfo> {
+    LOCKS.with(|locks| {
+        locks
+            .borrow()
+            .iter()
+            .map(|(_, lock_info)| lock_info)
+            .filter(|lock_info| lock_info.status == LockStatus::Active && lock_info.expires_at <= now)
+            .collect()
+    })
+}
+
+/// Moves the bounty plus platform fee from the company's available balance into a lock
+/// Nothing is written unless every check passes, so callers can rely on all-or-nothing
+/// @returns ID of the Lock transaction
+pub fn lock_funds_internal(request: LockRequest) -> Result<u64, ZeroLockError> {
+    // Check if vault is paused
+    if vault_state().is_paused {
+        return Err(ZeroLockError::InvalidState(
+            "Vault operations are currently paused".to_string()
+        ));
+    }
     
-    // Transfer to recipient
-    if net_amount > 0 {
-        let recipient_balance_key = make_balance_key(&request.recipient, &lock_info.token_type);
-        let recipient_balance = BALANCES.with(|balances| {
-            balances.borrow().get(&recipient_balance_key).unwrap_or(Balance {
-                owner: request.recipient,
-                token_type: lock_info.token_type.clone(),
-                available: 0,
-                locked: 0,
-                total: 0,
-            })
-        });
-        
-        let updated_recipient_balance = Balance {
-            owner: recipient_balance.owner,
-            token_type: recipient_balance.token_type,
-            available: recipient_balance.available + net_amount,
-            locked: recipient_balance.locked,
-            total: recipient_balance.total + net_amount,
-        };
-        
-        BALANCES.with(|balances| {
-            balances.borrow_mut().insert(recipient_balance_key, updated_recipient_balance)
-        });
+    // Validate request
+    let token = enabled_token(&request.token_type)?;
+    if request.amount < token.min_bounty {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "Lock amount below the {} minimum of {}",
+            token.symbol, token.min_bounty
+        )));
     }
     
-    // Handle platform fee
-    if platform_fee > 0 {
-        let platform_fee_recipient = PLATFORM_FEE_RECIPIENT.with(|p| *p.borrow());
-        let fee_balance_key = make_balance_key(&platform_fee_recipient, &lock_info.token_type);
-        let fee_balance = BALANCES.with(|balances| {
-            balances.borrow().get(&fee_balance_key).unwrap_or(Balance {
-                owner: platform_fee_recipient,
-                token_type: lock_info.token_type.clone(),
-                available: 0,
-                locked: 0,
-                total: 0,
-            })
-        });
-        
-        let updated_fee_balance = Balance {
-            owner: fee_balance.owner,
-            token_type: fee_balance.token_type,
-            available: fee_balance.available + platform_fee,
-            locked: fee_balance.locked,
-            total: fee_balance.total + platform_fee,
-        };
-        
-        BALANCES.with(|balances| {
-            balances.borrow_mut().insert(fee_balance_key, updated_fee_balance)
-        });
+    if request.duration <= 0 || request.duration > MAX_LOCK_DURATION {
+        return Err(ZeroLockError::InvalidInput(
+            "Lock duration must be positive and within the maximum allowed".to_string()
+        ));
     }
     
-    // Update lock status
-    let updated_lock = LockInfo {
-        challenge_id: lock_info.challenge_id,
-        company: lock_info.company,
-        amount: lock_info.amount,
-        token_type: lock_info.token_type.clone(),
-        locked_at: lock_info.locked_at,
-        expires_at: lock_info.expires_at,
-        status: LockStatus::Released,
-    };
+    // Check if challenge already has locked funds
+    if get_active_lock(request.challenge_id).is_some() {
+        return Err(ZeroLockError::InvalidState(
+            "Funds already locked for this challenge".to_string()
+        ));
+    }
     
-    LOCKS.with(|locks| {
-        locks.borrow_mut().insert(request.challenge_id, updated_lock)
-    });
+    let plan = plan_lock(&request, current_time())?;
     
-    // Record transactions
-    let current_time = current_time();
+    // Check company balance
+    let balance = load_balance(&request.company, &request.token_type);
+    if balance.available < plan.entry.amount {
+        return Err(ZeroLockError::InsufficientFunds(format!(
+            "Insufficient available balance: {} required (bounty {} + fee {}), {} available",
+            plan.entry.amount, request.amount, plan.lock_info.platform_fee, balance.available
+        )));
+    }
     
-    // Main payout transaction
-    if net_amount > 0 {
-        let payout_transaction_id = NEXT_TRANSACTION_ID.with(|id| {
-            let mut id = id.borrow_mut();
-            let current = *id;
-            *id += 1;
-            current
-        });
-        
-        let payout_transaction = Transaction {
-            id: payout_transaction_id,
-            transaction_type: TransactionType::Payout,
-            challenge_id: request.challenge_id,
-            from: lock_info.company,
-            to: request.recipient,
-            amount: net_amount,
-            token_type: lock_info.token_type.clone(),
-            timestamp: current_time,
-            status: TransactionStatus::Completed,
-        };
-        
-        TRANSACTIONS.with(|transactions| {
-            transactions.borrow_mut().insert(payout_transaction_id, payout_transaction)
-        });
+    let platform_fee = plan.lock_info.platform_fee;
+    let transaction_id = commit_lock(plan, ic_cdk::id())?;
+    
+    ic_cdk::println!(
+        "Funds locked: Challenge={}, Bounty={}, Fee={}",
+        request.challenge_id,
+        request.amount,
+        platform_fee
+    );
+    Ok(transaction_id)
+}
+
+/// Pays part of a lock's bounty out, or returns what is left of it to the company
+/// A payout takes the matching share of the platform fee for the fee recipient
+/// and leaves the rest locked for later payouts; the lock is Released once its
+/// bounty is exhausted. Any other reason refunds the remaining bounty and fee to
+/// the company and closes the lock: Expired for expired challenges, else Released
+/// @returns ID of the first transaction posted
+pub fn unlock_funds_internal(request: UnlockRequest) -> Result<u64, ZeroLockError> {
+    // Check if vault is paused
+    if vault_state().is_paused {
+        return Err(ZeroLockError::InvalidState(
+            "Vault operations are currently paused".to_string()
+        ));
     }
     
-    // Platform fee transaction
-    if platform_fee > 0 {
-        let fee_transaction_id = NEXT_TRANSACTION_ID.with(|id| {
-            let mut id = id.borrow_mut();
-            let current = *id;
-            *id += 1;
-            current
-        });
-        
-        let platform_fee_recipient = PLATFORM_FEE_RECIPIENT.with(|p| *p.borrow());
-        let fee_transaction = Transaction {
-            id: fee_transaction_id,
-            transaction_type: TransactionType::Fee,
-            challenge_id: request.challenge_id,
-            from: lock_info.company,
-            to: platform_fee_recipient,
-            amount: platform_fee,
-            token_type: lock_info.token_type,
-            timestamp: current_time,
-            status: TransactionStatus::Completed,
-        };
-        
-        TRANSACTIONS.with(|transactions| {
-            transactions.borrow_mut().insert(fee_transaction_id, fee_transaction)
-        });
+    // Get lock information
+    let lock_info = LOCKS.with(|locks| locks.borrow().get(&request.challenge_id))
+        .ok_or_else(|| ZeroLockError::NotFound(
+            "No locked funds found for this challenge".to_string()
+        ))?;
+    
+    if lock_info.status != LockStatus::Active {
+        return Err(ZeroLockError::InvalidState(
+            "Lock is not active".to_string()
+        ));
     }
     
+    // Split the lock between recipient, fee recipient and company
+    let plan = plan_unlock(lock_info, &request, ic_cdk::id(), vault_state().platform_fee_recipient)?;
+    let (payout, platform_fee, refund) = (plan.payout, plan.platform_fee, plan.refund);
+    let transaction_ids = commit_unlock(plan)?;
+    
     ic_cdk::println!(
-        "Funds unlocked: Challenge={}, Recipient={}, Amount={}",
+        "Funds unlocked: Challenge={}, Recipient={}, Payout={}, Fee={}, Refund={}",
         request.challenge_id,
         request.recipient.to_text(),
-        net_amount
+        payout,
+        platform_fee,
+        refund
     );
-    ApiResponse::Ok(())
+    transaction_ids.first().copied().ok_or_else(|| {
+        ZeroLockError::InternalError("Unlock moved no funds".to_string())
+    })
 }
 
-/// Gets balance for a user and token type
-/// @param user Principal of the user
-/// @param token_type Type of token
-/// @returns Balance information
-#[query]
-pub fn get_balance(user: Principal, token_type: TokenType) -> ApiResponse<Balance> {
-    let balance_key = make_balance_key(&user, &token_type);
-    let balance = BALANCES.with(|balances| {
-        balances.borrow().get(&balance_key).unwrap_or(Balance {
-            owner: user,
-            token_type,
-            available: 0,
-            locked: 0,
+/// Drops dedup index entries whose window has passed
+/// Run by the scheduler's DedupExpiry job
+pub fn prune_dedup_index() {
+    let now = current_time() as u64;
+    DEDUP_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        let expired: Vec<StorableString> = index
+            .iter()
+            .filter(|(_, entry)| dedup_window_passed(entry.created_at_time, now))
+            .map(|(key, _)| key)
+            .collect();
+        
+        for key in expired {
+            index.remove(&key);
+        }
+    });
+}
+
+/// Resends withdrawals whose ledger answer was never recorded
+/// Covers calls that trapped or lost the ledger's reply. The ledger deduplicates
+/// the resend, so it reports the original transfer or makes it for the first time.
+/// Once the ledger's deduplication window has passed that no longer works, and the
+/// withdrawal is left for a Treasurer to resolve instead of being resent forever.
+/// Run by the scheduler's WithdrawalRecovery job
+pub async fn recover_pending_withdrawals() {
+    let cutoff = current_time() - WITHDRAWAL_RECOVERY_DELAY;
+    let stale: Vec<PendingWithdrawal> = PENDING_WITHDRAWALS.with(|pending| {
+        pending.borrow()
+            .iter()
+            .map(|(_, withdrawal)| withdrawal)
+            .filter(|withdrawal| !withdrawal.needs_review && (withdrawal.created_at_time as i64) <= cutoff)
+            .collect()
+    });
+    
+    for withdrawal in stale {
+        let result = send_withdrawal(&withdrawal).await;
+        if matches!(result, Err(ZeroLockError::InvalidState(_))) {
+            flag_withdrawal_for_review(withdrawal.transaction_id);
+            continue;
+        }
+        if outcome_unknown(&result) {
+            ic_cdk::println!("Withdrawal {} still unresolved: {:?}", withdrawal.transaction_id, result);
+            continue;
+        }
+        settle_withdrawal(&withdrawal, &result);
+    }
+}
+
+/// Resends deposits whose ledger answer was never recorded
+/// Works like recover_pending_withdrawals: the ledger deduplicates the resend, and a
+/// deposit past its deduplication window is left for a Treasurer to resolve.
+/// Run by the scheduler's DepositRecovery job
+pub async fn recover_pending_deposits() {
+    let cutoff = current_time() - DEPOSIT_RECOVERY_DELAY;
+    let stale: Vec<PendingDeposit> = PENDING_DEPOSITS.with(|pending| {
+        pending.borrow()
+            .iter()
+            .map(|(_, deposit)| deposit)
+            .filter(|deposit| !deposit.needs_review && (deposit.created_at_time as i64) <= cutoff)
+            .collect()
+    });
+    
+    for deposit in stale {
+        let result = send_deposit(&deposit).await;
+        if matches!(result, Err(ZeroLockError::InvalidState(_))) {
+            flag_deposit_for_review(deposit.transaction_id);
+            continue;
+        }
+        if outcome_unknown(&result) {
+            ic_cdk::println!("Deposit {} still unresolved: {:?}", deposit.transaction_id, result);
+            continue;
+        }
+        settle_deposit(&deposit, &result);
+    }
+}
+
+/// Returns a paid-out bounty from the winner's vault balance to the company
+/// Fails if the winner has already withdrawn the funds; the platform fee is not returned
+pub fn reverse_payout_internal(
+    challenge_id: u64,
+    winner: Principal,
+    company: Principal,
+    amount: u64,
+    token_type: TokenType,
+) -> Result<(), ZeroLockError> {
+    if vault_state().is_paused {
+        return Err(ZeroLockError::InvalidState(
+            "Vault operations are currently paused".to_string()
+        ));
+    }
+    
+    post_transaction(TransactionType::Refund, challenge_id, winner, company, JournalEntry {
+        debit: JournalAccount::Available(winner),
+        credit: JournalAccount::Available(company),
+        amount,
+        token_type,
+    })?;
+    
+    ic_cdk::println!(
+        "Payout reversed: Challenge={}, From={}, To={}, Amount={}",
+        challenge_id,
+        winner.to_text(),
+        company.to_text(),
+        amount
+    );
+    Ok(())
+}
+
+// Private helper functions
+
+/// Pulls a deposit from the token ledger and credits it to the caller
+/// @param dedup Dedup index key reserved for this call, if the client sent one
+async fn deposit_internal(
+    caller: Principal,
+    token_type: TokenType,
+    amount: u64,
+    dedup: Option<StorableString>,
+) -> Result<u64, ZeroLockError> {
+    if caller == Principal::anonymous() {
+        return Err(ZeroLockError::Unauthorized(
+            "Anonymous principals cannot deposit".to_string()
+        ));
+    }
+    
+    if amount == 0 {
+        return Err(ZeroLockError::InvalidInput(
+            "Deposit amount must be greater than zero".to_string()
+        ));
+    }
+    
+    enabled_token(&token_type)?;
+    
+    let fee = match &token_type {
+        TokenType::ICP => check_icp_deposit(caller, amount).await?,
+        TokenType::ICRC1(_) => 0,
+    };
+    
+    // Checked after the balance lookup, with no await before the deposit is recorded
+    if vault_state().is_paused {
+        return Err(ZeroLockError::InvalidState(
+            "Vault operations are currently paused".to_string()
+        ));
+    }
+    
+    // Recorded before the ledger call, so a retry with the same key and the recovery
+    // job both find this deposit even if this call never sees the ledger's answer
+    let transaction_id = record_transaction(
+        TransactionType::Deposit,
+        0, // No specific challenge for deposits
+        caller,
+        ic_cdk::id(),
+        amount,
+        token_type.clone(),
+        TransactionStatus::Pending,
+    );
+    if let Some(key) = &dedup {
+        record_dedup_transaction(key, transaction_id);
+    }
+    let deposit = PendingDeposit {
+        transaction_id,
+        owner: caller,
+        token_type,
+        amount,
+        fee,
+        created_at_time: current_time() as u64,
+        needs_review: false,
+    };
+    PENDING_DEPOSITS.with(|pending| pending.borrow_mut().insert(transaction_id, deposit.clone()));
+    
+    let result = send_deposit(&deposit).await;
+    if outcome_unknown(&result) {
+        // The ledger may still have pulled the tokens; failing now could lose them
+        ic_cdk::println!("Deposit {} left Pending for recovery: {:?}", transaction_id, result);
+        return Ok(transaction_id);
+    }
+    
+    settle_deposit(&deposit, &result);
+    result.map(|_| transaction_id)
+}
+
+/// Debits the caller and sends the funds out through the token ledger
+/// @param dedup Dedup index key reserved for this call, if the client sent one
+async fn withdraw_internal(
+    caller: Principal,
+    token_type: TokenType,
+    amount: u64,
+    to_account: Account,
+    dedup: Option<StorableString>,
+) -> Result<u64, ZeroLockError> {
+    if caller == Principal::anonymous() {
+        return Err(ZeroLockError::Unauthorized(
+            "Anonymous callers not allowed".to_string()
+        ));
+    }
+    
+    ledger::validate_account(&to_account)?;
+    
+    let fee = ledger_fee(&token_type).await?;
+    if amount <= fee {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "Withdrawal amount must exceed the ledger fee of {}",
+            fee
+        )));
+    }
+    
+    // Checked after the fee lookup, with no await before the debit, so a pause or
+    // another withdrawal cannot slip in between
+    if vault_state().is_paused {
+        return Err(ZeroLockError::InvalidState(
+            "Vault operations are currently paused".to_string()
+        ));
+    }
+    let tracker = check_withdrawal_allowed(&caller, &token_type, amount)?;
+    
+    // Debit before calling the ledger so concurrent withdrawals cannot overdraw
+    let transaction_id = post_transaction_with_status(
+        TransactionType::Withdrawal,
+        0,
+        caller,
+        to_account.owner,
+        JournalEntry {
+            debit: JournalAccount::Available(caller),
+            credit: JournalAccount::Holdings,
+            amount,
+            token_type: token_type.clone(),
+        },
+        TransactionStatus::Pending,
+    )?;
+    save_withdrawal_tracker(&caller, &token_type, tracker);
+    
+    // Recorded before the ledger call, so a retry with the same key and the recovery
+    // job both find this withdrawal even if this call never sees the ledger's answer
+    if let Some(key) = &dedup {
+        record_dedup_transaction(key, transaction_id);
+    }
+    let withdrawal = PendingWithdrawal {
+        transaction_id,
+        owner: caller,
+        token_type,
+        to_account,
+        amount,
+        fee,
+        created_at_time: current_time() as u64,
+        needs_review: false,
+    };
+    PENDING_WITHDRAWALS.with(|pending| pending.borrow_mut().insert(transaction_id, withdrawal.clone()));
+    
+    let result = send_withdrawal(&withdrawal).await;
+    if outcome_unknown(&result) {
+        // The ledger may still have made the transfer; refunding now could pay twice
+        ic_cdk::println!("Withdrawal {} left Pending for recovery: {:?}", transaction_id, result);
+        return Ok(transaction_id);
+    }
+    
+    settle_withdrawal(&withdrawal, &result);
+    result.map(|_| transaction_id)
+}
+
+/// Runs an update at most once per idempotency key within the dedup window
+/// Without a key the update simply runs. A completed repeat returns the original
+/// transaction ID; a failed call frees its key so the client can retry.
+/// The update gets the reserved key so it can record its transaction before awaiting
+async fn run_idempotent<F>(
+    caller: Principal,
+    idempotency: Option<Idempotency>,
+    request_hash: Vec<u8>,
+    operation: impl FnOnce(Option<StorableString>) -> F,
+) -> Result<u64, ZeroLockError>
+where
+    F: std::future::Future<Output = Result<u64, ZeroLockError>>,
+{
+    let Some(idempotency) = idempotency else {
+        return operation(None).await;
+    };
+    
+    let key = dedup_key(&caller, &idempotency)?;
+    let now = current_time() as u64;
+    if dedup_window_passed(idempotency.created_at_time, now) {
+        return Err(ZeroLockError::InvalidInput(
+            "created_at_time is older than the deduplication window".to_string()
+        ));
+    }
+    if idempotency.created_at_time > now.saturating_add(PERMITTED_DRIFT) {
+        return Err(ZeroLockError::InvalidInput(
+            "created_at_time is in the future".to_string()
+        ));
+    }
+    
+    let existing = DEDUP_INDEX.with(|index| index.borrow().get(&key))
+        .filter(|entry| !dedup_window_passed(entry.created_at_time, now));
+    if let Some(entry) = existing {
+        if entry.request_hash != request_hash {
+            return Err(ZeroLockError::AlreadyExists(
+                "Idempotency key was already used for a different request".to_string()
+            ));
+        }
+        return entry.transaction_id.ok_or_else(|| ZeroLockError::InvalidState(
+            "A call with this idempotency key is still in progress".to_string()
+        ));
+    }
+    
+    // Reserve the key before any await so a concurrent retry cannot run the update again
+    DEDUP_INDEX.with(|index| index.borrow_mut().insert(key.clone(), DedupEntry {
+        request_hash: request_hash.clone(),
+        created_at_time: idempotency.created_at_time,
+        transaction_id: None,
+    }));
+    
+    let result = operation(Some(key.clone())).await;
+    DEDUP_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        match &result {
+            Ok(transaction_id) => index.insert(key, DedupEntry {
+                request_hash,
+                created_at_time: idempotency.created_at_time,
+                transaction_id: Some(*transaction_id),
+            }),
+            Err(_) => index.remove(&key),
+        }
+    });
+    result
+}
+
+/// Stores the transaction of a reserved dedup entry while its call is still running
+/// A retry then returns that transaction even if the call never finishes
+fn record_dedup_transaction(key: &StorableString, transaction_id: u64) {
+    DEDUP_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(entry) = index.get(key) {
+            index.insert(key.clone(), DedupEntry {
+                transaction_id: Some(transaction_id),
+                ..entry
+            });
+        }
+    });
+}
+
+/// Fingerprint of an update call, so a reused key with different arguments is caught
+fn request_hash<T: CandidType>(method: &str, args: &T, idempotency: &Option<Idempotency>) -> Vec<u8> {
+    let created_at_time = idempotency.as_ref().map(|idempotency| idempotency.created_at_time);
+    let encoded = candid::encode_args((method, args, created_at_time)).unwrap_or_default();
+    Sha256::digest(encoded).to_vec()
+}
+
+/// Creates the dedup index key of a caller's idempotency key
+fn dedup_key(caller: &Principal, idempotency: &Idempotency) -> Result<StorableString, ZeroLockError> {
+    let length = idempotency.idempotency_key.len();
+    if length == 0 || length > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(ZeroLockError::InvalidInput(format!(
+            "Idempotency key must be 1 to {} bytes",
+            MAX_IDEMPOTENCY_KEY_LENGTH
+        )));
+    }
+    
+    let key_hex: String = idempotency.idempotency_key.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(StorableString(format!("{}#{}", caller.to_text(), key_hex)))
+}
+
+/// Checks whether a call created at `created_at_time` is outside the dedup window
+fn dedup_window_passed(created_at_time: u64, now: u64) -> bool {
+    created_at_time.saturating_add(DEDUP_WINDOW + PERMITTED_DRIFT) < now
+}
+
+/// Creates a unique key for balance storage
+fn make_balance_key(principal: &Principal, token_type: &TokenType) -> StorableString {
+    StorableString(format!("{}#{}", principal.to_text(), token_key_str(token_type)))
+}
+
+/// Creates the token registry key for a token
+fn make_token_key(token_type: &TokenType) -> StorableString {
+    StorableString(token_key_str(token_type))
+}
+
+/// Text form of a token shared by balance and registry keys
+fn token_key_str(token_type: &TokenType) -> String {
+    match token_type {
+        TokenType::ICP => "ICP".to_string(),
+        TokenType::ICRC1(p) => format!("ICRC1:{}", p.to_text()),
+    }
+}
+
+/// Reads a principal's balance, zero if it has none yet
+fn load_balance(owner: &Principal, token_type: &TokenType) -> Balance {
+    BALANCES.with(|balances| balances.borrow().get(&make_balance_key(owner, token_type)))
+        .unwrap_or(Balance {
+            owner: *owner,
+            token_type: token_type.clone(),
+            available: 0,
+            locked: 0,
             total: 0,
         })
+}
+
+/// Records a completed transaction and the posting that moves its funds
+/// Nothing is written if the posting would overdraw the debited balance
+fn post_transaction(
+    transaction_type: TransactionType,
+    challenge_id: u64,
+    from: Principal,
+    to: Principal,
+    entry: JournalEntry,
+) -> Result<u64, ZeroLockError> {
+    post_transaction_with_status(transaction_type, challenge_id, from, to, entry, TransactionStatus::Completed)
+}
+
+/// Records a transaction in the given status and the posting that moves its funds
+fn post_transaction_with_status(
+    transaction_type: TransactionType,
+    challenge_id: u64,
+    from: Principal,
+    to: Principal,
+    entry: JournalEntry,
+    status: TransactionStatus,
+) -> Result<u64, ZeroLockError> {
+    apply_entry(&entry)?;
+    let transaction_id = record_transaction(
+        transaction_type,
+        challenge_id,
+        from,
+        to,
+        entry.amount,
+        entry.token_type.clone(),
+        status,
+    );
+    append_posting(transaction_id, entry);
+    Ok(transaction_id)
+}
+
+/// Reads the withdrawal policy of a token, no limits if none was set
+fn withdrawal_policy(token_type: &TokenType) -> WithdrawalPolicy {
+    WITHDRAWAL_POLICIES.with(|policies| policies.borrow().get(&make_token_key(token_type)))
+        .unwrap_or_default()
+}
+
+/// Reads a principal's withdrawal usage, starting a new day once the last one is over
+fn withdrawal_tracker(owner: &Principal, token_type: &TokenType, now: i64) -> WithdrawalTracker {
+    let tracker = WITHDRAWAL_TRACKERS.with(|trackers| trackers.borrow().get(&make_balance_key(owner, token_type)))
+        .unwrap_or_default();
+    
+    if now - tracker.day_started_at >= WITHDRAWAL_DAY {
+        WithdrawalTracker {
+            day_started_at: now,
+            withdrawn_today: 0,
+            ..tracker
+        }
+    } else {
+        tracker
+    }
+}
+
+fn save_withdrawal_tracker(owner: &Principal, token_type: &TokenType, tracker: WithdrawalTracker) {
+    WITHDRAWAL_TRACKERS.with(|trackers| {
+        trackers.borrow_mut().insert(make_balance_key(owner, token_type), tracker)
     });
+}
+
+/// Checks a withdrawal against the token's policy
+/// @returns The caller's usage including this withdrawal, to be saved once it is posted
+fn check_withdrawal_allowed(
+    owner: &Principal,
+    token_type: &TokenType,
+    amount: u64,
+) -> Result<WithdrawalTracker, ZeroLockError> {
+    let now = current_time();
+    let mut tracker = withdrawal_tracker(owner, token_type, now);
     
-    ApiResponse::Ok(balance)
+    if tracker.cooldown_until > now {
+        return Err(ZeroLockError::RateLimitExceeded(format!(
+            "Withdrawals are on hold after a large payout until {}",
+            tracker.cooldown_until
+        )));
+    }
+    
+    let withdrawn = Amount(tracker.withdrawn_today).checked_add(Amount(amount)).map_err(|_| {
+        ZeroLockError::InvalidInput("Withdrawal amount is too large".to_string())
+    })?;
+    if let Some(limit) = withdrawal_policy(token_type).daily_limit {
+        if withdrawn.get() > limit {
+            return Err(ZeroLockError::RateLimitExceeded(format!(
+                "Daily withdrawal limit of {} reached; {} remaining today",
+                limit,
+                limit.saturating_sub(tracker.withdrawn_today)
+            )));
+        }
+    }
+    
+    tracker.withdrawn_today = withdrawn.get();
+    Ok(tracker)
 }
 
-/// Gets lock information for a challenge
-/// @param challenge_id Challenge identifier
-/// @returns Lock information
-#[query]
-pub fn get_lock_info(challenge_id: u64) -> ApiResponse<LockInfo> {
+/// Starts the withdrawal cooldown of a payout recipient if the payout is large
+fn note_payout(recipient: &Principal, token_type: &TokenType, amount: u64) {
+    let policy = withdrawal_policy(token_type);
+    let Some(threshold) = policy.large_payout_threshold else { return };
+    if amount < threshold || policy.cooldown_seconds == 0 {
+        return;
+    }
+    
+    let now = current_time();
+    let mut tracker = withdrawal_tracker(recipient, token_type, now);
+    let cooldown_until = now.saturating_add(policy.cooldown_seconds as i64 * 1_000_000_000);
+    tracker.cooldown_until = tracker.cooldown_until.max(cooldown_until);
+    save_withdrawal_tracker(recipient, token_type, tracker);
+}
+
+/// Works out what locking a bounty changes: the posting that moves the bounty plus
+/// platform fee into the company's locked balance, and the new lock
+/// Reads and writes no state, so tests can drive the vault's own arithmetic
+fn plan_lock(request: &LockRequest, now: i64) -> Result<LockPlan, ZeroLockError> {
+    let platform_fee = platform_fee_for(request.amount)?;
+    let total_locked = Amount(request.amount).checked_add(Amount(platform_fee)).map_err(|_| {
+        ZeroLockError::InvalidInput("Lock amount is too large".to_string())
+    })?;
+    
+    Ok(LockPlan {
+        entry: JournalEntry {
+            debit: JournalAccount::Available(request.company),
+            credit: JournalAccount::Locked(request.company),
+            amount: total_locked.get(),
+            token_type: request.token_type.clone(),
+        },
+        lock_info: LockInfo {
+            challenge_id: request.challenge_id,
+            company: request.company,
+            amount: request.amount,
+            platform_fee,
+            paid_out: 0,
+            fee_paid: 0,
+            token_type: request.token_type.clone(),
+            locked_at: now,
+            expires_at: now.saturating_add(request.duration),
+            status: LockStatus::Active,
+        },
+    })
+}
+
+/// Posts a planned lock and stores it
+/// Nothing is written if the company cannot cover the posting
+/// @returns ID of the Lock transaction
+fn commit_lock(plan: LockPlan, vault: Principal) -> Result<u64, ZeroLockError> {
+    let LockPlan { entry, lock_info } = plan;
+    let transaction_id = post_transaction(
+        TransactionType::Lock,
+        lock_info.challenge_id,
+        lock_info.company,
+        vault,
+        entry,
+    )?;
+    
     LOCKS.with(|locks| {
-        match locks.borrow().get(&challenge_id) {
-            Some(lock_info) => ApiResponse::Ok(lock_info),
-            None => ApiResponse::Err(ZeroLockError::NotFound(
-                "No lock found for this challenge".to_string()
-            )),
-        }
+        locks.borrow_mut().insert(lock_info.challenge_id, lock_info)
+    });
+    Ok(transaction_id)
+}
+
+/// Works out what an unlock changes: one posting out of the company's locked balance
+/// per non-zero share, and the lock with its paid amounts and status updated
+/// Payouts keep the lock open until its bounty is exhausted. Reads and writes no state
+fn plan_unlock(
+    lock_info: LockInfo,
+    request: &UnlockRequest,
+    vault: Principal,
+    platform_fee_recipient: Principal,
+) -> Result<UnlockPlan, ZeroLockError> {
+    let (payout, platform_fee, refund) = unlock_shares(&lock_info, request.amount, &request.reason)?;
+    
+    let shares = [
+        (TransactionType::Payout, lock_info.company, request.recipient, payout),
+        (TransactionType::Fee, lock_info.company, platform_fee_recipient, platform_fee),
+        (TransactionType::Refund, vault, lock_info.company, refund),
+    ];
+    let postings = shares
+        .into_iter()
+        .filter(|(_, _, _, amount)| *amount > 0)
+        .map(|(transaction_type, from, to, amount)| PlannedPosting {
+            transaction_type,
+            from,
+            to,
+            entry: JournalEntry {
+                debit: JournalAccount::Locked(lock_info.company),
+                credit: JournalAccount::Available(to),
+                amount,
+                token_type: lock_info.token_type.clone(),
+            },
+        })
+        .collect();
+    
+    let paid_out = Amount(lock_info.paid_out).checked_add(Amount(payout))?.get();
+    let fee_paid = Amount(lock_info.fee_paid).checked_add(Amount(platform_fee))?.get();
+    let status = match request.reason {
+        UnlockReason::BountyPayout(_) if paid_out < lock_info.amount => LockStatus::Active,
+        UnlockReason::ChallengeExpired => LockStatus::Expired,
+        _ => LockStatus::Released,
+    };
+    
+    Ok(UnlockPlan {
+        postings,
+        lock_info: LockInfo {
+            paid_out,
+            fee_paid,
+            status,
+            ..lock_info
+        },
+        payout,
+        platform_fee,
+        refund,
     })
 }
 
-/// Gets transaction history for a user
-/// @param user Principal of the user
-/// @param offset Pagination offset
-/// @param limit Maximum number of results
-/// @returns Paginated transaction history
-#[query]
-pub fn get_transaction_history(user: Principal, offset: u64, limit: u64) -> ApiResponse<PaginatedResult<Transaction>> {
-    // Validate pagination parameters
-    let validated_limit = match validate_pagination_params(offset, limit.min(MAX_TRANSACTION_HISTORY_LIMIT)) {
-        Ok(l) => l,
-        Err(e) => return ApiResponse::Err(e),
+/// Posts a planned unlock and stores the updated lock
+/// Fails before writing anything if the company's locked balance cannot cover it
+/// @returns IDs of the transactions posted, one per share
+fn commit_unlock(plan: UnlockPlan) -> Result<Vec<u64>, ZeroLockError> {
+    let UnlockPlan { postings, lock_info, payout, platform_fee, refund } = plan;
+    
+    // Every share leaves the company's locked balance as its own transaction
+    let unlocked = Amount(payout).checked_add(Amount(platform_fee))?.checked_add(Amount(refund))?.get();
+    let company_locked = load_balance(&lock_info.company, &lock_info.token_type).locked;
+    if company_locked < unlocked {
+        return Err(ZeroLockError::InternalError(
+            "Company locked balance is smaller than the lock".to_string()
+        ));
+    }
+    
+    let mut transaction_ids = Vec::new();
+    for posting in postings {
+        if posting.transaction_type == TransactionType::Payout {
+            note_payout(&posting.to, &lock_info.token_type, posting.entry.amount);
+        }
+        transaction_ids.push(post_transaction(
+            posting.transaction_type,
+            lock_info.challenge_id,
+            posting.from,
+            posting.to,
+            posting.entry,
+        )?);
+    }
+    
+    LOCKS.with(|locks| {
+        locks.borrow_mut().insert(lock_info.challenge_id, lock_info)
+    });
+    Ok(transaction_ids)
+}
+
+/// Splits an unlock of `amount` into payout, platform fee and refund
+/// Payouts take the matching share of the fee, and the one exhausting the bounty
+/// takes whatever fee is left so no rounding dust stays locked. Every other
+/// reason refunds the remaining bounty and fee
+fn unlock_shares(
+    lock_info: &LockInfo,
+    amount: u64,
+    reason: &UnlockReason,
+) -> Result<(u64, u64, u64), ZeroLockError> {
+    let remaining_bounty = Amount(lock_info.remaining_bounty());
+    let remaining_fee = Amount(lock_info.remaining_fee());
+    
+    match reason {
+        UnlockReason::BountyPayout(_) => {
+            if amount == 0 || Amount(amount) > remaining_bounty {
+                return Err(ZeroLockError::InvalidInput(format!(
+                    "Payout must be positive and at most the {} left in the lock",
+                    remaining_bounty.get()
+                )));
+            }
+            
+            let platform_fee = if Amount(amount) == remaining_bounty {
+                remaining_fee
+            } else {
+                Amount(lock_info.platform_fee).mul_div(amount, lock_info.amount)?.min(remaining_fee)
+            };
+            Ok((amount, platform_fee.get(), 0))
+        }
+        // Refunds and cancellations return everything left to the company
+        _ => Ok((0, 0, remaining_bounty.checked_add(remaining_fee)?.get())),
+    }
+}
+
+/// Marks a withdrawal the ledger rejected as failed and posts the funds back
+fn fail_withdrawal(transaction_id: u64, owner: Principal, amount: u64, token_type: TokenType) {
+    let entry = JournalEntry {
+        debit: JournalAccount::Holdings,
+        credit: JournalAccount::Available(owner),
+        amount,
+        token_type: token_type.clone(),
     };
     
-    TRANSACTIONS.with(|transactions| {
-        let mut user_transactions: Vec<Transaction> = transactions
-            .borrow()
-            .iter()
-            .filter_map(|(_, transaction)| {
-                if transaction.from == user || transaction.to == user {
-                    Some(transaction)
-                } else {
-                    None
-                }
-            })
-            .collect();
-        
-        // Sort by timestamp (newest first)
-        user_transactions.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    match apply_entry(&entry) {
+        Ok(()) => append_posting(transaction_id, entry),
+        Err(e) => ic_cdk::println!("Failed withdrawal {} not re-credited: {:?}", transaction_id, e),
+    }
+    set_transaction_status(transaction_id, TransactionStatus::Failed);
+    
+    // A failed withdrawal does not use up the daily limit
+    let mut tracker = withdrawal_tracker(&owner, &token_type, current_time());
+    tracker.withdrawn_today = tracker.withdrawn_today.saturating_sub(amount);
+    save_withdrawal_tracker(&owner, &token_type, tracker);
+}
+
+/// Updates the balances an entry debits and credits
+/// Both balances are checked before either is written
+fn apply_entry(entry: &JournalEntry) -> Result<(), ZeroLockError> {
+    if entry.amount == 0 || entry.debit == entry.credit {
+        return Err(ZeroLockError::InvalidInput(
+            "A posting must move a positive amount between two accounts".to_string()
+        ));
+    }
+    
+    let mut updates: Vec<(StorableString, Balance)> = Vec::new();
+    for (account, debited) in [(&entry.debit, true), (&entry.credit, false)] {
+        let (owner, locked_part) = match account {
+            JournalAccount::Holdings => continue,
+            JournalAccount::Available(owner) => (*owner, false),
+            JournalAccount::Locked(owner) => (*owner, true),
+        };
         
-        let total = user_transactions.len() as u64;
+        // Locking debits and credits the same balance
+        let key = make_balance_key(&owner, &entry.token_type);
+        let mut balance = match updates.iter().position(|(existing, _)| *existing == key) {
+            Some(index) => updates.remove(index).1,
+            None => load_balance(&owner, &entry.token_type),
+        };
         
-        // Apply pagination
-        let start = offset as usize;
-        if start >= user_transactions.len() {
-            return ApiResponse::Ok(PaginatedResult {
-                data: Vec::new(),
-                total,
-                offset,
-                limit: validated_limit,
-                has_more: false,
-            });
+        if debited {
+            balance.debit(locked_part, Amount(entry.amount))?;
+        } else {
+            balance.credit(locked_part, Amount(entry.amount))?;
         }
         
-        let end = std::cmp::min(start + validated_limit as usize, user_transactions.len());
-        let data = user_transactions[start..end].to_vec();
-        let has_more = end < user_transactions.len();
-        
-        ApiResponse::Ok(PaginatedResult {
-            data,
-            total,
-            offset,
-            limit: validated_limit,
-            has_more,
-        })
-    })
+        updates.push((key, balance));
+    }
+    
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        for (key, balance) in updates {
+            balances.insert(key, balance);
+        }
+    });
+    Ok(())
 }
 
-/// Gets vault statistics
-/// @returns Vault statistics
-#[query]
-pub fn get_vault_stats() -> ApiResponse<VaultStats> {
-    let mut stats = VaultStats::default();
+/// Appends an applied entry to the journal
+fn append_posting(transaction_id: u64, entry: JournalEntry) {
+    POSTINGS.with(|postings| {
+        let mut postings = postings.borrow_mut();
+        let id = postings.last_key_value().map_or(1, |(id, _)| id + 1);
+        postings.insert(id, Posting {
+            id,
+            transaction_id,
+            debit: entry.debit,
+            credit: entry.credit,
+            amount: entry.amount,
+            token_type: entry.token_type,
+            timestamp: current_time(),
+        });
+    });
+}
+
+/// Recomputes every balance from the journal and compares it with the stored ones
+fn reconcile_vault() -> Vec<VaultInvariantReport> {
+    let mut tallies: BTreeMap<String, TokenTally> = BTreeMap::new();
+    // Net (available, locked) credits per balance key according to the journal
+    let mut derived: BTreeMap<String, (Principal, TokenType, i128, i128)> = BTreeMap::new();
+    
+    POSTINGS.with(|postings| {
+        for (_, posting) in postings.borrow().iter() {
+            for (account, sign) in [(&posting.debit, -1i128), (&posting.credit, 1i128)] {
+                let delta = sign * posting.amount as i128;
+                let owner = match account {
+                    JournalAccount::Holdings => {
+                        tally(&mut tallies, &posting.token_type).holdings -= delta;
+                        continue;
+                    }
+                    JournalAccount::Available(owner) | JournalAccount::Locked(owner) => *owner,
+                };
+                
+                let net = derived
+                    .entry(make_balance_key(&owner, &posting.token_type).0)
+                    .or_insert((owner, posting.token_type.clone(), 0, 0));
+                if matches!(account, JournalAccount::Locked(_)) {
+                    net.3 += delta;
+                } else {
+                    net.2 += delta;
+                }
+            }
+        }
+    });
+    
+    BALANCES.with(|balances| {
+        for (key, balance) in balances.borrow().iter() {
+            let expected = derived.remove(&key.0).map_or((0, 0), |(_, _, available, locked)| (available, locked));
+            let consistent = expected == (balance.available as i128, balance.locked as i128)
+                && balance.total as i128 == balance.available as i128 + balance.locked as i128;
+            
+            let token_tally = tally(&mut tallies, &balance.token_type);
+            token_tally.total_balances += balance.available as i128 + balance.locked as i128;
+            token_tally.total_locked += balance.locked as i128;
+            if !consistent {
+                token_tally.mismatched_balances.push(balance.owner);
+            }
+        }
+    });
+    
+    // Accounts with postings but no stored balance
+    for (owner, token_type, available, locked) in derived.into_values() {
+        if available != 0 || locked != 0 {
+            tally(&mut tallies, &token_type).mismatched_balances.push(owner);
+        }
+    }
     
     LOCKS.with(|locks| {
         for (_, lock_info) in locks.borrow().iter() {
             if lock_info.status == LockStatus::Active {
-                stats.total_locked += lock_info.amount;
-                stats.active_locks += 1;
+                tally(&mut tallies, &lock_info.token_type).active_locks +=
+                    lock_info.remaining_bounty() as i128 + lock_info.remaining_fee() as i128;
             }
         }
     });
     
+    tallies
+        .into_values()
+        .map(|tally| VaultInvariantReport {
+            holds: tally.mismatched_balances.is_empty()
+                && tally.total_balances == tally.holdings
+                && tally.total_locked == tally.active_locks,
+            token_type: tally.token_type,
+            holdings: clamp_to_u64(tally.holdings),
+            total_balances: clamp_to_u64(tally.total_balances),
+            total_locked: clamp_to_u64(tally.total_locked),
+            active_locks: clamp_to_u64(tally.active_locks),
+            mismatched_balances: tally.mismatched_balances,
+        })
+        .collect()
+}
+
+/// Gets the running reconciliation sums of a token, starting them if needed
+fn tally<'a>(tallies: &'a mut BTreeMap<String, TokenTally>, token_type: &TokenType) -> &'a mut TokenTally {
+    tallies.entry(token_key_str(token_type)).or_insert_with(|| TokenTally {
+        token_type: token_type.clone(),
+        holdings: 0,
+        total_balances: 0,
+        total_locked: 0,
+        active_locks: 0,
+        mismatched_balances: Vec::new(),
+    })
+}
+
+/// Narrows a reconciliation sum for reporting
+fn clamp_to_u64(value: i128) -> u64 {
+    value.clamp(0, u64::MAX as i128) as u64
+}
+
+/// Updates the status of a recorded transaction
+fn set_transaction_status(transaction_id: u64, status: TransactionStatus) {
     TRANSACTIONS.with(|transactions| {
-        stats.total_transactions = transactions.borrow().len();
-        for (_, transaction) in transactions.borrow().iter() {
-            if transaction.status == TransactionStatus::Completed {
-                stats.total_volume += transaction.amount;
+        let mut transactions = transactions.borrow_mut();
+        if let Some(transaction) = transactions.get(&transaction_id) {
+            transactions.insert(transaction_id, Transaction { status, ..transaction });
+        }
+    });
+}
+
+/// Checks that a user's deposit subaccount holds an ICP deposit and the ledger fee
+/// @returns Ledger fee, paid from the deposit subaccount on top of `amount`
+async fn check_icp_deposit(user: Principal, amount: u64) -> Result<u64, ZeroLockError> {
+    let ledger_canister = vault_state().icp_ledger_canister;
+    let subaccount = ledger::principal_to_subaccount(&user);
+    let deposit_account = ledger::account_identifier(&ic_cdk::id(), &subaccount);
+    
+    let fee = ledger::icp_transfer_fee(ledger_canister).await?;
+    let required = amount.checked_add(fee).ok_or_else(|| {
+        ZeroLockError::InvalidInput("Deposit amount is too large".to_string())
+    })?;
+    
+    let available = ledger::icp_account_balance(ledger_canister, deposit_account).await?;
+    if available < required {
+        return Err(ZeroLockError::InsufficientFunds(format!(
+            "Deposit account holds {} e8s, {} required including the ledger fee",
+            available, required
+        )));
+    }
+    
+    Ok(fee)
+}
+
+/// Pulls a deposit into the vault's main account
+/// ICP comes from the user's deposit subaccount, ICRC-2 tokens from their approved
+/// default account. The transaction ID is the memo; with the fixed created_at_time
+/// the ledger treats a resend as a duplicate and returns the block of the original transfer
+async fn send_deposit(deposit: &PendingDeposit) -> Result<u64, ZeroLockError> {
+    match &deposit.token_type {
+        TokenType::ICP => {
+            let subaccount = ledger::principal_to_subaccount(&deposit.owner);
+            let vault_account = ledger::account_identifier(&ic_cdk::id(), &[0u8; 32]);
+            ledger::icp_transfer(
+                vault_state().icp_ledger_canister,
+                Some(subaccount),
+                vault_account,
+                deposit.amount,
+                deposit.fee,
+                deposit.transaction_id,
+                Some(deposit.created_at_time),
+            ).await
+        }
+        TokenType::ICRC1(ledger_canister) => {
+            ledger::icrc2_transfer_from(
+                *ledger_canister,
+                Account { owner: deposit.owner, subaccount: None },
+                Account { owner: ic_cdk::id(), subaccount: None },
+                deposit.amount,
+                Some(deposit.transaction_id.to_be_bytes().to_vec()),
+                Some(deposit.created_at_time),
+            ).await
+        }
+    }
+}
+
+/// Looks up the current transfer fee of the token's ledger
+/// Keeps the registry's displayed fee in sync with what the ledger charges
+async fn ledger_fee(token_type: &TokenType) -> Result<u64, ZeroLockError> {
+    let fee = match token_type {
+        TokenType::ICP => ledger::icp_transfer_fee(vault_state().icp_ledger_canister).await?,
+        TokenType::ICRC1(ledger_canister) => ledger::icrc1_fee(*ledger_canister).await?,
+    };
+    
+    let token_key = make_token_key(token_type);
+    TOKEN_REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        if let Some(token) = registry.get(&token_key) {
+            if token.transfer_fee != fee {
+                registry.insert(token_key, TokenInfo { transfer_fee: fee, ..token });
             }
         }
     });
     
-    ApiResponse::Ok(stats)
+    Ok(fee)
 }
 
-/// Adds an authorized canister
-#[update]
-pub fn add_authorized_canister(canister: Principal) -> ApiResponse<()> {
-    // In production, this should be restricted to governance
+/// Sends a withdrawal from the vault's main account to its destination
+/// The transaction ID is the memo; with the fixed created_at_time the ledger treats
+/// a resend as a duplicate and returns the block of the original transfer
+async fn send_withdrawal(withdrawal: &PendingWithdrawal) -> Result<u64, ZeroLockError> {
+    let amount = withdrawal.amount - withdrawal.fee;
+    match &withdrawal.token_type {
+        TokenType::ICP => {
+            let destination = ledger::account_to_identifier(&withdrawal.to_account)?;
+            ledger::icp_transfer(
+                vault_state().icp_ledger_canister,
+                None,
+                destination,
+                amount,
+                withdrawal.fee,
+                withdrawal.transaction_id,
+                Some(withdrawal.created_at_time),
+            ).await
+        }
+        TokenType::ICRC1(ledger_canister) => {
+            ledger::icrc1_transfer(
+                *ledger_canister,
+                withdrawal.to_account.clone(),
+                amount,
+                withdrawal.fee,
+                Some(withdrawal.transaction_id.to_be_bytes().to_vec()),
+                Some(withdrawal.created_at_time),
+            ).await
+        }
+    }
+}
+
+/// Checks whether a transfer result leaves open if the ledger moved the tokens
+/// A failed call may have been executed, and a transfer past the ledger's
+/// deduplication window can no longer be matched by a resend
+fn outcome_unknown(result: &Result<u64, ZeroLockError>) -> bool {
+    matches!(result, Err(ZeroLockError::NetworkError(_)) | Err(ZeroLockError::InvalidState(_)))
+}
+
+/// Stops resending a withdrawal the ledger no longer deduplicates
+/// The funds stay debited until a Treasurer records the outcome with resolve_withdrawal
+fn flag_withdrawal_for_review(transaction_id: u64) {
+    PENDING_WITHDRAWALS.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        if let Some(withdrawal) = pending.get(&transaction_id) {
+            pending.insert(transaction_id, PendingWithdrawal { needs_review: true, ..withdrawal });
+        }
+    });
+    ic_cdk::println!("Withdrawal {} passed the ledger's dedup window; waiting for review", transaction_id);
+}
+
+/// Stops resending a deposit the ledger no longer deduplicates
+/// Nothing is credited until a Treasurer records the outcome with resolve_deposit
+fn flag_deposit_for_review(transaction_id: u64) {
+    PENDING_DEPOSITS.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        if let Some(deposit) = pending.get(&transaction_id) {
+            pending.insert(transaction_id, PendingDeposit { needs_review: true, ..deposit });
+        }
+    });
+    ic_cdk::println!("Deposit {} passed the ledger's dedup window; waiting for review", transaction_id);
+}
+
+/// Records the ledger's answer to a deposit
+/// Only the first answer counts, so the original call and the recovery job
+/// cannot both credit the same deposit
+fn settle_deposit(deposit: &PendingDeposit, result: &Result<u64, ZeroLockError>) {
+    let was_pending = PENDING_DEPOSITS.with(|pending| {
+        pending.borrow_mut().remove(&deposit.transaction_id)
+    }).is_some();
+    if !was_pending {
+        return;
+    }
     
-    if is_authorized_canister(&canister) {
-        return ApiResponse::Err(ZeroLockError::InvalidInput(
-            "Canister is already authorized".to_string()
-        ));
+    let Ok(block_index) = result else {
+        // The ledger rejected the transfer, so nothing was received
+        set_transaction_status(deposit.transaction_id, TransactionStatus::Failed);
+        return;
+    };
+    
+    // Credit only once the ledger has confirmed the transfer
+    let entry = JournalEntry {
+        debit: JournalAccount::Holdings,
+        credit: JournalAccount::Available(deposit.owner),
+        amount: deposit.amount,
+        token_type: deposit.token_type.clone(),
+    };
+    match apply_entry(&entry) {
+        Ok(()) => append_posting(deposit.transaction_id, entry),
+        Err(e) => ic_cdk::println!("Deposit {} received but not credited: {:?}", deposit.transaction_id, e),
+    }
+    set_transaction_status(deposit.transaction_id, TransactionStatus::Completed);
+    ic_cdk::println!(
+        "Deposit completed: User={}, Amount={}, Block={}",
+        deposit.owner.to_text(), deposit.amount, block_index
+    );
+}
+
+/// Records the ledger's answer to a withdrawal
+/// Only the first answer counts, so the original call and the recovery job
+/// cannot both complete or re-credit the same withdrawal
+fn settle_withdrawal(withdrawal: &PendingWithdrawal, result: &Result<u64, ZeroLockError>) {
+    let was_pending = PENDING_WITHDRAWALS.with(|pending| {
+        pending.borrow_mut().remove(&withdrawal.transaction_id)
+    }).is_some();
+    if !was_pending {
+        return;
     }
     
-    let canister_id = NEXT_CANISTER_ID.with(|id| {
-        let mut id = id.borrow_mut();
-        let current = *id;
-        *id += 1;
-        current
-    });
-    
-    AUTHORIZED_CANISTERS.with(|canisters| {
-        canisters.borrow_mut().insert(canister_id, StorablePrincipal(canister))
-    });
-    
-    ic_cdk::println!("Authorized canister added: {}", canister.to_text());
-    ApiResponse::Ok(())
+    match result {
+        Ok(block_index) => {
+            set_transaction_status(withdrawal.transaction_id, TransactionStatus::Completed);
+            ic_cdk::println!(
+                "Withdrawal completed: User={}, Amount={}, Block={}",
+                withdrawal.owner.to_text(), withdrawal.amount, block_index
+            );
+        }
+        // The ledger rejected the transfer, so give the funds back
+        Err(_) => fail_withdrawal(
+            withdrawal.transaction_id,
+            withdrawal.owner,
+            withdrawal.amount,
+            withdrawal.token_type.clone(),
+        ),
+    }
 }
 
-/// Gets list of authorized canisters
-#[query]
-pub fn get_authorized_canisters() -> ApiResponse<Vec<Principal>> {
-    let caller = match check_caller_not_anonymous() {
-        Ok(c) => c,
-        Err(e) => return ApiResponse::Err(e),
+/// Appends a completed transaction to the history
+fn record_transaction(
+    transaction_type: TransactionType,
+    challenge_id: u64,
+    from: Principal,
+    to: Principal,
+    amount: u64,
+    token_type: TokenType,
+    status: TransactionStatus,
+) -> u64 {
+    let transaction_id = next_transaction_id();
+    let transaction = Transaction {
+        id: transaction_id,
+        transaction_type,
+        challenge_id,
+        from,
+        to,
+        amount,
+        token_type,
+        timestamp: current_time(),
+        status,
     };
     
-    // In production, this should be restricted to admin only
-    AUTHORIZED_CANISTERS.with(|canisters| {
-        let canister_list: Vec<Principal> = canisters.borrow().iter().map(|(_, principal)| principal.0).collect();
-        ApiResponse::Ok(canister_list)
+    TRANSACTIONS.with(|transactions| {
+        transactions.borrow_mut().insert(transaction_id, transaction)
+    });
+    transaction_id
+}
+
+/// Reads the persisted vault state
+fn vault_state() -> VaultState {
+    VAULT_STATE.with(|cell| cell.borrow().get().clone())
+}
+
+/// Applies a change to the persisted vault state and writes it back
+fn update_vault_state<R>(f: impl FnOnce(&mut VaultState) -> R) -> R {
+    VAULT_STATE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        let mut state = cell.get().clone();
+        let result = f(&mut state);
+        cell.set(state).expect("Failed to persist vault state");
+        result
     })
 }
 
-/// Emergency function to pause all operations
-#[update]
-pub fn set_pause_status(paused: bool) -> ApiResponse<()> {
-    // In production, this should be restricted to governance
-    IS_PAUSED.with(|p| *p.borrow_mut() = paused);
-    ic_cdk::println!("Vault pause status set to: {}", paused);
-    ApiResponse::Ok(())
+/// Allocates the next transaction ID
+fn next_transaction_id() -> u64 {
+    update_vault_state(|state| {
+        let current = state.next_transaction_id;
+        state.next_transaction_id += 1;
+        current
+    })
 }
 
-/// Gets pause status
-#[query]
-pub fn is_paused() -> ApiResponse<bool> {
-    IS_PAUSED.with(|p| ApiResponse::Ok(*p.borrow()))
+
+// Storable implementations for stable storage
+use std::borrow::Cow;
+
+impl Storable for LockInfo {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 1024,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
 }
 
-/// Sets platform fee recipient
-#[update]
-pub fn set_platform_fee_recipient(recipient: Principal) -> ApiResponse<()> {
-    // In production, this should be restricted to governance
-    PLATFORM_FEE_RECIPIENT.with(|p| *p.borrow_mut() = recipient);
-    ic_cdk::println!("Platform fee recipient set to: {}", recipient.to_text());
-    ApiResponse::Ok(())
+
+
+
+
+impl Storable for Posting {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 512,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
 }
 
-// Private helper functions
+impl Storable for WithdrawalPolicy {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 128,
+        is_fixed_size: false,
+    };
 
-/// Creates a unique key for balance storage
-fn make_balance_key(principal: &Principal, token_type: &TokenType) -> StorableString {
-    let token_str = match token_type {
-        TokenType::ICP => "ICP".to_string(),
-        TokenType::ICRC1(p) => format!("ICRC1:{}", p.to_text()),
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
+
+impl Storable for WithdrawalTracker {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 128,
+        is_fixed_size: false,
     };
-    StorableString(format!("{}#{}", principal.to_text(), token_str))
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
 }
 
-/// Checks if a canister is authorized to call vault functions
-fn is_authorized_canister(canister: &Principal) -> bool {
-    AUTHORIZED_CANISTERS.with(|canisters| {
-        canisters
-            .borrow()
-            .iter()
-            .any(|(_, authorized)| authorized.0 == *canister)
-    })
+impl Storable for DedupEntry {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
 }
 
-// Storable implementations for stable storage
-use std::borrow::Cow;
+impl Storable for PendingWithdrawal {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 512,
+        is_fixed_size: false,
+    };
 
-impl Storable for LockInfo {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
+
+impl Storable for PendingDeposit {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
-        max_size: 1024,
+        max_size: 512,
         is_fixed_size: false,
     };
 
     fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+        decode_stored_record(&bytes)
+    }
+}
+
+impl Storable for VaultState {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
     }
 }
 
@@ -838,4 +2454,386 @@ impl Storable for UnlockReason {
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         candid::decode_one(&bytes).unwrap()
     }
-}
\ No newline at end of file
+}
+
+
+
+
+
+impl Storable for TokenInfo {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 512,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const USERS: usize = 3;
+    const FEE_RECIPIENT: usize = USERS;
+    const VAULT: usize = USERS + 1;
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Deposit { user: usize, amount: u64 },
+        Lock { company: usize, amount: u64 },
+        Unlock { lock: usize, recipient: usize, percent: u64, payout: bool },
+    }
+
+    fn amount_strategy() -> impl Strategy<Value = u64> {
+        prop_oneof![1..1_000_000u64, u64::MAX / 2..=u64::MAX]
+    }
+
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..USERS, amount_strategy()).prop_map(|(user, amount)| Op::Deposit { user, amount }),
+            (0..USERS, amount_strategy()).prop_map(|(company, amount)| Op::Lock { company, amount }),
+            (0..8usize, 0..USERS, 0..=100u64, any::<bool>()).prop_map(|(lock, recipient, percent, payout)| {
+                Op::Unlock { lock, recipient, percent, payout }
+            }),
+        ]
+    }
+
+    fn principal(index: usize) -> Principal {
+        Principal::from_slice(&[index as u8 + 1])
+    }
+
+    fn empty_balance(index: usize) -> Balance {
+        Balance {
+            owner: principal(index),
+            token_type: TokenType::ICP,
+            available: 0,
+            locked: 0,
+            total: 0,
+        }
+    }
+
+    /// Runs an operation through the vault's own planning and posting functions
+    /// Only the pause, token registry and caller checks of the endpoints are left out
+    fn apply(op: &Op, deposited: &mut u128) -> Result<(), ZeroLockError> {
+        match *op {
+            Op::Deposit { user, amount } => {
+                post_transaction(TransactionType::Deposit, 0, principal(user), principal(VAULT), deposit_entry(user, amount))?;
+                *deposited += amount as u128;
+            }
+            Op::Lock { company, amount } => {
+                let challenge_id = LOCKS.with(|locks| locks.borrow().len());
+                let plan = plan_lock(&LockRequest {
+                    challenge_id,
+                    company: principal(company),
+                    amount,
+                    token_type: TokenType::ICP,
+                    duration: 1,
+                }, 0)?;
+                commit_lock(plan, principal(VAULT))?;
+            }
+            Op::Unlock { lock, recipient, percent, payout } => {
+                let Some(lock_info) = pick_active_lock(lock) else { return Ok(()) };
+                let amount = Amount(lock_info.remaining_bounty()).mul_div(percent, 100)?.get();
+                let reason = if payout {
+                    UnlockReason::BountyPayout(principal(recipient))
+                } else {
+                    UnlockReason::ChallengeExpired
+                };
+                let request = UnlockRequest {
+                    challenge_id: lock_info.challenge_id,
+                    recipient: principal(recipient),
+                    amount,
+                    reason,
+                };
+
+                let plan = plan_unlock(lock_info.clone(), &request, principal(VAULT), principal(FEE_RECIPIENT))?;
+                assert_shares_add_up(&lock_info, plan.payout, plan.platform_fee, plan.refund);
+                if plan.lock_info.remaining_bounty() == 0 {
+                    assert_eq!(plan.lock_info.remaining_fee(), 0, "exhausted lock still holds fee");
+                }
+                commit_unlock(plan)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn pick_active_lock(pick: usize) -> Option<LockInfo> {
+        let active: Vec<LockInfo> = LOCKS.with(|locks| {
+            locks
+                .borrow()
+                .iter()
+                .map(|(_, lock_info)| lock_info)
+                .filter(|lock_info| lock_info.status == LockStatus::Active)
+                .collect()
+        });
+        (!active.is_empty()).then(|| active[pick % active.len()].clone())
+    }
+
+    fn balance_snapshot() -> Vec<(u64, u64, u64)> {
+        BALANCES.with(|balances| {
+            balances
+                .borrow()
+                .iter()
+                .map(|(_, balance)| (balance.available, balance.locked, balance.total))
+                .collect()
+        })
+    }
+
+    fn assert_shares_add_up(lock_info: &LockInfo, payout: u64, platform_fee: u64, refund: u64) {
+        let remaining = lock_info.remaining_bounty() as u128 + lock_info.remaining_fee() as u128;
+        let unlocked = payout as u128 + platform_fee as u128 + refund as u128;
+        if refund > 0 {
+            // Refunds close the lock and return everything left in it
+            assert_eq!(unlocked, remaining, "refund does not empty the lock");
+        } else {
+            assert!(unlocked <= remaining, "payout takes more than the lock holds");
+            assert!(platform_fee <= lock_info.remaining_fee());
+            if payout == lock_info.remaining_bounty() {
+                assert_eq!(unlocked, remaining, "last payout leaves fee dust in the lock");
+            }
+        }
+    }
+
+    /// Empties the vault's balances, journal and locks
+    fn reset_vault() {
+        BALANCES.with(|balances| balances.borrow_mut().clear_new());
+        POSTINGS.with(|postings| postings.borrow_mut().clear_new());
+        LOCKS.with(|locks| locks.borrow_mut().clear_new());
+    }
+
+    /// Stores a balance directly, as the vault did before the journal
+    fn store_balance(index: usize, available: u64, locked: u64) {
+        let balance = Balance {
+            available,
+            locked,
+            total: available.wrapping_add(locked),
+            ..empty_balance(index)
+        };
+        BALANCES.with(|balances| {
+            balances.borrow_mut().insert(make_balance_key(&principal(index), &TokenType::ICP), balance)
+        });
+    }
+
+    fn deposit_entry(index: usize, amount: u64) -> JournalEntry {
+        JournalEntry {
+            debit: JournalAccount::Holdings,
+            credit: JournalAccount::Available(principal(index)),
+            amount,
+            token_type: TokenType::ICP,
+        }
+    }
+
+    fn icp_report() -> VaultInvariantReport {
+        let mut reports = reconcile_vault();
+        assert_eq!(reports.len(), 1);
+        reports.remove(0)
+    }
+
+    #[test]
+    fn open_journal_carries_balances_over() {
+        reset_vault();
+        store_balance(0, 500, 0);
+        store_balance(1, 200, 300);
+
+        open_journal().unwrap();
+
+        let postings = POSTINGS.with(|postings| postings.borrow().len());
+        assert_eq!(postings, 3, "one posting per non-zero part of each balance");
+        assert_eq!(load_balance(&principal(1), &TokenType::ICP).locked, 300);
+
+        let report = icp_report();
+        assert_eq!(report.holdings, 1000);
+        assert_eq!(report.total_balances, 1000);
+        // The locked 300 has no lock record behind it
+        assert!(!report.holds);
+        assert!(report.mismatched_balances.is_empty());
+    }
+
+    #[test]
+    fn open_journal_runs_once() {
+        reset_vault();
+        store_balance(0, 500, 0);
+        open_journal().unwrap();
+
+        store_balance(1, 700, 0);
+        open_journal().unwrap();
+
+        assert_eq!(POSTINGS.with(|postings| postings.borrow().len()), 1);
+        assert_eq!(load_balance(&principal(1), &TokenType::ICP).available, 700);
+    }
+
+    #[test]
+    fn open_journal_fails_on_a_balance_it_cannot_post() {
+        reset_vault();
+        store_balance(0, u64::MAX, 1);
+
+        assert!(matches!(open_journal(), Err(ZeroLockError::InternalError(_))));
+    }
+
+    #[test]
+    fn apply_entry_rejects_empty_and_self_postings() {
+        reset_vault();
+        assert!(matches!(apply_entry(&deposit_entry(0, 0)), Err(ZeroLockError::InvalidInput(_))));
+
+        let self_posting = JournalEntry {
+            debit: JournalAccount::Available(principal(0)),
+            credit: JournalAccount::Available(principal(0)),
+            amount: 1,
+            token_type: TokenType::ICP,
+        };
+        assert!(matches!(apply_entry(&self_posting), Err(ZeroLockError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn apply_entry_writes_nothing_when_it_would_overdraw() {
+        reset_vault();
+        apply_entry(&deposit_entry(0, 100)).unwrap();
+
+        let transfer = JournalEntry {
+            debit: JournalAccount::Available(principal(0)),
+            credit: JournalAccount::Available(principal(1)),
+            amount: 101,
+            token_type: TokenType::ICP,
+        };
+        assert!(matches!(apply_entry(&transfer), Err(ZeroLockError::InsufficientFunds(_))));
+        assert_eq!(load_balance(&principal(0), &TokenType::ICP).available, 100);
+        assert_eq!(load_balance(&principal(1), &TokenType::ICP).available, 0);
+    }
+
+    #[test]
+    fn apply_entry_moves_funds_within_one_balance() {
+        reset_vault();
+        apply_entry(&deposit_entry(0, 100)).unwrap();
+        apply_entry(&JournalEntry {
+            debit: JournalAccount::Available(principal(0)),
+            credit: JournalAccount::Locked(principal(0)),
+            amount: 40,
+            token_type: TokenType::ICP,
+        }).unwrap();
+
+        let balance = load_balance(&principal(0), &TokenType::ICP);
+        assert_eq!((balance.available, balance.locked, balance.total), (60, 40, 100));
+    }
+
+    #[test]
+    fn reconcile_vault_holds_for_journaled_balances() {
+        reset_vault();
+        for (index, amount) in [(0, 100), (1, 250)] {
+            let entry = deposit_entry(index, amount);
+            apply_entry(&entry).unwrap();
+            append_posting(index as u64 + 1, entry);
+        }
+
+        let report = icp_report();
+        assert!(report.holds);
+        assert_eq!(report.holdings, 350);
+        assert_eq!(report.total_balances, 350);
+    }
+
+    #[test]
+    fn reconcile_vault_reports_a_tampered_balance() {
+        reset_vault();
+        let entry = deposit_entry(0, 100);
+        apply_entry(&entry).unwrap();
+        append_posting(1, entry);
+
+        // Credited without a posting
+        store_balance(0, 150, 0);
+
+        let report = icp_report();
+        assert!(!report.holds);
+        assert_eq!(report.mismatched_balances, vec![principal(0)]);
+        assert_eq!(report.holdings, 100);
+        assert_eq!(report.total_balances, 150);
+    }
+
+    #[test]
+    fn reconcile_vault_reports_locked_funds_without_a_lock() {
+        reset_vault();
+        for entry in [
+            deposit_entry(0, 100),
+            JournalEntry {
+                debit: JournalAccount::Available(principal(0)),
+                credit: JournalAccount::Locked(principal(0)),
+                amount: 100,
+                token_type: TokenType::ICP,
+            },
+        ] {
+            apply_entry(&entry).unwrap();
+            append_posting(1, entry);
+        }
+
+        let report = icp_report();
+        assert!(report.mismatched_balances.is_empty());
+        assert_eq!((report.total_locked, report.active_locks), (100, 0));
+        assert!(!report.holds);
+    }
+
+    proptest! {
+        #[test]
+        fn random_sequences_conserve_funds(ops in prop::collection::vec(op_strategy(), 1..64)) {
+            reset_vault();
+            let mut deposited = 0u128;
+            for op in &ops {
+                let before = balance_snapshot();
+                match apply(op, &mut deposited) {
+                    Ok(()) => {}
+                    Err(ZeroLockError::InsufficientFunds(_))
+                    | Err(ZeroLockError::InvalidInput(_))
+                    | Err(ZeroLockError::InternalError(_)) => {
+                        prop_assert_eq!(before, balance_snapshot());
+                    }
+                    Err(e) => prop_assert!(false, "unexpected error {:?}", e),
+                }
+
+                // Every balance and lock agrees with the journal, and the balances add up to what was deposited
+                for report in reconcile_vault() {
+                    prop_assert!(report.holds, "vault does not reconcile: {:?}", report);
+                }
+                let total: u128 = balance_snapshot().iter().map(|(_, _, total)| *total as u128).sum();
+                prop_assert_eq!(total, deposited, "funds were created or destroyed");
+            }
+        }
+
+        #[test]
+        fn balance_updates_never_wrap(start in any::<u64>(), locked in any::<u64>(), amount in any::<u64>()) {
+            let mut balance = empty_balance(0);
+            if balance.credit(false, Amount(start)).is_err() || balance.credit(true, Amount(locked)).is_err() {
+                return Ok(());
+            }
+
+            let before = balance.clone();
+            match balance.credit(false, Amount(amount)) {
+                Ok(()) => prop_assert_eq!(balance.available, start + amount),
+                Err(e) => {
+                    prop_assert!(matches!(e, ZeroLockError::InternalError(_)));
+                    prop_assert_eq!(balance.total, before.total);
+                    prop_assert_eq!(balance.available, before.available);
+                }
+            }
+
+            let mut balance = before;
+            match balance.debit(true, Amount(amount)) {
+                Ok(()) => prop_assert_eq!(balance.locked, locked - amount),
+                Err(e) => {
+                    prop_assert!(matches!(e, ZeroLockError::InsufficientFunds(_)));
+                    prop_assert_eq!(balance.locked, locked);
+                }
+            }
+        }
+
+        #[test]
+        fn platform_fee_never_overflows(amount in any::<u64>()) {
+            let fee = platform_fee_for(amount).unwrap();
+            prop_assert!(fee <= amount);
+            prop_assert_eq!(fee as u128, amount as u128 * PLATFORM_FEE_BASIS_POINTS as u128 / 10_000);
+        }
+    }
+}
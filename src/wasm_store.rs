@@ -0,0 +1,271 @@
+//! WasmStore Module - Content-addressed storage for challenge target modules
+//! WASM modules are uploaded in chunks, assembled on commit and referenced by SHA-256 hash
+
+use crate::types::*;
+use crate::memory::{get_memory, Memory, MemoryRegion};
+use crate::migrations::{encode_record, decode_stored_record};
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk_macros::*;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+use std::borrow::Cow;
+
+// Memory management
+type WasmHash = [u8; 32];
+type ChunkStorage = StableBTreeMap<WasmHash, Vec<u8>, Memory>;
+type ModuleStorage = StableBTreeMap<WasmHash, WasmModule, Memory>;
+
+// Configuration constants
+const MAX_WASM_CHUNK_SIZE: usize = 1024 * 1024; // 1MB, fits in a single ingress message
+const MAX_WASM_CHUNKS: usize = 64;
+const WASM_MAGIC: &[u8] = b"\0asm";
+
+// Manifest of a committed WASM module
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct WasmModule {
+    pub hash: Vec<u8>,
+    pub chunk_hashes: Vec<Vec<u8>>,
+    pub size: u64,
+    pub uploaded_by: Principal,
+    pub created_at: i64,
+}
+
+// Global state - WasmStore uses MemoryId 40-49
+thread_local! {
+    static WASM_CHUNKS: RefCell<ChunkStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::WasmStore, 40)
+        )
+    );
+
+    static WASM_MODULES: RefCell<ModuleStorage> = RefCell::new(
+        StableBTreeMap::init(
+            get_memory(MemoryRegion::WasmStore, 41)
+        )
+    );
+}
+
+/// Uploads one chunk of a WASM module
+/// @param chunk Chunk bytes (at most 1MB)
+/// @returns SHA-256 hash of the chunk
+#[update]
+pub fn upload_wasm_chunk(chunk: Vec<u8>) -> ApiResponse<Vec<u8>> {
+    if let Err(e) = check_caller_not_anonymous() {
+        return ApiResponse::Err(e);
+    }
+
+    if chunk.is_empty() {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(
+            "WASM chunk cannot be empty".to_string()
+        ));
+    }
+
+    if chunk.len() > MAX_WASM_CHUNK_SIZE {
+        return ApiResponse::Err(ZeroLockError::WasmSizeExceeded(format!(
+            "WASM chunk size {} exceeds maximum allowed: {} bytes",
+            chunk.len(),
+            MAX_WASM_CHUNK_SIZE
+        )));
+    }
+
+    let chunk_hash = sha256(&chunk);
+    WASM_CHUNKS.with(|chunks| {
+        let mut chunks = chunks.borrow_mut();
+        if !chunks.contains_key(&chunk_hash) {
+            chunks.insert(chunk_hash, chunk);
+        }
+    });
+
+    ApiResponse::Ok(chunk_hash.to_vec())
+}
+
+/// Assembles uploaded chunks into a WASM module
+/// @param chunk_hashes Hashes returned by upload_wasm_chunk, in module order
+/// @param expected_hash SHA-256 hash of the complete module
+/// @returns Module hash to reference from a challenge
+#[update]
+pub fn commit_wasm(chunk_hashes: Vec<Vec<u8>>, expected_hash: Vec<u8>) -> ApiResponse<Vec<u8>> {
+    let caller = match check_caller_not_anonymous() {
+        Ok(c) => c,
+        Err(e) => return ApiResponse::Err(e),
+    };
+
+    let module_hash = match to_wasm_hash(&expected_hash) {
+        Ok(hash) => hash,
+        Err(e) => return ApiResponse::Err(e),
+    };
+
+    // Committing the same module twice is a no-op
+    if WASM_MODULES.with(|modules| modules.borrow().contains_key(&module_hash)) {
+        return ApiResponse::Ok(expected_hash);
+    }
+
+    if chunk_hashes.is_empty() || chunk_hashes.len() > MAX_WASM_CHUNKS {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(format!(
+            "A module must consist of 1 to {} chunks",
+            MAX_WASM_CHUNKS
+        )));
+    }
+
+    let wasm_module = match assemble_chunks(&chunk_hashes) {
+        Ok(bytes) => bytes,
+        Err(e) => return ApiResponse::Err(e),
+    };
+
+    if let Err(e) = validate_wasm_size(&wasm_module) {
+        return ApiResponse::Err(e);
+    }
+
+    if !wasm_module.starts_with(WASM_MAGIC) {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(
+            "Assembled module is not a WASM binary".to_string()
+        ));
+    }
+
+    if sha256(&wasm_module) != module_hash {
+        return ApiResponse::Err(ZeroLockError::InvalidInput(
+            "Assembled module does not match the expected hash".to_string()
+        ));
+    }
+
+    let module = WasmModule {
+        hash: expected_hash.clone(),
+        chunk_hashes,
+        size: wasm_module.len() as u64,
+        uploaded_by: caller,
+        created_at: current_time(),
+    };
+
+    WASM_MODULES.with(|modules| {
+        modules.borrow_mut().insert(module_hash, module)
+    });
+
+    ic_cdk::println!("WASM module committed: Size={}, Uploader={}", wasm_module.len(), caller.to_text());
+    ApiResponse::Ok(expected_hash)
+}
+
+/// Gets the manifest of a committed WASM module
+/// @param hash Module hash
+/// @returns Module manifest
+#[query]
+pub fn get_wasm_module(hash: Vec<u8>) -> ApiResponse<WasmModule> {
+    let module_hash = match to_wasm_hash(&hash) {
+        Ok(hash) => hash,
+        Err(e) => return ApiResponse::Err(e),
+    };
+
+    WASM_MODULES.with(|modules| {
+        match modules.borrow().get(&module_hash) {
+            Some(module) => ApiResponse::Ok(module),
+            None => ApiResponse::Err(ZeroLockError::NotFound("WASM module not found".to_string())),
+        }
+    })
+}
+
+// Public functions for other modules
+
+/// Checks whether a module with this hash has been committed
+pub fn wasm_module_exists(hash: &[u8]) -> bool {
+    match to_wasm_hash(hash) {
+        Ok(module_hash) => WASM_MODULES.with(|modules| modules.borrow().contains_key(&module_hash)),
+        Err(_) => false,
+    }
+}
+
+/// Loads the full bytes of a committed module
+pub fn load_wasm_module(hash: &[u8]) -> Result<Vec<u8>, ZeroLockError> {
+    let module_hash = to_wasm_hash(hash)?;
+    let module = WASM_MODULES.with(|modules| modules.borrow().get(&module_hash))
+        .ok_or_else(|| ZeroLockError::NotFound("WASM module not found".to_string()))?;
+
+    assemble_chunks(&module.chunk_hashes)
+}
+
+/// Stores a complete module in chunks and commits it in one step
+/// Used when migrating challenges that still carry their WASM inline
+pub fn import_wasm_module(wasm_module: &[u8], uploaded_by: Principal) -> Result<Vec<u8>, ZeroLockError> {
+    validate_wasm_size(wasm_module)?;
+
+    let module_hash = sha256(wasm_module);
+    if WASM_MODULES.with(|modules| modules.borrow().contains_key(&module_hash)) {
+        return Ok(module_hash.to_vec());
+    }
+
+    let mut chunk_hashes = Vec::new();
+    for chunk in wasm_module.chunks(MAX_WASM_CHUNK_SIZE) {
+        let chunk_hash = sha256(chunk);
+        WASM_CHUNKS.with(|chunks| {
+            chunks.borrow_mut().insert(chunk_hash, chunk.to_vec())
+        });
+        chunk_hashes.push(chunk_hash.to_vec());
+    }
+
+    let module = WasmModule {
+        hash: module_hash.to_vec(),
+        chunk_hashes,
+        size: wasm_module.len() as u64,
+        uploaded_by,
+        created_at: current_time(),
+    };
+
+    WASM_MODULES.with(|modules| {
+        modules.borrow_mut().insert(module_hash, module)
+    });
+
+    Ok(module_hash.to_vec())
+}
+
+// Private helper functions
+
+/// Concatenates stored chunks in the given order
+fn assemble_chunks(chunk_hashes: &[Vec<u8>]) -> Result<Vec<u8>, ZeroLockError> {
+    let mut wasm_module = Vec::new();
+
+    for chunk_hash in chunk_hashes {
+        let key = to_wasm_hash(chunk_hash)?;
+        let chunk = WASM_CHUNKS.with(|chunks| chunks.borrow().get(&key))
+            .ok_or_else(|| ZeroLockError::NotFound("WASM chunk not found".to_string()))?;
+
+        if wasm_module.len() + chunk.len() > MAX_WASM_SIZE {
+            return Err(ZeroLockError::WasmSizeExceeded(format!(
+                "WASM size exceeds maximum allowed: {} bytes",
+                MAX_WASM_SIZE
+            )));
+        }
+        wasm_module.extend_from_slice(&chunk);
+    }
+
+    Ok(wasm_module)
+}
+
+/// Converts a candid blob into a fixed-size hash key
+fn to_wasm_hash(bytes: &[u8]) -> Result<WasmHash, ZeroLockError> {
+    bytes.try_into().map_err(|_| {
+        ZeroLockError::InvalidInput("WASM hash must be 32 bytes (SHA-256)".to_string())
+    })
+}
+
+/// Computes the SHA-256 hash of some bytes
+fn sha256(bytes: &[u8]) -> WasmHash {
+    Sha256::digest(bytes).into()
+}
+
+// Storable implementations for stable storage
+
+impl Storable for WasmModule {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
+        max_size: 4096,
+        is_fixed_size: false,
+    };
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_stored_record(&bytes)
+    }
+}
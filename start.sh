@@ -199,7 +199,7 @@ deploy_canisters() {
         else
             echo -e " [Change Detection] Backend code changes detected, deploying backend Canister..."
         fi
-        dfx deploy zerolock_backend
+        dfx deploy zerolock_backend --argument "(record { super_admin = principal \"$(dfx identity get-principal)\" })"
     else
         echo -e " [Skip] No backend code changes, skipping deployment"
     fi
@@ -0,0 +1,144 @@
+#!/bin/bash
+set -e
+
+echo "ZeroLock Upgrade Persistence Test"
+echo "==========================================="
+
+# Color definitions
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+BLUE='\033[0;34m'
+NC='\033[0m' # No Color
+
+CANISTER=${CANISTER:-backend}
+ICP_LEDGER=${ICP_LEDGER:-ryjl3-tyaaa-aaaaa-aaaba-cai}
+FAILURES=0
+
+# Show help information
+show_help() {
+    echo "ZeroLock Upgrade Persistence Test"
+    echo ""
+    echo "Usage: $0"
+    echo ""
+    echo "Smoke test: reinstalls the backend canister on the local network, creates"
+    echo "state, upgrades the canister and checks that ID counters and configuration"
+    echo "survived the upgrade. The upgrade test proper is tests/upgrade.rs (PocketIC)."
+    echo ""
+    echo "Deposits go through a local ICP ledger that funds the current identity,"
+    echo "e.g. the one installed by 'dfx nns install'."
+    echo ""
+    echo "Environment:"
+    echo "  CANISTER              Canister name from dfx.json (default: backend)"
+    echo "  ICP_LEDGER            Local ICP ledger canister ID (default: ryjl3-tyaaa-aaaaa-aaaba-cai)"
+}
+
+if [[ "$1" == "--help" || "$1" == "-h" ]]; then
+    show_help
+    exit 0
+fi
+
+# Check if DFX is running
+if ! dfx ping > /dev/null 2>&1; then
+    echo -e "${RED}Error: IC network not running, please run 'dfx start' first${NC}"
+    exit 1
+fi
+
+# Compare a call result against an expected fragment
+expect() {
+    local description=$1
+    local expected=$2
+    local actual=$3
+
+    if [[ "$actual" == *"$expected"* ]]; then
+        echo -e "  ${GREEN}PASS${NC} $description"
+    else
+        echo -e "  ${RED}FAIL${NC} $description"
+        echo -e "       expected: $expected"
+        echo -e "       actual:   $actual"
+        FAILURES=$((FAILURES + 1))
+    fi
+}
+
+# Minimal WASM module used as the challenge target
+WASM_BLOB='\00\61\73\6d\01\00\00\00'
+WASM_HASH=$(printf '\x00\x61\x73\x6d\x01\x00\x00\x00' | sha256sum | cut -d' ' -f1 | sed 's/../\\&/g')
+
+# The module is a single chunk, so the chunk hash equals the module hash
+upload_wasm() {
+    dfx canister call "$CANISTER" upload_wasm_chunk "(blob \"$WASM_BLOB\")" > /dev/null
+    dfx canister call "$CANISTER" commit_wasm "(vec { blob \"$WASM_HASH\" }, blob \"$WASM_HASH\")"
+}
+
+create_challenge() {
+    dfx canister call "$CANISTER" create_challenge '(record {
+        wasm_hash = blob "'"$WASM_HASH"'";
+        candid_interface = "service : {}";
+        bounty_amount = 1_000_000 : nat64;
+        duration_hours = 24 : nat64;
+        token_type = variant { ICP };
+        description = "upgrade test";
+        difficulty_level = 1 : nat8;
+        balance_source = variant { Cycles };
+    })'
+}
+
+# Send ICP to the caller's deposit account so deposit can pull it in
+fund_deposit_account() {
+    local account_id
+    account_id=$(dfx canister call "$CANISTER" get_deposit_account | grep -o 'account_id = "[0-9a-f]*"' | cut -d'"' -f2)
+    dfx ledger transfer "$account_id" --amount 0.2 --memo 0 --ledger-canister-id "$ICP_LEDGER" > /dev/null
+}
+
+deposit() {
+    dfx canister call "$CANISTER" deposit '(variant { ICP }, 5_000_000 : nat64)'
+}
+
+# Same deposit every time; the vault should only apply it once
+CREATED_AT=$(date +%s%N)
+keyed_deposit() {
+    dfx canister call "$CANISTER" deposit "(variant { ICP }, 5_000_000 : nat64, opt record { idempotency_key = blob \"upgrade-test\"; created_at_time = $CREATED_AT : nat64 })"
+}
+
+echo -e "${BLUE}Reinstalling $CANISTER...${NC}"
+INIT_ARGS="(record { super_admin = principal \"$(dfx identity get-principal)\" })"
+dfx deploy "$CANISTER" --mode reinstall --yes --argument "$INIT_ARGS" > /dev/null
+dfx canister call "$CANISTER" set_icp_ledger_canister "(principal \"$ICP_LEDGER\")" > /dev/null
+fund_deposit_account
+
+echo -e "${BLUE}Creating state before upgrade...${NC}"
+expect "target module commits" "variant { Ok = blob" "$(upload_wasm)"
+expect "first deposit gets transaction ID 1" "Ok = 1 : nat64" "$(deposit)"
+expect "first challenge gets ID 1" "Ok = 1 : nat64" "$(create_challenge)"
+expect "keyed deposit gets transaction ID 3" "Ok = 3 : nat64" "$(keyed_deposit)"
+dfx canister call "$CANISTER" set_withdrawal_policy '(variant { ICP }, record { daily_limit = opt (50_000_000 : nat64); large_payout_threshold = null; cooldown_seconds = 0 : nat64 })' > /dev/null
+dfx canister call "$CANISTER" set_platform_fee_recipient "(principal \"$(dfx identity get-principal)\")" > /dev/null
+dfx canister call "$CANISTER" set_pause_status '(true)' > /dev/null
+
+echo -e "${YELLOW}Upgrading $CANISTER...${NC}"
+dfx deploy "$CANISTER" --upgrade-unchanged --argument "$INIT_ARGS" > /dev/null
+
+echo -e "${BLUE}Checking state after upgrade...${NC}"
+expect "roles survive upgrade" "SuperAdmin" "$(dfx canister call "$CANISTER" get_my_roles)"
+expect "pause status survives upgrade" "variant { Ok = true }" "$(dfx canister call "$CANISTER" is_paused)"
+expect "judge keeps its vault reference" "vault = opt principal" "$(dfx canister call "$CANISTER" get_config)"
+expect "vault keeps its ledger" "$ICP_LEDGER" "$(dfx canister call "$CANISTER" get_icp_ledger_canister)"
+expect "challenges keep their balance source" "balance_source = variant { Cycles }" "$(dfx canister call "$CANISTER" get_challenge '(1 : nat64)')"
+expect "challenges keep sharing one target" "sandboxed = false" "$(dfx canister call "$CANISTER" get_challenge '(1 : nat64)')"
+expect "scheduled jobs survive upgrade" "job = variant { DisputeDeadlines }" "$(dfx canister call "$CANISTER" get_scheduled_jobs)"
+expect "withdrawal limits survive upgrade" "daily_limit = opt (50_000_000 : nat64)" "$(dfx canister call "$CANISTER" get_withdrawal_policy '(variant { ICP })')"
+expect "bounty escrow survives upgrade" "platform_fee = 25_000 : nat64" "$(dfx canister call "$CANISTER" get_lock_info '(1 : nat64)')"
+expect "bounty escrow has paid nothing yet" "paid_out = 0 : nat64" "$(dfx canister call "$CANISTER" get_lock_info '(1 : nat64)')"
+dfx canister call "$CANISTER" set_pause_status '(false)' > /dev/null
+expect "vault balances reconcile with the journal" "holds = true" "$(dfx canister call "$CANISTER" check_vault_invariants)"
+expect "repeated keyed deposit returns the original transaction" "Ok = 3 : nat64" "$(keyed_deposit)"
+expect "transaction IDs keep increasing" "Ok = 4 : nat64" "$(deposit)"
+expect "challenge IDs keep increasing" "Ok = 2 : nat64" "$(create_challenge)"
+
+echo -e "==========================================="
+if [[ $FAILURES -eq 0 ]]; then
+    echo -e "${GREEN}✅ Upgrade test passed${NC}"
+else
+    echo -e "${RED}❌ Upgrade test failed: $FAILURES check(s)${NC}"
+    exit 1
+fi
@@ -0,0 +1,230 @@
+//! Upgrade Test - Installs the canister in PocketIC, creates state, upgrades it and
+//! checks that ID counters keep increasing and configuration survives
+//!
+//! Needs the release build of the canister and the PocketIC server binary:
+//!   cargo build --release
+//!   POCKET_IC_BIN=/path/to/pocket-ic cargo test --target x86_64-unknown-linux-gnu --test upgrade -- --ignored
+//! ZEROLOCK_WASM overrides the path of the canister module.
+//! test-upgrade.sh remains as a smoke test of the same upgrade on a dfx replica.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+use candid::{CandidType, Deserialize, Principal};
+use pocket_ic::{query_candid_as, update_candid_as, PocketIc};
+
+// Cycles given to the canister under test
+const INITIAL_CYCLES: u128 = 2_000_000_000_000;
+
+// Candid types of the calls the test makes; records only name the fields it checks
+
+#[derive(CandidType, Deserialize, Debug)]
+enum ApiResponse<T> {
+    Ok(T),
+    Err(ZeroLockError),
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum ZeroLockError {
+    NotFound(String),
+    Unauthorized(String),
+    InvalidInput(String),
+    InternalError(String),
+    ResourceLimit(String),
+    InvalidState(String),
+    InsufficientFunds(String),
+    NetworkError(String),
+    AlreadyExists(String),
+    PaginationError(String),
+    WasmSizeExceeded(String),
+    TimeRangeError(String),
+    PermissionDenied(String),
+    RateLimitExceeded(String),
+}
+
+#[derive(CandidType, Deserialize)]
+struct InitArgs {
+    super_admin: Principal,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+enum Role {
+    SuperAdmin,
+    Admin,
+    Judge,
+    Treasurer,
+    Pauser,
+}
+
+#[derive(CandidType, Deserialize)]
+enum TokenType {
+    #[serde(rename = "ICP")]
+    Icp,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+struct WithdrawalPolicy {
+    daily_limit: Option<u64>,
+    large_payout_threshold: Option<u64>,
+    cooldown_seconds: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+enum RuleCondition {
+    ManualTrigger,
+}
+
+#[derive(CandidType, Deserialize)]
+enum RuleAction {
+    RequireManualReview,
+}
+
+#[derive(CandidType, Deserialize)]
+struct RuleRequest {
+    name: String,
+    challenge_id: Option<u64>,
+    condition: RuleCondition,
+    action: RuleAction,
+    enabled: bool,
+    priority: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct AutomatedRule {
+    id: u64,
+    name: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+enum JobKind {
+    ChallengeExpiry,
+    LockExpiry,
+    BalanceChecks,
+    DisputeDeadlines,
+    DedupExpiry,
+    WithdrawalRecovery,
+    DepositRecovery,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct JobState {
+    job: JobKind,
+    interval_seconds: u64,
+    enabled: bool,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+struct JudgeConfig {
+    bounty_factory: Option<Principal>,
+    vault: Option<Principal>,
+}
+
+#[test]
+#[ignore = "needs the PocketIC server (POCKET_IC_BIN) and `cargo build --release`"]
+fn upgrade_keeps_id_counters_and_configuration() {
+    let pic = PocketIc::new();
+    let admin = Principal::from_slice(&[1; 29]);
+    let ledger = Principal::from_text("ryjl3-tyaaa-aaaaa-aaaba-cai").unwrap();
+    let policy = WithdrawalPolicy {
+        daily_limit: Some(50_000_000),
+        large_payout_threshold: None,
+        cooldown_seconds: 0,
+    };
+
+    let canister = pic.create_canister();
+    pic.add_cycles(canister, INITIAL_CYCLES);
+    pic.install_canister(canister, canister_wasm(), encode(&InitArgs { super_admin: admin }), None);
+
+    // State before the upgrade
+    let first_rule: u64 = update(&pic, canister, admin, "create_rule", (rule("first"),));
+    let second_rule: u64 = update(&pic, canister, admin, "create_rule", (rule("second"),));
+    assert_eq!((first_rule, second_rule), (1, 2));
+    let () = update(&pic, canister, admin, "set_icp_ledger_canister", (ledger,));
+    let () = update(&pic, canister, admin, "set_withdrawal_policy", (TokenType::Icp, &policy));
+    let () = update(&pic, canister, admin, "set_job_interval", (JobKind::DisputeDeadlines, 120u64));
+    let () = update(&pic, canister, admin, "set_job_enabled", (JobKind::BalanceChecks, false));
+    let () = update(&pic, canister, admin, "set_pause_status", (true,));
+
+    pic.upgrade_canister(canister, canister_wasm(), encode(&None::<InitArgs>), None)
+        .expect("Upgrade failed");
+
+    // Configuration after the upgrade
+    let roles: Vec<Role> = query(&pic, canister, admin, "get_my_roles", ());
+    assert!(roles.contains(&Role::SuperAdmin), "roles lost: {:?}", roles);
+    let paused: bool = query(&pic, canister, admin, "is_paused", ());
+    assert!(paused, "pause status lost");
+    let stored_ledger: Principal = query(&pic, canister, admin, "get_icp_ledger_canister", ());
+    assert_eq!(stored_ledger, ledger);
+    let stored_policy: WithdrawalPolicy = query(&pic, canister, admin, "get_withdrawal_policy", (TokenType::Icp,));
+    assert_eq!(stored_policy, policy);
+    let config: JudgeConfig = query(&pic, canister, admin, "get_config", ());
+    assert_eq!(config.vault, Some(canister), "judge lost its vault reference");
+    assert_eq!(config.bounty_factory, Some(canister), "judge lost its bounty factory reference");
+
+    let jobs: Vec<JobState> = query(&pic, canister, admin, "get_scheduled_jobs", ());
+    let job = |kind: JobKind| jobs.iter().find(|state| state.job == kind).expect("job not registered");
+    assert_eq!(job(JobKind::DisputeDeadlines).interval_seconds, 120);
+    assert!(!job(JobKind::BalanceChecks).enabled, "disabled job was re-enabled");
+    assert!(job(JobKind::WithdrawalRecovery).enabled);
+
+    // IDs continue where they left off instead of overwriting earlier records
+    let third_rule: u64 = update(&pic, canister, admin, "create_rule", (rule("third"),));
+    assert_eq!(third_rule, 3);
+    let kept: AutomatedRule = query(&pic, canister, admin, "get_rule", (first_rule,));
+    assert_eq!((kept.id, kept.name.as_str()), (1, "first"), "rule 1 was overwritten");
+}
+
+/// Reads the release build of the canister
+fn canister_wasm() -> Vec<u8> {
+    let path = std::env::var("ZEROLOCK_WASM").unwrap_or_else(|_| {
+        format!("{}/target/wasm32-unknown-unknown/release/zerolock.wasm", env!("CARGO_MANIFEST_DIR"))
+    });
+    std::fs::read(&path).unwrap_or_else(|e| panic!("Cannot read {} ({}); run `cargo build --release` first", path, e))
+}
+
+/// Rule that only fires when triggered by hand
+fn rule(name: &str) -> RuleRequest {
+    RuleRequest {
+        name: name.to_string(),
+        challenge_id: None,
+        condition: RuleCondition::ManualTrigger,
+        action: RuleAction::RequireManualReview,
+        enabled: true,
+        priority: 0,
+    }
+}
+
+fn encode<T: CandidType>(value: &T) -> Vec<u8> {
+    candid::encode_one(value).expect("Failed to encode argument")
+}
+
+/// Makes an update call and unwraps its ApiResponse
+fn update<Input, T>(pic: &PocketIc, canister: Principal, sender: Principal, method: &str, input: Input) -> T
+where
+    Input: ArgumentEncoder,
+    T: CandidType + for<'de> Deserialize<'de>,
+    (ApiResponse<T>,): for<'de> ArgumentDecoder<'de>,
+{
+    let (response,): (ApiResponse<T>,) = update_candid_as(pic, canister, sender, method, input)
+        .unwrap_or_else(|e| panic!("{} failed: {:?}", method, e));
+    unwrap_response(method, response)
+}
+
+/// Makes a query call and unwraps its ApiResponse
+fn query<Input, T>(pic: &PocketIc, canister: Principal, sender: Principal, method: &str, input: Input) -> T
+where
+    Input: ArgumentEncoder,
+    T: CandidType + for<'de> Deserialize<'de>,
+    (ApiResponse<T>,): for<'de> ArgumentDecoder<'de>,
+{
+    let (response,): (ApiResponse<T>,) = query_candid_as(pic, canister, sender, method, input)
+        .unwrap_or_else(|e| panic!("{} failed: {:?}", method, e));
+    unwrap_response(method, response)
+}
+
+fn unwrap_response<T>(method: &str, response: ApiResponse<T>) -> T {
+    match response {
+        ApiResponse::Ok(value) => value,
+        ApiResponse::Err(e) => panic!("{} returned an error: {:?}", method, e),
+    }
+}